@@ -1,12 +1,173 @@
 use std::io;
-use crate::cmd::hash_object;
-use crate::cmd::update_index;
+use std::fs;
+use std::thread;
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::index;
+use crate::index::{Entry, Index, Pathspec};
+use crate::worktree;
+use crate::cmd::GIT_INDEX;
 
-pub fn add(files: Vec<&str>) -> io::Result<()> {
-    // . is not support to stage all files
-    for file in files.iter() {
-        hash_object::hash_object(file, true)?;
-        update_index::update_index(file, None, None)?;
+pub fn add(paths: Vec<&str>, all: bool, update: bool, dry_run: bool, force: bool) -> io::Result<()> {
+    // the other paths are still added when some are refused, like git
+    let refused = index::with_lock(GIT_INDEX, || add_paths(paths, all, update, dry_run, force))?;
+    if !refused.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The following paths are ignored by one of your .gitignore files:\n{}\nhint: Use -f if you really want to add them.", refused.join("\n"))));
     }
     Ok(())
 }
+
+fn add_paths(paths: Vec<&str>, all: bool, update: bool, dry_run: bool, force: bool) -> io::Result<Vec<String>> {
+    // -A and -u without paths cover the whole tree
+    let paths = match paths.is_empty() {
+        true if all || update => vec!["."],
+        true => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Nothing specified, nothing added.\nhint: Maybe you wanted to say 'git add .'?")),
+        false => paths,
+    };
+    let pathspec = Pathspec::new(&paths);
+    let index = index::read_index(GIT_INDEX)?;
+    let tracked = index.entries.iter().map(|e| e.name.clone()).collect::<BTreeSet<String>>();
+    let visible = index::walk_worktree(false)?;
+    let on_disk = index::walk_worktree(true)?;
+    let ignored = on_disk.iter()
+                    .filter(|f| visible.binary_search(f).is_err() && !tracked.contains(*f))
+                    .cloned()
+                    .collect::<Vec<String>>();
+
+    // every path must name something, and ignored files only count with -f
+    let mut refused = Vec::new();
+    for path in paths.iter() {
+        let spec = Pathspec::new(&[path]);
+        let known = tracked.iter().chain(visible.iter()).any(|f| spec.matches(f));
+        match ignored.iter().any(|f| spec.matches(f)) {
+            _ if known => {},
+            true if !force => refused.push(String::from(*path)),
+            true => {},
+            false => return Err(io::Error::new(io::ErrorKind::NotFound, format!("pathspec '{}' did not match any files", path))),
+        }
+    }
+
+    // -u stays with what the index already tracks
+    let untracked = visible.iter()
+                    .chain(ignored.iter().filter(|_| force))
+                    .filter(|_| !update);
+    let candidates = tracked.iter()
+                    .chain(untracked)
+                    .filter(|name| pathspec.matches(name))
+                    .cloned()
+                    .collect::<BTreeSet<String>>();
+
+    // only files whose stat data cannot vouch for them get hashed
+    let mut index = index;
+    let mut removed: BTreeSet<&str> = BTreeSet::new();
+    let mut stale: Vec<(&str, Option<&Entry>)> = Vec::new();
+    for name in candidates.iter() {
+        if on_disk.binary_search(name).is_err() {
+            removed.insert(name);
+            continue;
+        }
+        let entry = index.entries.binary_search_by(|e| e.name.as_str().cmp(name))
+                        .ok()
+                        .map(|i| &index.entries[i])
+                        .filter(|e| e.stage == 0);
+        match (entry, fs::symlink_metadata(name)) {
+            (Some(entry), Ok(metadata)) if index.is_unchanged(entry, &metadata) => {},
+            _ => stale.push((name, entry)),
+        }
+    }
+    let staged = stage_blobs(&stale, !dry_run)?;
+
+    let mut changes = removed.iter().map(|name| (*name, "remove"))
+                    .chain(staged.iter().filter(|(_, changed)| *changed).map(|(e, _)| (e.name.as_str(), "add")))
+                    .collect::<Vec<(&str, &str)>>();
+    // like git, what the index tracked comes before the new files
+    changes.sort_by_key(|(name, _)| (!tracked.contains(*name), *name));
+    if dry_run {
+        for (name, action) in changes.iter() {
+            println!("{} '{}'", action, name);
+        }
+    } else if !staged.is_empty() || !changes.is_empty() {
+        // one pass over the index for every path added, removed or refreshed
+        let fresh = staged.iter().map(|(e, _)| e.name.as_str()).collect::<BTreeSet<&str>>();
+        let mut entries = index.entries.into_iter()
+                        .filter(|e| !removed.contains(e.name.as_str()) && !fresh.contains(e.name.as_str()))
+                        .collect::<Vec<Entry>>();
+        entries.extend(staged.iter().map(|(e, _)| e.clone()));
+        entries.sort_by(|a, b| (&a.name, a.stage).cmp(&(&b.name, b.stage)));
+        index = Index { entries, ..index };
+        index.invalidate_trees(changes.iter().map(|(name, _)| name));
+        index::write_index(GIT_INDEX, &index)?;
+    }
+    Ok(refused)
+}
+
+fn stage_blobs(files: &[(&str, Option<&Entry>)], write: bool) -> io::Result<Vec<(Entry, bool)>> {
+    // hash the files, and store them too when write is set, on a thread per core;
+    // each comes back in order as a fresh entry and whether it differs from the one it replaces
+    let next = AtomicUsize::new(0);
+    let workers = thread::available_parallelism().map_or(1, |n| n.get()).min(files.len());
+    let mut staged = thread::scope(|scope| {
+        let handles = (0..workers).map(|_| scope.spawn(|| {
+            let mut staged = Vec::new();
+            while let Some((name, old)) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                staged.push((name, stage_blob(name, *old, write)));
+            }
+            staged
+        })).collect::<Vec<_>>();
+        handles.into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    staged.sort_by(|a, b| a.0.cmp(b.0));
+    staged.into_iter().map(|(_, result)| result).collect()
+}
+
+fn stage_blob(name: &str, old: Option<&Entry>, write: bool) -> io::Result<(Entry, bool)> {
+    let hash = match write {
+        true => worktree::stage_file(name)?,
+        false => worktree::file_hash(name).ok_or(io::Error::new(io::ErrorKind::InvalidData, format!("unable to index file '{}'", name)))?,
+    };
+    let entry = Entry::from_name(hash, name)?;
+    let changed = old.is_none_or(|old| old.hash != entry.hash || old.mode != entry.mode);
+    Ok((entry, changed))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::Entry;
+    use crate::object::blob::Blob;
+
+    #[test]
+    fn test_stage_blobs() {
+        let dir = std::env::temp_dir().join(format!("rusgit-stage-blobs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let names = (0..32).map(|i| {
+            let path = dir.join(format!("file{:02}.txt", i));
+            std::fs::write(&path, "line\n".repeat(i)).unwrap();
+            String::from(path.to_str().unwrap())
+        }).collect::<Vec<String>>();
+
+        // the same entries, in the same order, as hashing the files one by one
+        let serial = names.iter().map(|name| {
+            let hash = Blob::from_name(name).unwrap().calc_hash();
+            Entry::from_name(hash, name).unwrap()
+        }).collect::<Vec<Entry>>();
+        let files = names.iter().map(|name| (name.as_str(), None)).collect::<Vec<(&str, Option<&Entry>)>>();
+        let staged = super::stage_blobs(&files, false).unwrap();
+        assert_eq!(staged.len(), serial.len());
+        for ((entry, changed), expected) in staged.iter().zip(serial.iter()) {
+            assert_eq!(entry.name, expected.name);
+            assert_eq!(entry.hash, expected.hash);
+            assert_eq!(entry.mode, expected.mode);
+            assert!(changed);
+        }
+
+        // an entry that still matches the file is not reported as changed
+        std::fs::write(&names[1], "edited\n").unwrap();
+        let files = names.iter().zip(serial.iter()).map(|(name, entry)| (name.as_str(), Some(entry))).collect::<Vec<(&str, Option<&Entry>)>>();
+        let staged = super::stage_blobs(&files, false).unwrap();
+        let changed = staged.iter().filter(|(_, changed)| *changed).map(|(e, _)| e.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(changed, vec![names[1].as_str()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
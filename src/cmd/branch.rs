@@ -1,18 +1,25 @@
-
 use std::io;
 use crate::refs;
 
-pub fn branch(branch_name: Option<&str>) -> io::Result<()> {
+pub fn branch(branch_name: Option<&str>, delete: bool, force: bool) -> io::Result<()> {
     match branch_name {
+        // -D is -d that does not ask whether the branch is merged
+        Some(branch_name) if delete || force => {
+            let hash = refs::delete_branch(branch_name, force)?;
+            println!("Deleted branch {} (was {}).", branch_name, &hash[..7]);
+        },
         Some(branch_name) => {
             refs::create_branch(branch_name)?;
         },
         None => {
             // show branch
             let branches = refs::show_branches()?;
-            let current = refs::read_head_branch()?;
+            let current = refs::read_head_branch().ok();
+            if current.is_none() {
+                println!("* (HEAD detached at {})", &refs::rev_parse("HEAD")?[..7]);
+            }
             for b in branches.iter() {
-                if b == &current {
+                if Some(b) == current.as_ref() {
                     println!("* {}", b);
                 } else {
                     println!("  {}", b);
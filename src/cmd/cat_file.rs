@@ -1,11 +1,10 @@
 use std::io;
-use std::str;
 use std::fs::File;
 use std::io::Read;
-use libflate::zlib::{Encoder, Decoder};
+use libflate::zlib::Decoder;
 
 use crate::object::{Object, ObjectType};
-use crate::object::blob::Blob;
+use crate::object::stream::ObjectReader;
 use crate::cmd::GIT_OBJECTS_DIR;
 
 pub enum CatFileType {
@@ -31,52 +30,50 @@ pub fn cat_file(sha1: &str, opt: CatFileType) -> io::Result<()> {
     match opt {
         CatFileType::Type => {
             // rusgit cat-file -t <hash key> 
-            print!("{}", cat_file_t(&path)?);
+            print!("{}", ObjectReader::open(sha1)?.typ.to_string());
             
         },
         CatFileType::Size => {
             // rusgit cat-file -s <hash key>
-            print!("{}", cat_file_s(&path)?);
+            print!("{}", ObjectReader::open(sha1)?.size);
         },
         CatFileType::Print => {
             // rusgit cat-file -p <hash key>
-            print!("{}", cat_file_p(&path)?);
+            // a blob goes straight through, however large it is
+            let mut reader = ObjectReader::open(sha1)?;
+            if reader.typ == ObjectType::Blob {
+                io::copy(&mut reader, &mut io::stdout().lock())?;
+            } else {
+                print!("{}", cat_file_p(&path)?);
+            }
         }
     };
 
     Ok(())
 }
 
+pub fn cat_file_typed(typ: &str, sha1: &str) -> io::Result<()> {
+    // rusgit cat-file <type> <hash key>, the raw content streamed to stdout
+    let mut reader = ObjectReader::open(sha1)?;
+    if reader.typ.to_string() != typ {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("git cat-file {}: bad file", sha1)));
+    }
+    io::copy(&mut reader, &mut io::stdout().lock())?;
+    Ok(())
+}
+
 pub fn cat_file_p(path: &str) -> io::Result<String> {
     let obj = file_to_object(path)?;
     match obj {
-        Object::Blob(blob) => Ok(format!("{}", blob.content)),
+        Object::Blob(blob) => Ok(String::from_utf8_lossy(&blob.content).into_owned()),
         Object::Commit(commit) => Ok(format!("{}", commit)),
         Object::Tree(tree) => Ok(format!("{}", tree)),
+        Object::Tag(tag) => Ok(format!("{}", tag)),
     }
 }
 
-fn cat_file_t(path: &str) -> io::Result<String> {
-    let obj = file_to_object(path)?;
-    Ok(format!("{}", obj.typ().to_string()))
-}
-
-fn cat_file_s(path: &str) -> io::Result<String> {
-    let mut file = File::open(path)?;
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf)?;
-
-    // decode
-    let mut decoder = Decoder::new(&buf[..])?;
-    let mut data = Vec::new();
-    decoder.read_to_end(&mut data)?;
-    let size = Object::size(&data).unwrap();
-    Ok(format!("{}", size))
-}
-
 pub fn hash_key_to_path(sha1: &str) -> String {
     let (dir, file) = sha1.split_at(2);
-    println!("{}", format!("{}/{}/{}", GIT_OBJECTS_DIR, dir, file));
     format!("{}/{}/{}", GIT_OBJECTS_DIR, dir, file)
 }
 
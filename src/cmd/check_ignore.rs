@@ -0,0 +1,115 @@
+use std::io;
+use std::io::BufRead;
+use std::path::Path;
+use std::collections::HashSet;
+use crate::index;
+use crate::index::{GitIgnore, Pattern};
+use crate::cmd::GIT_INDEX;
+
+pub fn check_ignore(paths: Vec<&str>, verbose: bool, non_matching: bool, no_index: bool, stdin: bool) -> io::Result<bool> {
+    // true when some path matched, which is the exit status git reports
+    if non_matching && !verbose {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--non-matching is only valid with --verbose"));
+    }
+    let paths = match (stdin, paths.is_empty()) {
+        (true, true) => io::stdin().lock().lines().collect::<io::Result<Vec<String>>>()?,
+        (true, false) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot specify pathnames with --stdin")),
+        (false, true) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no path specified")),
+        (false, false) => paths.iter().map(|p| String::from(*p)).collect(),
+    };
+    let tracked = if no_index {
+        HashSet::new()
+    } else {
+        index::read_index(GIT_INDEX)?.entries.into_iter().map(|e| e.name).collect::<HashSet<String>>()
+    };
+    let gitignore = GitIgnore::read_gitignore()?;
+
+    let mut matched = false;
+    for path in paths.iter() {
+        let pattern = check_path(&gitignore, &tracked, path, verbose);
+        if let Some(line) = format_line(path, pattern.as_ref(), verbose, non_matching) {
+            println!("{}", line);
+        }
+        matched |= pattern.is_some();
+    }
+    Ok(matched)
+}
+
+fn check_path(gitignore: &GitIgnore, tracked: &HashSet<String>, path: &str, verbose: bool) -> Option<Pattern> {
+    // the rule check-ignore reports for a path, tracked files are not subject to exclude rules
+    let name = normalize(path);
+    if tracked.contains(&name) {
+        return None;
+    }
+    // a negated rule only shows up with -v
+    gitignore.is_ignored(Path::new(&name)).filter(|p| verbose || !p.negative)
+}
+
+fn format_line(path: &str, pattern: Option<&Pattern>, verbose: bool, non_matching: bool) -> Option<String> {
+    match pattern {
+        Some(pattern) if verbose => Some(format!("{}\t{}", pattern, path)),
+        Some(_) => Some(String::from(path)),
+        None if non_matching => Some(format!("::\t{}", path)),
+        None => None,
+    }
+}
+
+fn normalize(path: &str) -> String {
+    // resolve . and .. without touching the file system
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {},
+            ".." => { parts.pop(); },
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use crate::index::GitIgnore;
+
+    fn check(tracked: &[&str], path: &str, verbose: bool, non_matching: bool) -> Option<String> {
+        let gitignore = GitIgnore::from_rules("*.log\n!keep.log\nbuild/\n", ".gitignore");
+        let tracked = tracked.iter().map(|name| String::from(*name)).collect::<HashSet<String>>();
+        let pattern = super::check_path(&gitignore, &tracked, path, verbose);
+        super::format_line(path, pattern.as_ref(), verbose, non_matching)
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(super::normalize("./a/../b"), "b");
+        assert_eq!(super::normalize("dir/"), "dir");
+        assert_eq!(super::normalize("a//b/./c"), "a/b/c");
+        assert_eq!(super::normalize("../a"), "a");
+    }
+    #[test]
+    fn test_check_path() {
+        assert_eq!(check(&[], "a.log", false, false), Some(String::from("a.log")));
+        assert_eq!(check(&[], "./x/../a.log", true, false), Some(String::from(".gitignore:1:*.log\t./x/../a.log")));
+        assert_eq!(check(&[], "build/out", true, false), Some(String::from(".gitignore:3:build/\tbuild/out")));
+        assert_eq!(check(&[], "a.txt", false, false), None);
+        assert_eq!(check(&[], "a.txt", true, true), Some(String::from("::\ta.txt")));
+    }
+    #[test]
+    fn test_check_path_negated() {
+        // a path kept by a negated rule is reported only with -v
+        assert_eq!(check(&[], "keep.log", false, false), None);
+        assert_eq!(check(&[], "keep.log", true, false), Some(String::from(".gitignore:2:!keep.log\tkeep.log")));
+    }
+    #[test]
+    fn test_check_path_tracked() {
+        // an empty set is what --no-index passes
+        assert_eq!(check(&["a.log"], "a.log", true, false), None);
+        assert_eq!(check(&["a.log"], "./a.log", true, true), Some(String::from("::\t./a.log")));
+        assert_eq!(check(&[], "a.log", true, false), Some(String::from(".gitignore:1:*.log\ta.log")));
+    }
+    #[test]
+    fn test_non_matching_needs_verbose() {
+        let err = super::check_ignore(vec!["a.log"], false, true, false, false).unwrap_err();
+        assert_eq!(err.to_string(), "--non-matching is only valid with --verbose");
+    }
+}
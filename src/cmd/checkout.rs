@@ -1,16 +1,16 @@
-
 use std::io;
 use crate::refs;
+use crate::cmd::switch;
+use crate::cmd::switch::SwitchMode;
 
 pub fn checkout(branch: &str, new: bool) -> io::Result<()> {
-    match new {
-        true => {
-            refs::create_branch(branch)?;
-            refs::switch_branch(branch)?;
-        },
-        false => {
-            refs::switch_branch(branch)?;
-        },
+    // checkout is switch, except that naming a commit detaches HEAD
+    let mode = if new {
+        SwitchMode::Create
+    } else if refs::resolve_ref(&format!("refs/heads/{}", branch)).is_ok() {
+        SwitchMode::Branch
+    } else {
+        SwitchMode::Detach
     };
-    Ok(())
+    switch::switch(branch, None, mode)
 }
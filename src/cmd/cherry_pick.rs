@@ -0,0 +1,27 @@
+use std::io;
+use crate::sequencer;
+use crate::sequencer::{Action, Options};
+
+pub enum CherryPickOption {
+    Start {
+        commits: Vec<String>,
+        no_commit: bool,
+        record_origin: bool,
+        mainline: Option<usize>,
+    },
+    Continue,
+    Skip,
+    Abort,
+}
+
+pub fn cherry_pick(option: CherryPickOption) -> io::Result<bool> {
+    match option {
+        CherryPickOption::Start { commits, no_commit, record_origin, mainline } => {
+            let options = Options { no_commit, record_origin, mainline };
+            sequencer::start(Action::Pick, commits.iter().map(|c| c.as_str()).collect(), options)
+        },
+        CherryPickOption::Continue => sequencer::resume(),
+        CherryPickOption::Skip => sequencer::skip(),
+        CherryPickOption::Abort => sequencer::abort().map(|_| true),
+    }
+}
@@ -1,47 +1,94 @@
-
 use std::io;
-use std::path::Path;
+use std::fs;
 use crate::object::tree;
 use crate::object::commit;
+use crate::object::commit::User;
 use crate::object::Object;
 use crate::refs;
+use crate::merge;
+use crate::graph;
+use crate::cmd::{GIT_CHERRY_PICK_HEAD, GIT_REVERT_HEAD, GIT_INDEX};
+use crate::index;
+
+pub fn commit(message: Option<&str>) -> io::Result<()> {
+    index::with_lock(GIT_INDEX, || commit_staged(message))
+}
 
-pub fn commit(message: &str) -> io::Result<()> {
+fn commit_staged(message: Option<&str>) -> io::Result<()> {
     /* console output
         [master ca77114] second git
         1 file changed, 1 insertion(+)
         create mode 100644 .dockerignore
     */
-    // git write-tree
-    let tree = tree::write_tree()?;
-    let obj = Object::Tree(tree);
-    let hash = obj.write()?;
-
-    // git commit-tree
     // look up parent commit
     let ref_path = refs::read_head()?;
     let parent_res = refs::read_ref(&ref_path);
-    let parent: Option<&str> = match  parent_res {
-        Ok(ref p) => Some(p),
+    let mut parents: Vec<String> = match parent_res {
+        Ok(p) => vec![p],
         Err(e) => {
             if e.kind() != io::ErrorKind::NotFound {
                 return Err(e);
             }
-            None
+            Vec::new()
         }
     };
+    // concluding a merge records the merged commit as the second parent
+    let merge_head = merge::read_merge_head()?;
+    let merge_msg = merge::read_merge_msg()?;
+    if let Some(hash) = &merge_head {
+        parents.push(hash.clone());
+    }
+    // a stopped cherry-pick or revert leaves its message behind as well
+    let pick_head = fs::read_to_string(GIT_CHERRY_PICK_HEAD).ok();
+    let revert_head = fs::read_to_string(GIT_REVERT_HEAD).ok();
+    let picking = pick_head.is_some() || revert_head.is_some();
+    let message = match (message, &merge_msg) {
+        (Some(message), _) => String::from(message),
+        (None, Some(msg)) if merge_head.is_some() || picking => String::from(msg.trim_end()),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "commit message must be specified")),
+    };
+
+    let author = match &pick_head {
+        Some(hash) => Some(graph::read_commit(hash.trim())?.author),
+        None => None,
+    };
+    let commit_hash = commit_index(&message, parents.iter().map(|p| p.as_str()).collect(), author)?;
+    if merge_head.is_some() || picking {
+        merge::clear_merge_state()?;
+    }
+    for path in [GIT_CHERRY_PICK_HEAD, GIT_REVERT_HEAD].iter() {
+        if fs::metadata(path).is_ok() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    // output
+    let branch = refs::read_head_branch().unwrap_or_else(|_| String::from("detached HEAD"));
+    println!("[{} {}] {}", branch, &commit_hash[0..7], message.lines().next().unwrap_or(""));
+    Ok(())
+}
+
+// the identity every commit is made with
+pub const USER_NAME: &str = "terassyi";
+pub const USER_EMAIL: &str = "example@terassyi.net";
+
+pub fn commit_index(message: &str, parents: Vec<&str>, author: Option<User>) -> io::Result<String> {
+    // author is kept when commits are replayed, the committer is always us
+    // git write-tree
+    let tree = tree::write_tree()?;
+    let obj = Object::Tree(tree);
+    let hash = obj.write()?;
 
-    let name = "terassyi";
-    let email = "example@terassyi.net";
-    let commit = commit::commit_tree(name, email, &hash, message, parent)?;
+    // git commit-tree
+    let mut commit = commit::commit_tree(USER_NAME, USER_EMAIL, &hash, message, parents)?;
+    if let Some(author) = author {
+        commit.author = author;
+    }
     let obj = Object::Commit(commit);
     let commit_hash = obj.write()?;
 
     // git update-ref
+    let ref_path = refs::read_head()?;
     refs::update_ref(&ref_path, &commit_hash)?;
-
-    // output
-    let branch = Path::new(&ref_path).file_name().unwrap().to_str().unwrap();
-    println!("[{} {}] {}", branch, &commit_hash[0..7], message);
-    Ok(())
+    Ok(commit_hash)
 }
@@ -8,7 +8,7 @@ pub fn commit_tree(sha1: &str, parent: Option<&str>, message: Option<&str>) -> i
     // TODO read config
     let name = "terassyi";
     let email = "example@terassyi.net";
-    let commit = commit::commit_tree(name, email, sha1, message, parent)?;
+    let commit = commit::commit_tree(name, email, sha1, message, parent.into_iter().collect())?;
     let obj = Object::Commit(commit);
     obj.write()
 }
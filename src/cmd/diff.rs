@@ -1,9 +1,16 @@
 
 use std::io;
+use std::io::Write;
 use crate::index;
+use crate::index::DiffEntry;
 use crate::cmd::GIT_INDEX;
 
-pub fn diff() -> io::Result<()> {
+// lines of context around each change, as git shows by default
+pub const DEFAULT_CONTEXT: usize = 3;
+// git cuts the function line in a hunk header at this many bytes
+const FUNC_LINE_SIZE: usize = 80;
+
+pub fn diff(context: usize) -> io::Result<()> {
     /*
         1. read index and get entries
         2. get blob objects of each entries
@@ -14,37 +21,264 @@ pub fn diff() -> io::Result<()> {
     let index = index::read_index(GIT_INDEX)?;
     let diff_entries = index.diff()?;
     for entry in diff_entries {
-        println!("diff --git a/{} b/{}", entry.name, entry.name);
-        if entry.is_mode_modified() {
-            println!("old mode {}", entry.old_mode);
-            println!("new mode {}", entry.new_mode);
-        }
-        let new: Vec<&str> = entry.new.content.split('\n').collect();
-        let old: Vec<&str> = entry.old.content.split('\n').collect();
-        if entry.is_contents_modified() {
+        print_entry(&entry, context)?;
+    }
+    Ok(())
+}
+
+pub fn print_entry(entry: &DiffEntry, context: usize) -> io::Result<()> {
+    write_entry(&mut io::stdout().lock(), entry, context)
+}
+
+fn write_entry<W: Write>(out: &mut W, entry: &DiffEntry, context: usize) -> io::Result<()> {
+    writeln!(out, "diff --git a/{} b/{}", entry.name, entry.name)?;
+    // mode 0 stands for a side the path does not exist on
+    let old_hash = if entry.old_mode == 0 { String::from("0000000") } else { hex::encode(entry.old.calc_hash())[0..7].to_string() };
+    let new_hash = if entry.new_mode == 0 { String::from("0000000") } else { hex::encode(entry.new.calc_hash())[0..7].to_string() };
+    if entry.old_mode == 0 {
+        writeln!(out, "new file mode {}", entry.new_mode)?;
+        writeln!(out, "index {}..{}", old_hash, new_hash)?;
+    } else if entry.new_mode == 0 {
+        writeln!(out, "deleted file mode {}", entry.old_mode)?;
+        writeln!(out, "index {}..{}", old_hash, new_hash)?;
+    } else if entry.is_mode_modified() {
+        writeln!(out, "old mode {}", entry.old_mode)?;
+        writeln!(out, "new mode {}", entry.new_mode)?;
+    }
+    if entry.is_contents_modified() {
+        if entry.old_mode != 0 && entry.new_mode != 0 {
             if entry.is_mode_modified() {
-                println!("index {}..{}", &hex::encode(entry.old.calc_hash())[0..7], &hex::encode(entry.new.calc_hash())[0..7]);
+                writeln!(out, "index {}..{}", old_hash, new_hash)?;
             } else {
-                println!("index {}..{} {}", &hex::encode(entry.old.calc_hash())[0..7], &hex::encode(entry.new.calc_hash())[0..7], entry.new_mode);
+                writeln!(out, "index {}..{} {}", old_hash, new_hash, entry.new_mode)?;
             }
-            println!("--- a/{}", entry.name);
-            println!("+++ b/{}", entry.name);
-            let result = entry.compare();
-            for i in 0..(result.len()-1) {
-                match &result[i] {
-                    wu_diff::DiffResult::Common(elm) => {
-                        println!("{}", new[elm.new_index.unwrap()]); 
-                    },
-                    wu_diff::DiffResult::Removed(elm) => {
-                        println!("- {}", old[elm.old_index.unwrap()]);
-                    },
-                    wu_diff::DiffResult::Added(elm) => {
-                        println!("+ {}", new[elm.new_index.unwrap()]);
-                    },
+        }
+        let old_name = if entry.old_mode == 0 { String::from("/dev/null") } else { format!("a/{}", entry.name) };
+        let new_name = if entry.new_mode == 0 { String::from("/dev/null") } else { format!("b/{}", entry.name) };
+        if entry.is_binary() {
+            writeln!(out, "Binary files {} and {} differ", old_name, new_name)?;
+            return Ok(());
+        }
+        writeln!(out, "--- {}", old_name)?;
+        writeln!(out, "+++ {}", new_name)?;
+        write_hunks(out, &entry.old_lines(), &entry.new_lines(), context)?;
+    }
+    Ok(())
+}
+
+fn write_hunks<W: Write>(out: &mut W, old: &[&str], new: &[&str], context: usize) -> io::Result<()> {
+    let lines = diff_lines(&wu_diff::diff(old, new));
+    for (start, end) in hunks(&lines, context) {
+        write_hunk(out, old, new, &lines[start..end])?;
+    }
+    Ok(())
+}
+
+fn diff_lines(result: &[wu_diff::DiffResult]) -> Vec<(char, usize, usize)> {
+    // each line as ' ', '-' or '+' with the old and new line it stands at,
+    // the removals of a change coming before its additions like git shows them
+    let mut lines: Vec<(char, usize, usize)> = Vec::new();
+    let (mut old, mut new) = (0, 0);
+    let (mut removed, mut added) = (0, 0);
+    for r in result.iter().map(Some).chain(std::iter::once(None)) {
+        match r {
+            Some(wu_diff::DiffResult::Removed(_)) => removed += 1,
+            Some(wu_diff::DiffResult::Added(_)) => added += 1,
+            _ => {
+                lines.extend((0..removed).map(|i| ('-', old + i, new)));
+                lines.extend((0..added).map(|i| ('+', old + removed, new + i)));
+                old += removed;
+                new += added;
+                removed = 0;
+                added = 0;
+                if r.is_some() {
+                    lines.push((' ', old, new));
+                    old += 1;
+                    new += 1;
                 }
+            },
+        }
+    }
+    lines
+}
 
-            }
+fn hunks(lines: &[(char, usize, usize)], context: usize) -> Vec<(usize, usize)> {
+    // the changes with their context, changes closer than twice the context share a hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in lines.iter().enumerate().filter(|(_, (c, _, _))| *c != ' ') {
+        let start = i.saturating_sub(context);
+        let end = (i + 1 + context).min(lines.len());
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.1 => hunk.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    hunks
+}
+
+fn write_hunk<W: Write>(out: &mut W, old: &[&str], new: &[&str], lines: &[(char, usize, usize)]) -> io::Result<()> {
+    let (_, old_start, new_start) = lines[0];
+    let old_count = lines.iter().filter(|(c, _, _)| *c != '+').count();
+    let new_count = lines.iter().filter(|(c, _, _)| *c != '-').count();
+    let func = func_line(&old[..old_start]).map(|f| format!(" {}", f)).unwrap_or_default();
+    writeln!(out, "@@ -{} +{} @@{}", hunk_range(old_start, old_count), hunk_range(new_start, new_count), func)?;
+    for &(c, o, n) in lines {
+        let line = if c == '+' { new[n] } else { old[o] };
+        write!(out, "{}{}", c, line)?;
+        if !line.ends_with('\n') {
+            writeln!(out)?;
+            writeln!(out, "\\ No newline at end of file")?;
         }
     }
     Ok(())
 }
+
+fn hunk_range(start: usize, count: usize) -> String {
+    // 1-based, an empty range names the line before it and a single line goes without its count
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+fn func_line(lines: &[&str]) -> Option<String> {
+    // the closest line above the hunk that starts with a letter, '_' or '$', as git's default funcname
+    let line = lines.iter().rev().find(|l| l.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '$'))?;
+    let mut end = line.len().min(FUNC_LINE_SIZE);
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    Some(String::from(line[..end].trim_end()))
+}
+
+pub fn print_stat(entries: &[DiffEntry]) -> io::Result<()> {
+    write_stat(&mut io::stdout().lock(), entries)
+}
+
+fn write_stat<W: Write>(out: &mut W, entries: &[DiffEntry]) -> io::Result<()> {
+    // name | changes +++--- per file, a binary file with its sizes, then the totals
+    let counts = entries.iter()
+                    .map(|entry| entry.compare().iter().fold((0, 0), |(added, removed), r| match r {
+                        wu_diff::DiffResult::Added(_) => (added + 1, removed),
+                        wu_diff::DiffResult::Removed(_) => (added, removed + 1),
+                        wu_diff::DiffResult::Common(_) => (added, removed),
+                    }))
+                    .collect::<Vec<(usize, usize)>>();
+    let name_width = entries.iter().map(|e| e.name.len()).max().unwrap_or(0);
+    let count_width = entries.iter().zip(counts.iter())
+                    .map(|(e, (a, r))| if e.is_binary() { "Bin".len() } else { (a + r).to_string().len() })
+                    .max().unwrap_or(0);
+    for (entry, (added, removed)) in entries.iter().zip(counts.iter()) {
+        if entry.is_binary() {
+            writeln!(out, " {:<nw$} | Bin {} -> {} bytes", entry.name, entry.old.content.len(), entry.new.content.len(), nw = name_width)?;
+            continue;
+        }
+        writeln!(out, " {:<nw$} | {:>cw$} {}{}", entry.name, added + removed, "+".repeat(*added), "-".repeat(*removed), nw = name_width, cw = count_width)?;
+    }
+    let added = counts.iter().map(|(a, _)| a).sum::<usize>();
+    let removed = counts.iter().map(|(_, r)| r).sum::<usize>();
+    let mut summary = format!(" {} file{} changed", entries.len(), if entries.len() == 1 { "" } else { "s" });
+    // git names both counts when there are none, as for binary files and mode changes
+    if added > 0 || removed == 0 {
+        summary.push_str(&format!(", {} insertion{}(+)", added, if added == 1 { "" } else { "s" }));
+    }
+    if removed > 0 || added == 0 {
+        summary.push_str(&format!(", {} deletion{}(-)", removed, if removed == 1 { "" } else { "s" }));
+    }
+    writeln!(out, "{}", summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::DiffEntry;
+    use crate::object::blob::Blob;
+
+    fn hunks(old: &[&str], new: &[&str], context: usize) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        super::write_hunks(&mut out, old, new, context).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn numbered(n: usize) -> Vec<String> {
+        (1..=n).map(|i| format!("{}\n", i)).collect()
+    }
+
+    #[test]
+    fn test_hunk_ranges() {
+        assert_eq!(hunks(&[], &["a\n", "b\n"], 3), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+        assert_eq!(hunks(&["a\n", "b\n"], &[], 3), "@@ -1,2 +0,0 @@\n-a\n-b\n");
+        assert_eq!(hunks(&["a\n"], &["b\n"], 3), "@@ -1 +1 @@\n-a\n+b\n");
+        assert_eq!(super::hunk_range(3, 0), "3,0");
+        assert_eq!(super::hunk_range(3, 1), "4");
+        assert_eq!(super::hunk_range(3, 2), "4,2");
+    }
+    #[test]
+    fn test_hunks_merge_and_split() {
+        let old = numbered(20);
+        let old = old.iter().map(|l| l.as_str()).collect::<Vec<&str>>();
+        // six unchanged lines between the changes still share a hunk with three lines of context
+        let mut new = old.clone();
+        new[4] = "five\n";
+        new[11] = "twelve\n";
+        assert_eq!(hunks(&old, &new, 3), "@@ -2,14 +2,14 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n 9\n 10\n 11\n-12\n+twelve\n 13\n 14\n 15\n");
+        // seven do not
+        let mut new = old.clone();
+        new[4] = "five\n";
+        new[12] = "thirteen\n";
+        assert_eq!(hunks(&old, &new, 3), "@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n@@ -10,7 +10,7 @@\n 10\n 11\n 12\n-13\n+thirteen\n 14\n 15\n 16\n");
+    }
+    #[test]
+    fn test_hunks_no_context() {
+        let old = numbered(10);
+        let old = old.iter().map(|l| l.as_str()).collect::<Vec<&str>>();
+        let mut new = old.clone();
+        new[4] = "five\n";
+        new.insert(8, "x\n");
+        assert_eq!(hunks(&old, &new, 0), "@@ -5 +5 @@\n-5\n+five\n@@ -8,0 +9 @@\n+x\n");
+    }
+    #[test]
+    fn test_hunks_no_newline() {
+        assert_eq!(hunks(&["a\n", "b"], &["a\n", "c"], 3), "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n");
+        assert_eq!(hunks(&["a"], &["a\n"], 3), "@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+a\n");
+        assert_eq!(hunks(&["a\n"], &["a"], 3), "@@ -1 +1 @@\n-a\n+a\n\\ No newline at end of file\n");
+    }
+    #[test]
+    fn test_func_line() {
+        assert_eq!(super::func_line(&["int main(void)  \n", "{\n", "\tint a;\n", "1\n"]), Some(String::from("int main(void)")));
+        assert_eq!(super::func_line(&["{\n", "  a\n"]), None);
+        // 79 ASCII bytes then a two-byte character that would end at byte 81
+        let long = format!("{}\u{e9}tail\n", "x".repeat(79));
+        assert_eq!(super::func_line(&[&long]), Some("x".repeat(79)));
+        let header = hunks(&[&long, "a\n"], &[&long, "b\n"], 0);
+        assert_eq!(header.lines().next().unwrap(), format!("@@ -2 +2 @@ {}", "x".repeat(79)));
+    }
+    #[test]
+    fn test_binary_entry() {
+        let entry = |old: &[u8], new: &[u8], old_mode: u32, new_mode: u32| {
+            let entry = DiffEntry::new("bin", Blob::from(new).unwrap(), Blob::from(old).unwrap(), new_mode, old_mode);
+            let mut out: Vec<u8> = Vec::new();
+            super::write_entry(&mut out, &entry, 3).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(entry(b"x\0old", b"x\0new", 100644, 100644), "diff --git a/bin b/bin\nindex e92c3bd..08337e3 100644\nBinary files a/bin and b/bin differ\n");
+        // text on one side is not enough for a line diff
+        assert_eq!(entry(b"text\n", &[0xff, 0xfe], 100644, 100644), "diff --git a/bin b/bin\nindex 8e27be7..46b134b 100644\nBinary files a/bin and b/bin differ\n");
+        assert_eq!(entry(b"", b"x\0", 0, 100644), "diff --git a/bin b/bin\nnew file mode 100644\nindex 0000000..7a002a8\nBinary files /dev/null and b/bin differ\n");
+        assert_eq!(entry(b"x\0", b"", 100644, 0), "diff --git a/bin b/bin\ndeleted file mode 100644\nindex 7a002a8..0000000\nBinary files a/bin and /dev/null differ\n");
+    }
+    #[test]
+    fn test_binary_stat() {
+        let entries = vec![
+            DiffEntry::new("bin", Blob::from(b"x\0new").unwrap(), Blob::from(b"x\0ours").unwrap(), 100644, 100644),
+            DiffEntry::new("t", Blob::new("t\nmore\n"), Blob::new("t\n"), 100644, 100644),
+        ];
+        let mut out: Vec<u8> = Vec::new();
+        super::write_stat(&mut out, &entries).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), " bin | Bin 6 -> 5 bytes\n t   |   1 +\n 2 files changed, 1 insertion(+)\n");
+
+        let mut out: Vec<u8> = Vec::new();
+        super::write_stat(&mut out, &entries[..1]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), " bin | Bin 6 -> 5 bytes\n 1 file changed, 0 insertions(+), 0 deletions(-)\n");
+    }
+}
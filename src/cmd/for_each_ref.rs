@@ -0,0 +1,366 @@
+use std::io;
+use std::cmp::Ordering;
+use chrono::{DateTime, FixedOffset};
+use crate::refs;
+use crate::refs::Ref;
+use crate::config::Config;
+use crate::graph;
+use crate::object::Object;
+use crate::cmd::cat_file::{file_to_object, hash_key_to_path};
+
+const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+const ATOMS: [&str; 12] = [
+    "refname", "objectname", "objecttype", "committerdate", "authordate", "taggerdate",
+    "creatordate", "subject", "upstream", "HEAD", "tag", "type",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    Atom(String),
+}
+
+struct RefInfo {
+    r: Ref,
+    object: Option<Object>,
+}
+
+pub fn for_each_ref(patterns: Vec<&str>, format: Option<&str>, sort: Vec<&str>, count: Option<usize>, points_at: Option<&str>) -> io::Result<()> {
+    let format = parse_format(format.unwrap_or(DEFAULT_FORMAT))?;
+    let sort = if sort.is_empty() { vec!["refname"] } else { sort };
+    for key in sort.iter() {
+        validate_atom(key.trim_start_matches('-'))?;
+    }
+    let points_at = match points_at {
+        Some(p) => Some(refs::rev_parse(p)?),
+        None => None,
+    };
+    let config = Config::read_config()?;
+    let head = refs::read_head_branch().ok();
+
+    let mut infos = refs::read_refs()?.into_iter()
+        .filter(|r| patterns.is_empty() || patterns.iter().any(|p| is_match(&r.name, p)))
+        .map(|r| {
+            let object = file_to_object(&hash_key_to_path(&r.hash)).ok();
+            RefInfo { r, object }
+        })
+        .filter(|info| match &points_at {
+            Some(target) => points_to(info, target),
+            None => true,
+        })
+        .collect::<Vec<RefInfo>>();
+
+    sort_infos(&mut infos, &sort, &config, &head)?;
+
+    for info in infos.iter().take(count.unwrap_or(usize::MAX)) {
+        let mut line = String::new();
+        for token in format.iter() {
+            match token {
+                Token::Literal(l) => line.push_str(l),
+                Token::Atom(atom) => line.push_str(&atom_value(atom, info, &config, &head)?),
+            }
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn sort_infos(infos: &mut [RefInfo], sort: &[&str], config: &Config, head: &Option<String>) -> io::Result<()> {
+    // the last --sort key is the primary one, a leading '-' sorts it descending
+    let mut error = None;
+    infos.sort_by(|a, b| {
+        for key in sort.iter().rev() {
+            let (atom, reverse) = match key.strip_prefix('-') {
+                Some(atom) => (atom, true),
+                None => (*key, false),
+            };
+            let order = match (sort_value(atom, a, config, head), sort_value(atom, b, config, head)) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Err(e), _) | (_, Err(e)) => {
+                    error = Some(e);
+                    Ordering::Equal
+                },
+            };
+            let order = if reverse { order.reverse() } else { order };
+            if order != Ordering::Equal {
+                return order;
+            }
+        }
+        Ordering::Equal
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn parse_format(format: &str) -> io::Result<Vec<Token>> {
+    // literal text with %(atom), %% and %xx hex escapes
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut literal = String::new();
+    let mut rest = format;
+    while let Some(i) = rest.find('%') {
+        literal.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(r) = rest.strip_prefix("%%") {
+            literal.push('%');
+            rest = r;
+        } else if rest.starts_with("%(") {
+            let tail = rest.find(')')
+                .ok_or(io::Error::new(io::ErrorKind::InvalidInput, format!("malformed format string {}", rest)))?;
+            let atom = &rest[2..tail];
+            validate_atom(atom)?;
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(literal.clone()));
+                literal.clear();
+            }
+            tokens.push(Token::Atom(String::from(atom)));
+            rest = &rest[(tail + 1)..];
+        } else if let Some(b) = rest.get(1..3).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            literal.push(b as char);
+            rest = &rest[3..];
+        } else {
+            literal.push('%');
+            rest = &rest[1..];
+        }
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    Ok(tokens)
+}
+
+fn validate_atom(atom: &str) -> io::Result<()> {
+    let name = atom.split(':').next().unwrap_or(atom);
+    if ATOMS.contains(&name) {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown field name: {}", atom)))
+    }
+}
+
+fn atom_value(atom: &str, info: &RefInfo, config: &Config, head: &Option<String>) -> io::Result<String> {
+    let mut iter = atom.splitn(2, ':');
+    let name = iter.next().unwrap_or(atom);
+    let modifier = iter.next();
+    let value = match name {
+        "refname" => match modifier {
+            Some("short") => String::from(info.r.short_name()),
+            _ => info.r.name.clone(),
+        },
+        "objectname" => match modifier {
+            Some("short") => String::from(&info.r.hash[..7]),
+            _ => info.r.hash.clone(),
+        },
+        "objecttype" => info.object.as_ref().map(|o| o.typ().to_string()).unwrap_or_default(),
+        "committerdate" | "authordate" | "taggerdate" | "creatordate" => {
+            date(name, &info.object).map(|d| format_date(&d, modifier)).unwrap_or_default()
+        },
+        "subject" => match &info.object {
+            Some(Object::Commit(commit)) => String::from(commit.message.lines().next().unwrap_or("")),
+            Some(Object::Tag(tag)) => String::from(tag.message.lines().next().unwrap_or("")),
+            _ => String::new(),
+        },
+        "tag" => match &info.object {
+            Some(Object::Tag(tag)) => tag.tag.clone(),
+            _ => String::new(),
+        },
+        "type" => match &info.object {
+            Some(Object::Tag(tag)) => tag.typ.to_string(),
+            _ => String::new(),
+        },
+        "HEAD" => match head {
+            Some(branch) if info.r.name == format!("refs/heads/{}", branch) => String::from("*"),
+            _ => String::from(" "),
+        },
+        "upstream" => upstream_value(&info.r, modifier, config)?,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown field name: {}", atom))),
+    };
+    Ok(value)
+}
+
+fn sort_value(atom: &str, info: &RefInfo, config: &Config, head: &Option<String>) -> io::Result<(i64, String)> {
+    // dates compare by timestamp, everything else by its string value
+    let name = atom.split(':').next().unwrap_or(atom);
+    match name {
+        "committerdate" | "authordate" | "taggerdate" | "creatordate" => {
+            Ok((date(name, &info.object).map(|d| d.timestamp()).unwrap_or(0), String::new()))
+        },
+        _ => Ok((0, atom_value(atom, info, config, head)?)),
+    }
+}
+
+fn date(name: &str, object: &Option<Object>) -> Option<DateTime<FixedOffset>> {
+    match (name, object) {
+        ("committerdate", Some(Object::Commit(commit))) | ("creatordate", Some(Object::Commit(commit))) => Some(commit.commiter.timestamp),
+        ("authordate", Some(Object::Commit(commit))) => Some(commit.author.timestamp),
+        ("taggerdate", Some(Object::Tag(tag))) | ("creatordate", Some(Object::Tag(tag))) => tag.tagger.as_ref().map(|t| t.timestamp),
+        _ => None,
+    }
+}
+
+fn format_date(date: &DateTime<FixedOffset>, modifier: Option<&str>) -> String {
+    match modifier {
+        Some("short") => date.format("%Y-%m-%d").to_string(),
+        Some("iso") => date.format("%Y-%m-%d %H:%M:%S %z").to_string(),
+        Some("unix") => date.timestamp().to_string(),
+        _ => date.format("%a %b %-d %H:%M:%S %Y %z").to_string(),
+    }
+}
+
+fn upstream_value(r: &Ref, modifier: Option<&str>, config: &Config) -> io::Result<String> {
+    let upstream = match refs::upstream_of(&r.name, config) {
+        Some(upstream) => upstream,
+        None => return Ok(String::new()),
+    };
+    let value = match modifier {
+        Some("short") => String::from(Ref::new(&upstream, "", None).short_name()),
+        Some("track") | Some("trackshort") => {
+            let counts = match refs::resolve_ref(&upstream) {
+                Ok(hash) => Some(graph::ahead_behind(&r.hash, &hash)?),
+                Err(_) => None,
+            };
+            track_value(counts, modifier == Some("trackshort"))
+        },
+        _ => upstream,
+    };
+    Ok(value)
+}
+
+fn track_value(counts: Option<(usize, usize)>, short: bool) -> String {
+    // how far a branch is ahead of and behind its upstream, None when the upstream is gone
+    match (counts, short) {
+        (None, false) => String::from("[gone]"),
+        (None, true) => String::new(),
+        (Some((0, 0)), true) => String::from("="),
+        (Some((_, 0)), true) => String::from(">"),
+        (Some((0, _)), true) => String::from("<"),
+        (Some(_), true) => String::from("<>"),
+        (Some((0, 0)), false) => String::new(),
+        (Some((a, 0)), false) => format!("[ahead {}]", a),
+        (Some((0, b)), false) => format!("[behind {}]", b),
+        (Some((a, b)), false) => format!("[ahead {}, behind {}]", a, b),
+    }
+}
+
+fn points_to(info: &RefInfo, target: &str) -> bool {
+    info.r.hash == target
+        || info.r.peeled.as_deref() == Some(target)
+        || matches!(&info.object, Some(Object::Tag(tag)) if tag.object == target)
+}
+
+fn is_match(name: &str, pattern: &str) -> bool {
+    // refs/heads matches everything below it, globs match within a path component
+    let pattern = pattern.trim_end_matches('/');
+    if pattern.contains(['*', '?']) {
+        return wildcard(name.as_bytes(), pattern.as_bytes());
+    }
+    name == pattern || name.starts_with(&format!("{}/", pattern))
+}
+
+fn wildcard(name: &[u8], pattern: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (None, Some(_)) => false,
+        (Some(b'*'), _) => {
+            wildcard(name, &pattern[1..])
+                || (!name.is_empty() && name[0] != b'/' && wildcard(&name[1..], pattern))
+        },
+        (Some(b'?'), Some(&c)) => c != b'/' && wildcard(&name[1..], &pattern[1..]),
+        (Some(&p), Some(&c)) => p == c && wildcard(&name[1..], &pattern[1..]),
+        (Some(_), None) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::refs::Ref;
+    use crate::config::Config;
+    use super::{Token, RefInfo};
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn info(name: &str) -> RefInfo {
+        RefInfo { r: Ref::new(name, HASH, None), object: None }
+    }
+
+    fn value(atom: &str, name: &str, config: &Config) -> String {
+        super::atom_value(atom, &info(name), config, &Some(String::from("main"))).unwrap()
+    }
+
+    fn sorted(sort: &[&str]) -> Vec<String> {
+        let mut infos = ["refs/tags/v1", "refs/heads/topic", "refs/heads/main", "refs/heads/fix"].iter()
+                        .map(|name| info(name))
+                        .collect::<Vec<RefInfo>>();
+        super::sort_infos(&mut infos, sort, &Config::new(vec![]), &Some(String::from("main"))).unwrap();
+        infos.into_iter().map(|i| i.r.name).collect()
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let tokens = super::parse_format("%(refname:short) %%%(objectname)%09x").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Atom(String::from("refname:short")),
+            Token::Literal(String::from(" %")),
+            Token::Atom(String::from("objectname")),
+            Token::Literal(String::from("\tx")),
+        ]);
+        // a lone % that starts no escape stays as it is
+        assert_eq!(super::parse_format("100%zz").unwrap(), vec![Token::Literal(String::from("100%zz"))]);
+        assert_eq!(super::parse_format("%(bogus)").unwrap_err().to_string(), "unknown field name: bogus");
+        assert_eq!(super::parse_format("%(refname").unwrap_err().to_string(), "malformed format string %(refname");
+    }
+    #[test]
+    fn test_short_atoms() {
+        let config = Config::new(vec![]);
+        assert_eq!(value("refname", "refs/heads/main", &config), "refs/heads/main");
+        assert_eq!(value("refname:short", "refs/heads/main", &config), "main");
+        assert_eq!(value("refname:short", "refs/tags/v1", &config), "v1");
+        assert_eq!(value("refname:short", "refs/remotes/origin/main", &config), "origin/main");
+        assert_eq!(value("objectname:short", "refs/heads/main", &config), "0123456");
+        assert_eq!(value("HEAD", "refs/heads/main", &config), "*");
+        assert_eq!(value("HEAD", "refs/heads/topic", &config), " ");
+    }
+    #[test]
+    fn test_upstream() {
+        let config = Config::from("[remote \"origin\"]\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n[branch \"main\"]\n\tremote = origin\n\tmerge = refs/heads/main\n[branch \"topic\"]\n\tremote = .\n\tmerge = refs/heads/main\n");
+        assert_eq!(value("upstream", "refs/heads/main", &config), "refs/remotes/origin/main");
+        assert_eq!(value("upstream:short", "refs/heads/main", &config), "origin/main");
+        assert_eq!(value("upstream:short", "refs/heads/topic", &config), "main");
+        assert_eq!(value("upstream", "refs/heads/fix", &config), "");
+
+        assert_eq!(super::track_value(Some((0, 0)), false), "");
+        assert_eq!(super::track_value(Some((2, 0)), false), "[ahead 2]");
+        assert_eq!(super::track_value(Some((0, 3)), false), "[behind 3]");
+        assert_eq!(super::track_value(Some((2, 3)), false), "[ahead 2, behind 3]");
+        assert_eq!(super::track_value(None, false), "[gone]");
+        let short = [Some((0, 0)), Some((2, 0)), Some((0, 3)), Some((2, 3)), None].iter()
+                        .map(|counts| super::track_value(*counts, true))
+                        .collect::<Vec<String>>();
+        assert_eq!(short, vec!["=", ">", "<", "<>", ""]);
+    }
+    #[test]
+    fn test_sort() {
+        assert_eq!(sorted(&["refname"]), vec!["refs/heads/fix", "refs/heads/main", "refs/heads/topic", "refs/tags/v1"]);
+        assert_eq!(sorted(&["-refname"]), vec!["refs/tags/v1", "refs/heads/topic", "refs/heads/main", "refs/heads/fix"]);
+        // the last key decides first, the earlier ones break its ties
+        assert_eq!(sorted(&["-refname", "-HEAD"]), vec!["refs/heads/main", "refs/tags/v1", "refs/heads/topic", "refs/heads/fix"]);
+        assert_eq!(sorted(&["refname:short"]), vec!["refs/heads/fix", "refs/heads/main", "refs/heads/topic", "refs/tags/v1"]);
+    }
+    #[test]
+    fn test_is_match() {
+        // a pattern without a glob matches the ref or what is below it, by whole components
+        assert!(super::is_match("refs/heads/main", "refs/heads"));
+        assert!(super::is_match("refs/heads/main", "refs/heads/"));
+        assert!(super::is_match("refs/heads/main", "refs/heads/main"));
+        assert!(!super::is_match("refs/heads/main", "refs/heads/ma"));
+        assert!(!super::is_match("refs/heads/main", "heads"));
+        // a glob does not cross a '/'
+        assert!(super::is_match("refs/heads/main", "refs/heads/m*"));
+        assert!(super::is_match("refs/heads/main", "refs/*/main"));
+        assert!(super::is_match("refs/heads/main", "refs/heads/mai?"));
+        assert!(!super::is_match("refs/heads/feature/x", "refs/heads/*"));
+        assert!(!super::is_match("refs/heads/main", "refs/*"));
+    }
+}
@@ -1,20 +1,13 @@
 use std::io;
-use std::fs::File;
-use std::io::Read;
 
-use crate::object::blob::Blob;
-use crate::object::Object;
+use crate::object::stream;
 
 pub fn hash_object(path: &str, w: bool) -> io::Result<()> {
-    let mut file = File::open(path)?;
-    let mut buf: Vec<u8> = Vec::new();
-    file.read_to_end(&mut buf)?;
-    let blob = Blob::from(&buf).ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+    // streamed, so the size of the file does not matter
     if !w {
-        println!("{}", hex::encode(blob.calc_hash()));
+        println!("{}", hex::encode(stream::hash_file(path)?));
     } else {
-        let obj = Object::Blob(blob);
-        obj.write()?;
+        stream::write_file(path)?;
     }
     Ok(())
 }
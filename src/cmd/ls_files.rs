@@ -1,16 +1,15 @@
 use std::io;
-use std::fs::File;
-use std::io::Read;
 use crate::index::read_index;
 use crate::cmd::GIT_INDEX;
 
-pub fn ls_files(staged: bool) -> io::Result<()> {
+pub fn ls_files(staged: bool, unmerged: bool) -> io::Result<()> {
     let index_path = GIT_INDEX;
     let index = read_index(index_path)?;
-    if staged {
-        print!("{}", index);
-    } else {
-        for e in index.entries {
+    // -u implies -s, otherwise there is no way to tell the stages apart
+    for e in index.entries.iter().filter(|e| !unmerged || e.stage != 0) {
+        if staged || unmerged {
+            println!("{}", e);
+        } else {
             println!("{}", e.name);
         }
     }
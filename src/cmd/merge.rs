@@ -0,0 +1,63 @@
+use std::io;
+use crate::refs;
+use crate::merge;
+use crate::graph;
+use crate::object::commit::Commit;
+use crate::cmd::commit;
+use crate::cmd::cat_file::hash_key_to_path;
+use crate::index;
+use crate::cmd::GIT_INDEX;
+
+pub fn merge(name: &str, no_ff: bool, message: Option<&str>) -> io::Result<bool> {
+    // false when the merge stopped at conflicts
+    index::with_lock(GIT_INDEX, || merge_into(name, no_ff, message))
+}
+
+fn merge_into(name: &str, no_ff: bool, message: Option<&str>) -> io::Result<bool> {
+    if merge::read_merge_head()?.is_some() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "You have not concluded your merge (MERGE_HEAD exists)."));
+    }
+    let theirs = refs::rev_parse(name)?;
+    let ours = refs::rev_parse("HEAD")?;
+    let base = graph::merge_base(&ours, &theirs)?;
+    if base.as_deref() == Some(theirs.as_str()) {
+        println!("Already up to date.");
+        return Ok(true);
+    }
+    let ours_commit = Commit::from_hash_file(&hash_key_to_path(&ours))?;
+    let theirs_commit = Commit::from_hash_file(&hash_key_to_path(&theirs))?;
+
+    if base.as_deref() == Some(ours.as_str()) && !no_ff {
+        let entries = merge::merge_trees(Some(&ours_commit.tree), &ours_commit.tree, &theirs_commit.tree, "HEAD", name)?;
+        merge::check_worktree(&entries)?;
+        println!("Updating {}..{}", &ours[..7], &theirs[..7]);
+        println!("Fast-forward");
+        merge::checkout_entries(&entries)?;
+        refs::update_ref(&refs::read_head()?, &theirs)?;
+        return Ok(true);
+    }
+
+    let base_tree = match &base {
+        Some(base) => Some(Commit::from_hash_file(&hash_key_to_path(base))?.tree),
+        None => None,
+    };
+    let entries = merge::merge_trees(base_tree.as_deref(), &ours_commit.tree, &theirs_commit.tree, "HEAD", name)?;
+    merge::check_worktree(&entries)?;
+    merge::checkout_entries(&entries)?;
+    for message in merge::describe(&entries) {
+        println!("{}", message);
+    }
+
+    let message = match message {
+        Some(message) => String::from(message),
+        None => format!("Merge branch '{}'", name),
+    };
+    if entries.iter().any(|e| !e.is_clean()) {
+        merge::write_merge_state(&theirs, &format!("{}\n", message))?;
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        return Ok(false);
+    }
+    commit::commit_index(&message, vec![&ours, &theirs], None)?;
+    println!("Merge made by the 'recursive' strategy.");
+    Ok(true)
+}
@@ -0,0 +1,32 @@
+use std::io;
+use crate::refs;
+use crate::graph;
+
+pub fn merge_base(commits: Vec<&str>, all: bool, octopus: bool, is_ancestor: bool, independent: bool) -> io::Result<bool> {
+    // false when there is nothing to print, or --is-ancestor does not hold
+    let hashes = commits.iter()
+        .map(|c| refs::rev_parse(c))
+        .collect::<io::Result<Vec<String>>>()?;
+    let hashes = hashes.iter().map(|h| h.as_str()).collect::<Vec<&str>>();
+    if is_ancestor {
+        if hashes.len() != 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "--is-ancestor takes exactly two commits"));
+        }
+        return graph::is_ancestor(hashes[0], hashes[1]);
+    }
+    let bases = if independent {
+        graph::independent(&hashes)?
+    } else if octopus {
+        graph::octopus_merge_bases(&hashes)?.into_iter().rev().collect()
+    } else {
+        if hashes.len() < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "merge-base needs at least two commits"));
+        }
+        graph::merge_bases(hashes[0], &hashes[1..])?
+    };
+    let show_all = all || independent;
+    for base in bases.iter().take(if show_all { bases.len() } else { 1 }) {
+        println!("{}", base);
+    }
+    Ok(!bases.is_empty())
+}
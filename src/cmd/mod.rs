@@ -13,6 +13,20 @@ pub mod status;
 pub mod diff;
 pub mod branch;
 pub mod checkout;
+pub mod show_ref;
+pub mod for_each_ref;
+pub mod merge;
+pub mod merge_base;
+pub mod rebase;
+pub mod cherry_pick;
+pub mod revert;
+pub mod reset;
+pub mod switch;
+pub mod restore;
+pub mod stash;
+pub mod rm;
+pub mod mv;
+pub mod check_ignore;
 
 pub const RUSGIT_BASE_DIR: &str = ".rusgit";
 pub const RUSGIT_OBJECTS_DIR: &str = ".rusgit/objects";
@@ -30,4 +44,16 @@ pub const GIT_REFS_HEADS_DIR: &str = ".git/refs/heads";
 pub const REFS_HEADS_DIR: &str = "refs/heads";
 pub const GIT_REFS_REMOTES_DIR: &str = ".git/refs/remotes";
 pub const GIT_HEAD_FILE: &str = ".git/HEAD";
+pub const GIT_LOGS_DIR: &str = ".git/logs";
+pub const GIT_PACKED_REFS: &str = ".git/packed-refs";
+pub const GIT_CONFIG: &str = ".git/config";
+pub const GIT_ORIG_HEAD: &str = ".git/ORIG_HEAD";
+pub const GIT_MERGE_HEAD: &str = ".git/MERGE_HEAD";
+pub const GIT_MERGE_MSG: &str = ".git/MERGE_MSG";
+pub const GIT_REBASE_MERGE_DIR: &str = ".git/rebase-merge";
+pub const GIT_COMMIT_EDITMSG: &str = ".git/COMMIT_EDITMSG";
+pub const GIT_SEQUENCER_DIR: &str = ".git/sequencer";
+pub const GIT_CHERRY_PICK_HEAD: &str = ".git/CHERRY_PICK_HEAD";
+pub const GIT_REVERT_HEAD: &str = ".git/REVERT_HEAD";
+pub const GIT_INFO_EXCLUDE: &str = ".git/info/exclude";
 pub const GITIGNORE: &str = ".gitignore";
@@ -0,0 +1,158 @@
+use std::io;
+use std::fs;
+use std::path::Path;
+use crate::index;
+use crate::index::Index;
+use crate::cmd::GIT_INDEX;
+
+// a source and where it goes
+type Move = (String, String);
+
+pub fn mv(sources: Vec<&str>, destination: &str) -> io::Result<()> {
+    index::with_lock(GIT_INDEX, || move_paths(sources, destination))
+}
+
+fn move_paths(sources: Vec<&str>, destination: &str) -> io::Result<()> {
+    let index = index::read_index(GIT_INDEX)?;
+    let stat = |path: &str| fs::symlink_metadata(path).ok().map(|m| m.is_dir());
+    let (index, moves) = plan_moves(index, &sources, destination, Path::new(destination).is_dir(), &stat)?;
+    // the index is only written once every file has moved
+    move_files(&moves)?;
+    if let Err(e) = index::write_index(GIT_INDEX, &index) {
+        for (source, target) in moves.iter().rev() {
+            fs::rename(target, source)?;
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn plan_moves(mut index: Index, sources: &[&str], destination: &str, is_dir: bool, stat: &dyn Fn(&str) -> Option<bool>) -> io::Result<(Index, Vec<Move>)> {
+    // the renamed index and each source with its target,
+    // stat tells whether a path is a directory, None when it is missing
+    // several sources, or a destination directory that exists, move into it
+    let into_dir = sources.len() > 1 || is_dir;
+    if sources.len() > 1 && !is_dir {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("destination '{}' is not a directory", destination)));
+    }
+    let mut moves: Vec<Move> = Vec::new();
+    for source in sources.iter() {
+        let source = source.trim_end_matches('/');
+        let target = if into_dir {
+            let base = Path::new(source).file_name().and_then(|n| n.to_str()).unwrap_or(source);
+            format!("{}/{}", destination.trim_end_matches('/'), base)
+        } else {
+            String::from(destination.trim_end_matches('/'))
+        };
+        let error = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{}, source={}, destination={}", message, source, target));
+        if stat(source).is_none() {
+            return Err(error("bad source"));
+        }
+        if source == target || target.starts_with(&format!("{}/", source)) {
+            return Err(error("can not move directory into itself"));
+        }
+        if stat(&target).is_some() {
+            return Err(error("destination exists"));
+        }
+        // a directory carries every entry below it
+        let names = index.entries.iter()
+                    .filter(|e| index::match_pathspec(&e.name, &[source]))
+                    .map(|e| e.name.clone())
+                    .collect::<Vec<String>>();
+        if names.is_empty() {
+            return Err(error("not under version control"));
+        }
+        if index.entries.iter().any(|e| e.stage != 0 && names.contains(&e.name)) {
+            return Err(error("conflicted"));
+        }
+        for name in names.iter() {
+            let renamed = format!("{}{}", target, &name[source.len()..]);
+            index = index::rename_index(index, name, &renamed)?;
+        }
+        moves.push((String::from(source), target));
+    }
+    Ok((index, moves))
+}
+
+fn move_files(moves: &[Move]) -> io::Result<()> {
+    // a failed move puts the earlier ones back
+    for (i, (source, target)) in moves.iter().enumerate() {
+        if let Some(dir) = Path::new(target).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if let Err(e) = fs::rename(source, target) {
+            for (source, target) in moves[..i].iter().rev() {
+                fs::rename(target, source)?;
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process;
+    use std::collections::HashMap;
+    use crate::index::{Entry, Index};
+    use super::Move;
+
+    fn plan(sources: &[&str], destination: &str) -> Result<(Vec<String>, Vec<Move>), String> {
+        // a.txt, b.txt and dir/c.txt are tracked, untracked.txt is not, dir and other are directories
+        let index = Index::new(["a.txt", "b.txt", "dir/c.txt"].iter()
+                        .map(|name| Entry::from_stage(vec![1; 20], name, 100644, 0))
+                        .collect(), vec![]);
+        let paths = vec![("a.txt", false), ("b.txt", false), ("untracked.txt", false), ("dir", true), ("dir/c.txt", false), ("other", true)].into_iter()
+                        .collect::<HashMap<&str, bool>>();
+        let stat = |path: &str| paths.get(path).copied();
+        let is_dir = stat(destination.trim_end_matches('/')) == Some(true);
+        let (index, moves) = super::plan_moves(index, sources, destination, is_dir, &stat).map_err(|e| e.to_string())?;
+        Ok((index.entries.iter().map(|e| e.name.clone()).collect(), moves))
+    }
+
+    fn moves(list: &[(&str, &str)]) -> Vec<Move> {
+        list.iter().map(|(s, t)| (String::from(*s), String::from(*t))).collect()
+    }
+
+    #[test]
+    fn test_plan_moves() {
+        let (names, planned) = plan(&["a.txt"], "new.txt").unwrap();
+        assert_eq!(names, vec!["b.txt", "dir/c.txt", "new.txt"]);
+        assert_eq!(planned, moves(&[("a.txt", "new.txt")]));
+        // an existing directory takes the source by its name
+        let (names, planned) = plan(&["a.txt"], "other/").unwrap();
+        assert_eq!(names, vec!["b.txt", "dir/c.txt", "other/a.txt"]);
+        assert_eq!(planned, moves(&[("a.txt", "other/a.txt")]));
+        let (names, planned) = plan(&["a.txt", "dir/"], "other").unwrap();
+        assert_eq!(names, vec!["b.txt", "other/a.txt", "other/dir/c.txt"]);
+        assert_eq!(planned, moves(&[("a.txt", "other/a.txt"), ("dir", "other/dir")]));
+    }
+    #[test]
+    fn test_plan_moves_refused() {
+        assert_eq!(plan(&["a.txt", "b.txt"], "new"), Err(String::from("destination 'new' is not a directory")));
+        assert_eq!(plan(&["missing.txt"], "new.txt"), Err(String::from("bad source, source=missing.txt, destination=new.txt")));
+        assert_eq!(plan(&["untracked.txt"], "new.txt"), Err(String::from("not under version control, source=untracked.txt, destination=new.txt")));
+        assert_eq!(plan(&["a.txt"], "b.txt"), Err(String::from("destination exists, source=a.txt, destination=b.txt")));
+        assert_eq!(plan(&["dir"], "dir/sub"), Err(String::from("can not move directory into itself, source=dir, destination=dir/sub")));
+    }
+    #[test]
+    fn test_move_files_rollback() {
+        let dir = std::env::temp_dir().join(format!("rusgit-mv-{}", process::id()));
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(path("a.txt"), "a\n").unwrap();
+        fs::write(path("b.txt"), "b\n").unwrap();
+        // the second source is missing, so the first move is undone
+        let planned = vec![(path("a.txt"), path("sub/a.txt")), (path("missing.txt"), path("sub/missing.txt"))];
+        assert!(super::move_files(&planned).is_err());
+        assert_eq!(fs::read_to_string(path("a.txt")).unwrap(), "a\n");
+        assert!(!dir.join("sub/a.txt").exists());
+
+        let planned = vec![(path("a.txt"), path("sub/a.txt")), (path("b.txt"), path("sub/b.txt"))];
+        super::move_files(&planned).unwrap();
+        assert_eq!(fs::read_to_string(path("sub/b.txt")).unwrap(), "b\n");
+        assert!(!dir.join("a.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
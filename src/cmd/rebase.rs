@@ -0,0 +1,669 @@
+use std::io;
+use std::fs;
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use crate::refs;
+use crate::merge;
+use crate::graph;
+use crate::graph::RevWalk;
+use crate::index;
+use crate::worktree;
+use crate::config::Config;
+use crate::object::Object;
+use crate::object::tree;
+use crate::object::commit::Commit;
+use crate::cmd::commit;
+use crate::cmd::{GIT_INDEX, GIT_REBASE_MERGE_DIR, GIT_COMMIT_EDITMSG};
+
+// files in .git/rebase-merge
+const HEAD_NAME: &str = "head-name";
+const ONTO: &str = "onto";
+const ORIG_HEAD: &str = "orig-head";
+const TODO: &str = "git-rebase-todo";
+const DONE: &str = "done";
+const INTERACTIVE: &str = "interactive";
+const STOPPED_SHA: &str = "stopped-sha";
+const AMEND: &str = "amend";
+const MESSAGE_SQUASH: &str = "message-squash";
+
+const TODO_HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\", but discard this commit's log message
+# x, exec <command> = run command (the rest of the line) using shell
+# b, break = stop here (continue rebase later with 'rusgit rebase --continue')
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+#
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Pick,
+    Reword,
+    Edit,
+    Squash,
+    Fixup,
+    Drop,
+    Exec,
+    Break,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    action: Action,
+    // the commit for pick-like actions, the command line for exec
+    arg: String,
+}
+
+pub enum RebaseOption {
+    Start { upstream: String, interactive: bool, autosquash: bool },
+    Continue,
+    Skip,
+    Abort,
+}
+
+pub fn rebase(option: RebaseOption) -> io::Result<bool> {
+    // false when the rebase stopped at a conflict or a failed exec
+    let in_progress = Path::new(GIT_REBASE_MERGE_DIR).exists();
+    match option {
+        RebaseOption::Start { .. } if in_progress => {
+            Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("It seems that there is already a {} directory", GIT_REBASE_MERGE_DIR)))
+        },
+        RebaseOption::Start { upstream, interactive, autosquash } => start(&upstream, interactive, autosquash),
+        _ if !in_progress => Err(io::Error::new(io::ErrorKind::NotFound, "No rebase in progress?")),
+        RebaseOption::Continue => resume(),
+        RebaseOption::Skip => skip(),
+        RebaseOption::Abort => abort().map(|_| true),
+    }
+}
+
+fn start(upstream: &str, interactive: bool, autosquash: bool) -> io::Result<bool> {
+    let lock = index::lock_index(GIT_INDEX)?;
+    let branch = refs::read_head_branch()
+        .or(Err(io::Error::new(io::ErrorKind::InvalidInput, "rebasing a detached HEAD is not supported")))?;
+    let head = refs::rev_parse("HEAD")?;
+    let onto = refs::rev_parse(upstream)?;
+    let head_commit = graph::read_commit(&head)?;
+    if !worktree::is_clean(&head_commit.tree)? {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot rebase: You have unstaged or uncommitted changes."));
+    }
+    if !interactive && graph::is_ancestor(&onto, &head)? {
+        println!("Current branch {} is up to date.", branch);
+        return Ok(true);
+    }
+
+    // commits on our side only, oldest first, merges are dropped like git does
+    let upstream_commits = graph::reachable(&onto)?;
+    let mut walk = RevWalk::new();
+    walk.push(&head)?;
+    let mut commits: Vec<(String, Commit)> = Vec::new();
+    for node in walk {
+        let node = node?;
+        if !upstream_commits.contains(&node.hash) && node.commit.parents.len() <= 1 {
+            commits.push((node.hash, node.commit));
+        }
+    }
+    commits.reverse();
+    let mut steps = commits.iter()
+        .map(|(hash, _)| Step { action: Action::Pick, arg: hash.clone() })
+        .collect::<Vec<Step>>();
+    if autosquash {
+        steps = autosquash_steps(steps, &commits);
+    }
+    check_steps(&steps)?;
+
+    fs::create_dir_all(GIT_REBASE_MERGE_DIR)?;
+    write_state(HEAD_NAME, &format!("refs/heads/{}\n", branch))?;
+    write_state(ONTO, &format!("{}\n", onto))?;
+    write_state(ORIG_HEAD, &format!("{}\n", head))?;
+    write_state(DONE, "")?;
+    write_steps(&steps)?;
+    let lock = if interactive {
+        // the todo list is edited without the lock, like git
+        drop(lock);
+        write_state(INTERACTIVE, "")?;
+        let todo = format!("{}{}", read_state(TODO)?, TODO_HELP);
+        write_state(TODO, &todo)?;
+        if let Err(e) = run_editor(&state_path(TODO), true) {
+            fs::remove_dir_all(GIT_REBASE_MERGE_DIR)?;
+            return Err(e);
+        }
+        let steps = read_steps()?;
+        if steps.is_empty() {
+            fs::remove_dir_all(GIT_REBASE_MERGE_DIR)?;
+            println!("Nothing to do");
+            return Ok(true);
+        }
+        if let Err(e) = check_steps(&steps) {
+            fs::remove_dir_all(GIT_REBASE_MERGE_DIR)?;
+            return Err(e);
+        }
+        write_steps(&steps)?;
+        index::lock_index(GIT_INDEX)?
+    } else {
+        lock
+    };
+
+    // detach at the new base and replay from there
+    worktree::checkout_tree(&graph::read_commit(&onto)?.tree)?;
+    refs::detach_head(&onto)?;
+    lock.commit()?;
+    run()
+}
+
+fn check_steps(steps: &[Step]) -> io::Result<()> {
+    // a fixup or squash goes into a commit of this rebase, never into the one it starts from
+    let mut picked = false;
+    for step in steps {
+        match step.action {
+            Action::Squash | Action::Fixup if !picked => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("cannot '{}' without a previous commit", action_name(step.action))));
+            },
+            Action::Pick | Action::Reword | Action::Edit => picked = true,
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+fn autosquash_steps(steps: Vec<Step>, commits: &[(String, Commit)]) -> Vec<Step> {
+    // move "fixup! <subject>" and "squash! <subject>" right after the earlier commit they name
+    let mut result: Vec<Step> = Vec::new();
+    for step in steps {
+        let subject = subject_of(commits, &step.arg);
+        let (action, target) = match (subject.strip_prefix("fixup! "), subject.strip_prefix("squash! ")) {
+            (Some(target), _) => (Action::Fixup, target),
+            (_, Some(target)) => (Action::Squash, target),
+            _ => {
+                result.push(step);
+                continue;
+            },
+        };
+        let target = target.trim_start_matches("fixup! ").trim_start_matches("squash! ");
+        let found = result.iter().position(|s| subject_of(commits, &s.arg) == target)
+            .or_else(|| result.iter().position(|s| target.len() >= 4 && s.arg.starts_with(target)))
+            .or_else(|| result.iter().position(|s| subject_of(commits, &s.arg).starts_with(target)));
+        match found {
+            Some(pos) => {
+                // after the fixups already placed behind the target
+                let mut end = pos + 1;
+                while end < result.len() && matches!(result[end].action, Action::Fixup | Action::Squash) {
+                    end += 1;
+                }
+                result.insert(end, Step { action, arg: step.arg });
+            },
+            None => result.push(step),
+        }
+    }
+    result
+}
+
+fn subject_of(commits: &[(String, Commit)], hash: &str) -> String {
+    commits.iter()
+        .find(|(h, _)| h == hash)
+        .map(|(_, c)| String::from(c.message.lines().next().unwrap_or("")))
+        .unwrap_or_default()
+}
+
+fn run() -> io::Result<bool> {
+    loop {
+        let mut steps = read_steps()?;
+        if steps.is_empty() {
+            return finish().map(|_| true);
+        }
+        let step = steps.remove(0);
+        write_steps(&steps)?;
+        let mut done = read_state(DONE)?;
+        done.push_str(&format!("{}\n", step_line(&step)?));
+        write_state(DONE, &done)?;
+        let next = steps.first().map(|s| s.action);
+
+        match step.action {
+            Action::Drop => continue,
+            Action::Break => {
+                println!("Stopped at {}", &refs::rev_parse("HEAD")?[..7]);
+                return Ok(true);
+            },
+            Action::Exec => {
+                println!("Executing: {}", step.arg);
+                let status = Command::new("sh").arg("-c").arg(&step.arg).status()?;
+                if !status.success() {
+                    println!("warning: execution failed: {}", step.arg);
+                    println!("You can fix the problem, and then run");
+                    println!();
+                    println!("  rusgit rebase --continue");
+                    return Ok(false);
+                }
+                continue;
+            },
+            _ => {},
+        }
+
+        // the lock is held step by step, an exec or the editor may run git on this repository
+        if !index::with_lock(GIT_INDEX, || apply_step(&step, next))? {
+            // a conflict leaves stopped-sha behind, an edit does not
+            return Ok(!Path::new(&state_path(STOPPED_SHA)).exists());
+        }
+    }
+}
+
+fn apply_step(step: &Step, next: Option<Action>) -> io::Result<bool> {
+    // false when the rebase stops at this step
+    let commit = graph::read_commit(&step.arg)?;
+    let head = refs::rev_parse("HEAD")?;
+    let fast_forward = matches!(step.action, Action::Pick | Action::Reword | Action::Edit)
+        && commit.parent() == Some(head.as_str());
+    if fast_forward {
+        worktree::checkout_tree(&commit.tree)?;
+        refs::detach_head(&step.arg)?;
+    } else {
+        let base = match commit.parent() {
+            Some(parent) => Some(graph::read_commit(parent)?.tree),
+            None => None,
+        };
+        let label = format!("{} ({})", &step.arg[..7], subject(&commit));
+        let ours = graph::read_commit(&head)?.tree;
+        let entries = merge::apply_change(base.as_deref(), &ours, &commit.tree, &label)?;
+        for message in merge::describe(&entries) {
+            println!("{}", message);
+        }
+        if entries.iter().any(|e| !e.is_clean()) {
+            write_state(STOPPED_SHA, &format!("{}\n", step.arg))?;
+            println!("error: could not apply {}... {}", &step.arg[..7], subject(&commit));
+            println!("hint: Resolve all conflicts manually, mark them as resolved with");
+            println!("hint: \"rusgit add <conflicted_files>\", then run \"rusgit rebase --continue\".");
+            println!("hint: You can instead skip this commit: run \"rusgit rebase --skip\".");
+            println!("hint: To abort and get back to the state before \"rusgit rebase\", run \"rusgit rebase --abort\".");
+            return Ok(false);
+        }
+        commit_step(step, &commit, next)?;
+    }
+    if fast_forward && step.action == Action::Reword {
+        amend_head(&edit_message(&commit.message)?)?;
+    }
+    if step.action == Action::Edit {
+        let head = refs::rev_parse("HEAD")?;
+        write_state(AMEND, &format!("{}\n", head))?;
+        println!("Stopped at {}...  {}", &step.arg[..7], subject(&commit));
+        println!("You can amend the commit now, staged changes are folded into it by");
+        println!();
+        println!("  rusgit rebase --continue");
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+fn commit_step(step: &Step, commit: &Commit, next: Option<Action>) -> io::Result<()> {
+    // record the applied change according to the todo action
+    let head = graph::read_commit(&refs::rev_parse("HEAD")?)?;
+    match step.action {
+        Action::Squash | Action::Fixup => {
+            let message = match step.action {
+                Action::Squash => {
+                    write_state(MESSAGE_SQUASH, "")?;
+                    // the "squash! <subject>" line itself is left out like git does
+                    let body = match commit.message.strip_prefix("squash! ") {
+                        Some(rest) => rest.split_once('\n').map(|(_, body)| body.trim_start()).unwrap_or(""),
+                        None => commit.message.as_str(),
+                    };
+                    if body.is_empty() {
+                        head.message.clone()
+                    } else {
+                        format!("{}\n\n{}", head.message, body)
+                    }
+                },
+                _ => head.message.clone(),
+            };
+            // the editor is shown once, for the last squash of a chain
+            let last = !matches!(next, Some(Action::Squash) | Some(Action::Fixup));
+            let message = if last && Path::new(&state_path(MESSAGE_SQUASH)).exists() {
+                fs::remove_file(state_path(MESSAGE_SQUASH))?;
+                edit_message(&message)?
+            } else {
+                message
+            };
+            amend_head(&message)
+        },
+        Action::Reword => {
+            let message = edit_message(&commit.message)?;
+            let head_hash = refs::rev_parse("HEAD")?;
+            commit::commit_index(&message, vec![&head_hash], Some(commit.author.clone())).map(|_| ())
+        },
+        _ => {
+            let head_hash = refs::rev_parse("HEAD")?;
+            commit::commit_index(&commit.message, vec![&head_hash], Some(commit.author.clone())).map(|_| ())
+        },
+    }
+}
+
+fn amend_head(message: &str) -> io::Result<()> {
+    // replace HEAD with a commit of the index, keeping its parents and author
+    let head = graph::read_commit(&refs::rev_parse("HEAD")?)?;
+    let parents = head.parents.iter().map(|p| p.as_str()).collect::<Vec<&str>>();
+    commit::commit_index(message, parents, Some(head.author.clone())).map(|_| ())
+}
+
+fn resume() -> io::Result<bool> {
+    let mut steps = Vec::new();
+    for line in read_state(DONE)?.lines() {
+        steps.extend(parse_step(line)?);
+    }
+    steps.extend(read_steps()?);
+    check_steps(&steps).map_err(|e| io::Error::new(e.kind(), format!("{}\nYou can fix this by editing {} and then run 'rusgit rebase --continue'.\nOr you can abort the rebase with 'rusgit rebase --abort'.", e, state_path(TODO))))?;
+    if !index::with_lock(GIT_INDEX, resume_step)? {
+        return Ok(true);
+    }
+    run()
+}
+
+fn resume_step() -> io::Result<bool> {
+    // false when the rebase stays stopped at an edit
+    let index = index::read_index(GIT_INDEX)?;
+    if !index.unmerged().is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "you must edit all merge conflicts and then mark them as resolved using rusgit add"));
+    }
+    let head = refs::rev_parse("HEAD")?;
+    let head_commit = graph::read_commit(&head)?;
+    let staged = hex::encode(Object::Tree(tree::write_tree()?).calc_hash());
+    if let Ok(amend) = read_state(AMEND) {
+        // stopped by edit, fold staged changes into the commit unless one was made since
+        if amend.trim() == head && staged != head_commit.tree {
+            amend_head(&head_commit.message)?;
+        }
+        fs::remove_file(state_path(AMEND))?;
+    } else if let Ok(stopped) = read_state(STOPPED_SHA) {
+        // stopped by a conflict, commit the resolution as the todo line asked
+        let stopped = String::from(stopped.trim());
+        let commit = graph::read_commit(&stopped)?;
+        let action = read_state(DONE)?.lines().last()
+            .and_then(|l| parse_step(l).ok().flatten())
+            .map(|s| s.action)
+            .unwrap_or(Action::Pick);
+        let next = read_steps()?.first().map(|s| s.action);
+        if staged != head_commit.tree {
+            commit_step(&Step { action, arg: stopped.clone() }, &commit, next)?;
+        }
+        fs::remove_file(state_path(STOPPED_SHA))?;
+        if action == Action::Edit {
+            write_state(AMEND, &format!("{}\n", refs::rev_parse("HEAD")?))?;
+            println!("Stopped at {}...  {}", &stopped[..7], subject(&commit));
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn skip() -> io::Result<bool> {
+    index::with_lock(GIT_INDEX, || {
+        let head = graph::read_commit(&refs::rev_parse("HEAD")?)?;
+        worktree::checkout_tree(&head.tree)
+    })?;
+    for name in [STOPPED_SHA, AMEND].iter() {
+        let _ = fs::remove_file(state_path(name));
+    }
+    run()
+}
+
+fn abort() -> io::Result<()> {
+    index::with_lock(GIT_INDEX, || {
+        let orig_head = String::from(read_state(ORIG_HEAD)?.trim());
+        let head_name = String::from(read_state(HEAD_NAME)?.trim());
+        worktree::checkout_tree(&graph::read_commit(&orig_head)?.tree)?;
+        restore_head(&head_name, &orig_head)?;
+        fs::remove_dir_all(GIT_REBASE_MERGE_DIR)
+    })
+}
+
+fn finish() -> io::Result<()> {
+    let head = refs::rev_parse("HEAD")?;
+    let head_name = String::from(read_state(HEAD_NAME)?.trim());
+    restore_head(&head_name, &head)?;
+    fs::remove_dir_all(GIT_REBASE_MERGE_DIR)?;
+    println!("Successfully rebased and updated {}.", head_name);
+    Ok(())
+}
+
+fn restore_head(head_name: &str, hash: &str) -> io::Result<()> {
+    let branch = head_name.strip_prefix("refs/heads/").unwrap_or(head_name);
+    let ref_path = refs::update_head(branch)?;
+    refs::update_ref(&ref_path, hash)
+}
+
+fn subject(commit: &Commit) -> &str {
+    commit.message.lines().next().unwrap_or("")
+}
+
+fn action_name(action: Action) -> &'static str {
+    match action {
+        Action::Pick => "pick",
+        Action::Reword => "reword",
+        Action::Edit => "edit",
+        Action::Squash => "squash",
+        Action::Fixup => "fixup",
+        Action::Drop => "drop",
+        Action::Exec => "exec",
+        Action::Break => "break",
+    }
+}
+
+fn step_line(step: &Step) -> io::Result<String> {
+    let subject = match step.action {
+        Action::Exec | Action::Break => String::new(),
+        _ => String::from(subject(&graph::read_commit(&step.arg)?)),
+    };
+    Ok(format_step(step, &subject))
+}
+
+fn format_step(step: &Step, subject: &str) -> String {
+    match step.action {
+        Action::Exec => format!("exec {}", step.arg),
+        Action::Break => String::from("break"),
+        action => format!("{} {} {}", action_name(action), &step.arg[..7], subject),
+    }
+}
+
+fn parse_step(line: &str) -> io::Result<Option<Step>> {
+    match parse_line(line)? {
+        Some(step) if step.action != Action::Exec && step.action != Action::Break => {
+            Ok(Some(Step { arg: refs::rev_parse(&step.arg)?, ..step }))
+        },
+        step => Ok(step),
+    }
+}
+
+fn parse_line(line: &str) -> io::Result<Option<Step>> {
+    // the commit is left as written, parse_step resolves it
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (command, rest) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+    let action = match command {
+        "p" | "pick" => Action::Pick,
+        "r" | "reword" => Action::Reword,
+        "e" | "edit" => Action::Edit,
+        "s" | "squash" => Action::Squash,
+        "f" | "fixup" => Action::Fixup,
+        "d" | "drop" => Action::Drop,
+        "x" | "exec" => return Ok(Some(Step { action: Action::Exec, arg: String::from(rest) })),
+        "b" | "break" => return Ok(Some(Step { action: Action::Break, arg: String::new() })),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid line in todo list: {}", line))),
+    };
+    let commit = rest.split_whitespace().next()
+        .ok_or(io::Error::new(io::ErrorKind::InvalidInput, format!("missing commit in todo list: {}", line)))?;
+    Ok(Some(Step { action, arg: String::from(commit) }))
+}
+
+fn read_steps() -> io::Result<Vec<Step>> {
+    let mut steps: Vec<Step> = Vec::new();
+    for line in read_state(TODO)?.lines() {
+        if let Some(step) = parse_step(line)? {
+            steps.push(step);
+        }
+    }
+    Ok(steps)
+}
+
+fn write_steps(steps: &[Step]) -> io::Result<()> {
+    let lines = steps.iter()
+        .map(|s| step_line(s).map(|l| format!("{}\n", l)))
+        .collect::<io::Result<String>>()?;
+    write_state(TODO, &lines)
+}
+
+fn state_path(name: &str) -> String {
+    format!("{}/{}", GIT_REBASE_MERGE_DIR, name)
+}
+
+fn read_state(name: &str) -> io::Result<String> {
+    fs::read_to_string(state_path(name))
+}
+
+fn write_state(name: &str, content: &str) -> io::Result<()> {
+    fs::write(state_path(name), content)
+}
+
+fn edit_message(message: &str) -> io::Result<String> {
+    // lines starting with # are dropped like git's default cleanup
+    fs::write(GIT_COMMIT_EDITMSG, format!("{}\n", message))?;
+    run_editor(GIT_COMMIT_EDITMSG, false)?;
+    let edited = fs::read_to_string(GIT_COMMIT_EDITMSG)?;
+    let message = edited.lines()
+        .filter(|l| !l.starts_with('#'))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    let message = message.trim();
+    if message.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Aborting commit due to empty commit message."));
+    }
+    Ok(String::from(message))
+}
+
+fn run_editor(path: &str, sequence: bool) -> io::Result<()> {
+    // $GIT_SEQUENCE_EDITOR for todo lists, then the usual git editor lookup order
+    let config = Config::read_config()?;
+    let mut candidates: Vec<Option<String>> = Vec::new();
+    if sequence {
+        candidates.push(env::var("GIT_SEQUENCE_EDITOR").ok());
+        candidates.push(config.get("sequence.editor").map(String::from));
+    }
+    candidates.push(env::var("GIT_EDITOR").ok());
+    candidates.push(config.get("core.editor").map(String::from));
+    candidates.push(env::var("VISUAL").ok());
+    candidates.push(env::var("EDITOR").ok());
+    let editor = candidates.into_iter().flatten()
+        .find(|e| !e.is_empty())
+        .unwrap_or_else(|| String::from("vi"));
+    if editor == ":" {
+        return Ok(());
+    }
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("There was a problem with the editor '{}'.", editor)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Step};
+    use crate::object::commit::{Commit, User};
+
+    fn commits(subjects: &[&str]) -> Vec<(String, Commit)> {
+        subjects.iter().enumerate().map(|(i, subject)| {
+            let user = User::now("a", "a@b");
+            (format!("{:x}{}", i + 10, "0".repeat(39)), Commit::new("", Vec::new(), user.clone(), user, &format!("{}\n", subject)))
+        }).collect()
+    }
+
+    fn autosquash(subjects: &[&str]) -> Vec<(Action, String)> {
+        let commits = commits(subjects);
+        let steps = commits.iter().map(|(hash, _)| Step { action: Action::Pick, arg: hash.clone() }).collect();
+        super::autosquash_steps(steps, &commits).into_iter()
+            .map(|step| (step.action, super::subject_of(&commits, &step.arg)))
+            .collect()
+    }
+
+    fn step(action: Action) -> Step {
+        Step { action, arg: "a".repeat(40) }
+    }
+
+    #[test]
+    fn test_autosquash_steps() {
+        assert_eq!(autosquash(&["a", "b", "fixup! a", "squash! b"]), vec![
+            (Action::Pick, String::from("a")),
+            (Action::Fixup, String::from("fixup! a")),
+            (Action::Pick, String::from("b")),
+            (Action::Squash, String::from("squash! b")),
+        ]);
+        // several fixups for one commit keep their order behind it
+        assert_eq!(autosquash(&["a", "b", "fixup! a", "squash! a", "fixup! fixup! a"]), vec![
+            (Action::Pick, String::from("a")),
+            (Action::Fixup, String::from("fixup! a")),
+            (Action::Squash, String::from("squash! a")),
+            (Action::Fixup, String::from("fixup! fixup! a")),
+            (Action::Pick, String::from("b")),
+        ]);
+        // a hash prefix names the commit as well, a target that is not in the list stays put
+        assert_eq!(autosquash(&["a", "b", "fixup! a000", "fixup! c"]), vec![
+            (Action::Pick, String::from("a")),
+            (Action::Fixup, String::from("fixup! a000")),
+            (Action::Pick, String::from("b")),
+            (Action::Pick, String::from("fixup! c")),
+        ]);
+        // only an earlier commit is a target
+        assert_eq!(autosquash(&["fixup! a", "a"]), vec![
+            (Action::Pick, String::from("fixup! a")),
+            (Action::Pick, String::from("a")),
+        ]);
+    }
+    #[test]
+    fn test_check_steps() {
+        assert!(super::check_steps(&[step(Action::Pick), step(Action::Fixup), step(Action::Squash)]).is_ok());
+        assert!(super::check_steps(&[step(Action::Exec), step(Action::Reword), step(Action::Fixup)]).is_ok());
+        let e = super::check_steps(&[step(Action::Fixup), step(Action::Pick)]).unwrap_err();
+        assert_eq!(e.to_string(), "cannot 'fixup' without a previous commit");
+        let e = super::check_steps(&[step(Action::Drop), step(Action::Break), step(Action::Squash)]).unwrap_err();
+        assert_eq!(e.to_string(), "cannot 'squash' without a previous commit");
+    }
+    #[test]
+    fn test_parse_line() {
+        let hash = "0123456789abcdef0123456789abcdef01234567";
+        for action in [Action::Pick, Action::Reword, Action::Edit, Action::Squash, Action::Fixup, Action::Drop].iter() {
+            let line = super::format_step(&Step { action: *action, arg: String::from(hash) }, "a subject");
+            assert_eq!(line, format!("{} 0123456 a subject", super::action_name(*action)));
+            let step = super::parse_line(&line).unwrap().unwrap();
+            assert_eq!((step.action, step.arg.as_str()), (*action, "0123456"));
+        }
+        for line in ["exec make test", "break"].iter() {
+            let step = super::parse_line(line).unwrap().unwrap();
+            assert_eq!(&super::format_step(&step, ""), line);
+        }
+        let step = super::parse_line("  f 0123456 fixup! x").unwrap().unwrap();
+        assert_eq!((step.action, step.arg.as_str()), (Action::Fixup, "0123456"));
+        let step = super::parse_line("x echo  two  spaces ").unwrap().unwrap();
+        assert_eq!((step.action, step.arg.as_str()), (Action::Exec, "echo  two  spaces"));
+        assert!(super::parse_line("# a comment").unwrap().is_none());
+        assert!(super::parse_line("   ").unwrap().is_none());
+        assert!(super::parse_line("pick").is_err());
+        assert!(super::parse_line("squish 0123456").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::io;
+use std::collections::{BTreeSet, HashMap};
+use crate::refs;
+use crate::refs::RefTransaction;
+use crate::merge;
+use crate::merge::Version;
+use crate::graph;
+use crate::index;
+use crate::index::{Entry, Index};
+use crate::worktree;
+use crate::object::tree::Tree;
+use crate::cmd::cat_file::hash_key_to_path;
+use crate::cmd::{GIT_INDEX, GIT_ORIG_HEAD, GIT_MERGE_HEAD, GIT_CHERRY_PICK_HEAD, GIT_REVERT_HEAD};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetMode {
+    // move the branch only
+    Soft,
+    // move the branch and the index
+    Mixed,
+    // move the branch, the index and the working tree
+    Hard,
+    // like hard, but keep local changes to files the reset does not touch
+    Keep,
+}
+
+impl ResetMode {
+    fn name(&self) -> &'static str {
+        match self {
+            ResetMode::Soft => "soft",
+            ResetMode::Mixed => "mixed",
+            ResetMode::Hard => "hard",
+            ResetMode::Keep => "keep",
+        }
+    }
+}
+
+pub fn reset(commit: Option<&str>, mode: ResetMode, paths: Vec<&str>) -> io::Result<()> {
+    index::with_lock(GIT_INDEX, || reset_to(commit, mode, paths))
+}
+
+fn reset_to(commit: Option<&str>, mode: ResetMode, paths: Vec<&str>) -> io::Result<()> {
+    // reset <path> is reset HEAD -- <path> when <path> is not a revision
+    if let Some(name) = commit {
+        if paths.is_empty() && refs::rev_parse(name).is_err() && std::path::Path::new(name).exists() {
+            return reset(None, mode, vec![name]);
+        }
+    }
+    let rev = commit.unwrap_or("HEAD");
+    // an unborn branch resets to the empty tree
+    let target = match refs::rev_parse(rev) {
+        Ok(hash) => Some(hash),
+        Err(_) if commit.is_none() => None,
+        Err(e) => return Err(e),
+    };
+    let tree = match &target {
+        Some(hash) => Some(graph::read_commit(hash)?.tree),
+        None => None,
+    };
+    if !paths.is_empty() {
+        if mode != ResetMode::Mixed {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Cannot do {} reset with paths.", mode.name())));
+        }
+        reset_paths(tree.as_deref(), &paths)?;
+        return print_unstaged();
+    }
+    let target = target.ok_or(io::Error::new(io::ErrorKind::NotFound, format!("unknown revision '{}'", rev)))?;
+    let tree = tree.unwrap_or_default();
+    if mode == ResetMode::Soft && std::path::Path::new(GIT_MERGE_HEAD).exists() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot do a soft reset in the middle of a merge."));
+    }
+
+    let head = refs::rev_parse("HEAD").ok();
+    match mode {
+        ResetMode::Soft => {},
+        ResetMode::Mixed => reset_index(&tree)?,
+        ResetMode::Hard => worktree::checkout_tree(&tree)?,
+        ResetMode::Keep => {
+            let head_tree = match &head {
+                Some(head) => Some(graph::read_commit(head)?.tree),
+                None => None,
+            };
+            keep_tree(head_tree.as_deref(), &tree).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}\nCould not reset index file to revision '{}'.", e, rev))
+            })?;
+        },
+    }
+
+    let mut transaction = RefTransaction::new();
+    if let Some(head) = &head {
+        transaction.update(GIT_ORIG_HEAD, head, None);
+    }
+    transaction.update(&refs::read_head()?, &target, head.as_deref());
+    transaction.commit()?;
+    // whatever was in progress is abandoned
+    merge::clear_merge_state()?;
+    for path in [GIT_CHERRY_PICK_HEAD, GIT_REVERT_HEAD].iter() {
+        let _ = std::fs::remove_file(path);
+    }
+
+    match mode {
+        ResetMode::Hard => {
+            let commit = graph::read_commit(&target)?;
+            println!("HEAD is now at {} {}", &target[..7], commit.message.lines().next().unwrap_or(""));
+        },
+        ResetMode::Mixed => print_unstaged()?,
+        _ => {},
+    }
+    Ok(())
+}
+
+fn read_tree_index(tree: Option<&str>) -> io::Result<Index> {
+    match tree {
+        Some(tree) => Tree::from_hash_file(&hash_key_to_path(tree))?.to_index(),
+        None => Ok(Index::new(Vec::new(), Vec::new())),
+    }
+}
+
+fn reset_index(tree: &str) -> io::Result<()> {
+    let old = index::read_index(GIT_INDEX)?;
+    let Index { mut entries, tree_entries, .. } = read_tree_index(Some(tree))?;
+    index::keep_stat(&old, &mut entries);
+    index::write_index(GIT_INDEX, &Index { entries, tree_entries, ..old })
+}
+
+fn reset_paths(tree: Option<&str>, paths: &[&str]) -> io::Result<()> {
+    let old = index::read_index(GIT_INDEX)?;
+    let entries = read_tree_index(tree)?.entries;
+    index::write_index(GIT_INDEX, &reset_entries(old, entries, paths))
+}
+
+fn reset_entries(old: Index, tree_entries: Vec<Entry>, paths: &[&str]) -> Index {
+    // only the matching entries are taken from the tree, the rest of the index stays,
+    // and a matching path the tree does not have leaves the index
+    let mut entries = tree_entries.into_iter()
+                        .filter(|e| index::match_pathspec(&e.name, paths))
+                        .collect::<Vec<Entry>>();
+    index::keep_stat(&old, &mut entries);
+    let names = entries.iter().chain(old.entries.iter())
+                    .filter(|e| index::match_pathspec(&e.name, paths))
+                    .map(|e| e.name.clone())
+                    .collect::<BTreeSet<String>>();
+    index::replace_paths(old, &names, entries)
+}
+
+fn keep_tree(head_tree: Option<&str>, tree: &str) -> io::Result<()> {
+    let head_files = merge::read_tree_files(head_tree)?;
+    let files = merge::read_tree_files(Some(tree))?;
+    let mut index = index::read_index(GIT_INDEX)?;
+    let exists = |name: &str| std::path::Path::new(name).exists();
+    let changed = keep_changes(&head_files, &files, &index, &worktree::file_hash, &exists)?;
+    let changes = changed.iter().map(|name| (name.as_str(), files.get(name))).collect::<Vec<(&str, Option<&Version>)>>();
+    worktree::update_files(&changes)?;
+    index.entries.retain(|e| !changed.contains(&e.name));
+    for (name, version) in changes.iter() {
+        if let Some(version) = version {
+            let mut entry = Entry::from_name(version.hash.clone(), name)?;
+            entry.mode = version.mode;
+            index.entries.push(entry);
+        }
+    }
+    index.entries.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()).then(a.stage.cmp(&b.stage)));
+    index.invalidate_trees(&changed);
+    index::write_index(GIT_INDEX, &index)
+}
+
+fn keep_changes(
+    head_files: &HashMap<String, Version>,
+    files: &HashMap<String, Version>,
+    index: &Index,
+    file_hash: &dyn Fn(&str) -> Option<Vec<u8>>,
+    exists: &dyn Fn(&str) -> bool,
+) -> io::Result<Vec<String>> {
+    // the paths that differ between the two commits, each has to be clean to be touched;
+    // local changes to the other paths are kept
+    let mut changed = head_files.keys().chain(files.keys())
+                        .filter(|name| head_files.get(*name) != files.get(*name))
+                        .cloned()
+                        .collect::<Vec<String>>();
+    changed.sort();
+    changed.dedup();
+    for name in changed.iter() {
+        let staged = index.entries.iter().find(|e| &e.name == name);
+        let unmerged = staged.is_some_and(|e| e.stage != 0);
+        let staged_hash = staged.map(|e| &e.hash);
+        if unmerged || staged_hash != head_files.get(name).map(|v| &v.hash) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Entry '{}' would be overwritten by merge. Cannot merge.", name)));
+        }
+        if staged.is_some() && file_hash(name).as_ref() != staged_hash {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Entry '{}' not uptodate. Cannot merge.", name)));
+        }
+        if staged.is_none() && files.contains_key(name) && exists(name) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("Untracked working tree file '{}' would be overwritten by merge.", name)));
+        }
+    }
+    Ok(changed)
+}
+
+fn print_unstaged() -> io::Result<()> {
+    let index = index::read_index(GIT_INDEX)?;
+    let mut lines: Vec<String> = Vec::new();
+    for entry in index.entries.iter() {
+        if entry.stage != 0 {
+            let line = format!("U\t{}", entry.name);
+            if lines.last() != Some(&line) {
+                lines.push(line);
+            }
+            continue;
+        }
+        match worktree::file_hash(&entry.name) {
+            None => lines.push(format!("D\t{}", entry.name)),
+            Some(hash) if hash != entry.hash => lines.push(format!("M\t{}", entry.name)),
+            _ => {},
+        }
+    }
+    if !lines.is_empty() {
+        println!("Unstaged changes after reset:");
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::index::{Entry, Index};
+    use crate::merge::Version;
+
+    fn files(list: &[(&str, u8)]) -> HashMap<String, Version> {
+        list.iter().map(|(name, byte)| (String::from(*name), Version::new(100644, &[*byte; 20]))).collect()
+    }
+
+    fn keep(index: &[(&str, u8, u16)], worktree: &[(&str, u8)]) -> Result<Vec<String>, String> {
+        // HEAD has a, b and c, the target changes b, removes c and adds d
+        let head = files(&[("a", 1), ("b", 2), ("c", 3)]);
+        let target = files(&[("a", 1), ("b", 5), ("d", 6)]);
+        let index = Index::new(index.iter().map(|(name, byte, stage)| Entry::from_stage(vec![*byte; 20], name, 100644, *stage)).collect(), vec![]);
+        let worktree = worktree.iter().map(|(name, byte)| (String::from(*name), vec![*byte; 20])).collect::<HashMap<String, Vec<u8>>>();
+        let file_hash = |name: &str| worktree.get(name).cloned();
+        let exists = |name: &str| worktree.contains_key(name);
+        super::keep_changes(&head, &target, &index, &file_hash, &exists).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_keep_changes() {
+        let clean: &[(&str, u8, u16)] = &[("a", 1, 0), ("b", 2, 0), ("c", 3, 0)];
+        assert_eq!(keep(clean, &[("a", 1), ("b", 2), ("c", 3)]), Ok(vec![String::from("b"), String::from("c"), String::from("d")]));
+        // changes to a path both commits agree on are kept, staged or not
+        assert!(keep(clean, &[("a", 9), ("b", 2), ("c", 3)]).is_ok());
+        assert!(keep(&[("a", 9, 0), ("b", 2, 0), ("c", 3, 0), ("new", 7, 0)], &[("a", 9), ("b", 2), ("c", 3), ("new", 7)]).is_ok());
+    }
+    #[test]
+    fn test_keep_changes_refused() {
+        let clean: &[(&str, u8, u16)] = &[("a", 1, 0), ("b", 2, 0), ("c", 3, 0)];
+        assert_eq!(keep(clean, &[("a", 1), ("b", 9), ("c", 3)]), Err(String::from("Entry 'b' not uptodate. Cannot merge.")));
+        // a deleted file counts as a change too
+        assert_eq!(keep(clean, &[("a", 1), ("b", 2)]), Err(String::from("Entry 'c' not uptodate. Cannot merge.")));
+        assert_eq!(keep(&[("a", 1, 0), ("b", 9, 0), ("c", 3, 0)], &[("a", 1), ("b", 9), ("c", 3)]), Err(String::from("Entry 'b' would be overwritten by merge. Cannot merge.")));
+        assert_eq!(keep(&[("a", 1, 0), ("c", 3, 0)], &[("a", 1), ("c", 3)]), Err(String::from("Entry 'b' would be overwritten by merge. Cannot merge.")));
+        assert_eq!(keep(&[("a", 1, 0), ("b", 2, 2), ("b", 4, 3), ("c", 3, 0)], &[("a", 1), ("b", 2), ("c", 3)]), Err(String::from("Entry 'b' would be overwritten by merge. Cannot merge.")));
+        assert_eq!(keep(clean, &[("a", 1), ("b", 2), ("c", 3), ("d", 8)]), Err(String::from("Untracked working tree file 'd' would be overwritten by merge.")));
+    }
+    #[test]
+    fn test_reset_entries() {
+        let old = Index::new(vec![
+            Entry::from_stage(vec![1; 20], "a.txt", 100644, 0),
+            Entry::from_stage(vec![2; 20], "dir/b.txt", 100644, 0),
+            Entry::from_stage(vec![3; 20], "dir/new.txt", 100644, 0),
+            Entry::from_stage(vec![4; 20], "new.txt", 100644, 0),
+        ], vec![]);
+        let tree = vec![
+            Entry::from_stage(vec![5; 20], "a.txt", 100644, 0),
+            Entry::from_stage(vec![6; 20], "dir/b.txt", 100755, 0),
+        ];
+        // new.txt is missing from the target, so its entry goes; a.txt is not named and stays staged
+        let index = super::reset_entries(old.clone(), tree.clone(), &["new.txt", "dir/b.txt"]);
+        let entries = index.entries.iter().map(|e| (e.name.as_str(), e.hash[0], e.mode)).collect::<Vec<_>>();
+        assert_eq!(entries, vec![("a.txt", 1, 100644), ("dir/b.txt", 6, 100755), ("dir/new.txt", 3, 100644)]);
+        // a directory covers the paths below it, the ones the target has and the ones it does not
+        let index = super::reset_entries(old, tree, &["dir"]);
+        let entries = index.entries.iter().map(|e| (e.name.as_str(), e.hash[0])).collect::<Vec<_>>();
+        assert_eq!(entries, vec![("a.txt", 1), ("dir/b.txt", 6), ("new.txt", 4)]);
+    }
+}
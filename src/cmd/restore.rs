@@ -0,0 +1,134 @@
+use std::io;
+use std::collections::{BTreeSet, HashMap};
+use crate::refs;
+use crate::merge;
+use crate::merge::Version;
+use crate::graph;
+use crate::index;
+use crate::index::{Entry, Index};
+use crate::worktree;
+use crate::cmd::GIT_INDEX;
+
+pub fn restore(paths: Vec<&str>, source: Option<&str>, staged: bool, worktree: bool) -> io::Result<()> {
+    index::with_lock(GIT_INDEX, || restore_paths(paths, source, staged, worktree))
+}
+
+fn restore_paths(paths: Vec<&str>, source: Option<&str>, staged: bool, worktree: bool) -> io::Result<()> {
+    // the working tree is restored unless only --staged is given
+    let worktree = worktree || !staged;
+    let source = match source_rev(source, staged) {
+        Some(rev) => Some(read_tree_ish(rev)?),
+        None => None,
+    };
+    let files = match &source {
+        Some(tree) => Some(merge::read_tree_files(Some(tree))?),
+        None => None,
+    };
+    let old = index::read_index(GIT_INDEX)?;
+
+    let mut names = old.entries.iter()
+                    .map(|e| e.name.clone())
+                    .chain(files.iter().flat_map(|f| f.keys().cloned()))
+                    .filter(|name| index::match_pathspec(name, &paths))
+                    .collect::<BTreeSet<String>>();
+    if let Some(path) = paths.iter().find(|p| !names.iter().any(|name| index::match_pathspec(name, &[p]))) {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("pathspec '{}' did not match any file(s) known to git", path)));
+    }
+
+    if worktree {
+        match &files {
+            Some(files) => restore_worktree(&names, files, &old)?,
+            None => {
+                if let Some(entry) = old.entries.iter().find(|e| e.stage != 0 && names.contains(&e.name)) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("path '{}' is unmerged", entry.name)));
+                }
+                let staged = old.entries.iter()
+                                .filter(|e| names.contains(&e.name))
+                                .map(|e| (e.name.clone(), Version::new(e.mode, &e.hash)))
+                                .collect::<HashMap<String, Version>>();
+                // files added since the index was written are left alone
+                names.retain(|name| staged.contains_key(name));
+                restore_worktree(&names, &staged, &old)?;
+            },
+        }
+    }
+    if staged {
+        index::write_index(GIT_INDEX, &restore_index(old, &names, &files.unwrap_or_default()))?;
+    }
+    Ok(())
+}
+
+fn source_rev(source: Option<&str>, staged: bool) -> Option<&str> {
+    // files come from the index, or from HEAD for --staged
+    match source {
+        Some(source) => Some(source),
+        None if staged => Some("HEAD"),
+        None => None,
+    }
+}
+
+fn restore_index(old: Index, names: &BTreeSet<String>, files: &HashMap<String, Version>) -> Index {
+    // the named paths take their version in files, the ones missing from it leave the index
+    let mut entries = names.iter()
+                        .filter_map(|name| files.get(name).map(|v| Entry::from_stage(v.hash.clone(), name, v.mode, 0)))
+                        .collect::<Vec<Entry>>();
+    index::keep_stat(&old, &mut entries);
+    index::replace_paths(old, names, entries)
+}
+
+fn restore_worktree(names: &BTreeSet<String>, files: &HashMap<String, Version>, index: &Index) -> io::Result<()> {
+    let mut changes: Vec<(&str, Option<&Version>)> = Vec::new();
+    for name in names.iter() {
+        match files.get(name) {
+            Some(version) if worktree::file_hash(name).as_ref() != Some(&version.hash) || !worktree::has_mode(name, version.mode) => {
+                changes.push((name, Some(version)));
+            },
+            Some(_) => {},
+            // tracked files missing from the source go away
+            None if index.entries.iter().any(|e| &e.name == name) => changes.push((name, None)),
+            None => {},
+        }
+    }
+    worktree::update_files(&changes)
+}
+
+fn read_tree_ish(rev: &str) -> io::Result<String> {
+    let hash = refs::rev_parse(rev)?;
+    match graph::read_commit(&hash) {
+        Ok(commit) => Ok(commit.tree),
+        Err(_) => Ok(hash),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+    use crate::index::{Entry, Index};
+    use crate::merge::Version;
+
+    #[test]
+    fn test_source_rev() {
+        // --staged alone restores from HEAD, the working tree alone from the index
+        assert_eq!(super::source_rev(None, true), Some("HEAD"));
+        assert_eq!(super::source_rev(None, false), None);
+        assert_eq!(super::source_rev(Some("main~1"), true), Some("main~1"));
+        assert_eq!(super::source_rev(Some("main~1"), false), Some("main~1"));
+    }
+    #[test]
+    fn test_restore_index() {
+        let old = Index::new(vec![
+            Entry::from_stage(vec![1; 20], "a.txt", 100644, 0),
+            Entry::from_stage(vec![2; 20], "b.txt", 100644, 0),
+            Entry::from_stage(vec![3; 20], "new.txt", 100644, 0),
+            Entry::from_stage(vec![4; 20], "other.txt", 100644, 0),
+        ], vec![]);
+        // the source holds a.txt as it is staged, b.txt changed and no new.txt
+        let files = vec![("a.txt", 1, 100644), ("b.txt", 5, 100755), ("other.txt", 6, 100644)].into_iter()
+                        .map(|(name, byte, mode)| (String::from(name), Version::new(mode, &[byte; 20])))
+                        .collect::<HashMap<String, Version>>();
+        let names = ["a.txt", "b.txt", "new.txt"].iter().map(|name| String::from(*name)).collect::<BTreeSet<String>>();
+        let index = super::restore_index(old, &names, &files);
+        let entries = index.entries.iter().map(|e| (e.name.as_str(), e.hash[0], e.mode)).collect::<Vec<_>>();
+        assert_eq!(entries, vec![("a.txt", 1, 100644), ("b.txt", 5, 100755), ("other.txt", 4, 100644)]);
+    }
+}
@@ -0,0 +1,26 @@
+use std::io;
+use crate::sequencer;
+use crate::sequencer::{Action, Options};
+
+pub enum RevertOption {
+    Start {
+        commits: Vec<String>,
+        no_commit: bool,
+        mainline: Option<usize>,
+    },
+    Continue,
+    Skip,
+    Abort,
+}
+
+pub fn revert(option: RevertOption) -> io::Result<bool> {
+    match option {
+        RevertOption::Start { commits, no_commit, mainline } => {
+            let options = Options { no_commit, record_origin: false, mainline };
+            sequencer::start(Action::Revert, commits.iter().map(|c| c.as_str()).collect(), options)
+        },
+        RevertOption::Continue => sequencer::resume(),
+        RevertOption::Skip => sequencer::skip(),
+        RevertOption::Abort => sequencer::abort().map(|_| true),
+    }
+}
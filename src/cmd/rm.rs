@@ -0,0 +1,169 @@
+use std::io;
+use std::collections::HashMap;
+use crate::refs;
+use crate::merge;
+use crate::merge::Version;
+use crate::graph;
+use crate::index;
+use crate::index::{Entry, Pathspec};
+use crate::worktree;
+use crate::cmd::GIT_INDEX;
+
+pub fn rm(paths: Vec<&str>, cached: bool, recursive: bool, force: bool) -> io::Result<()> {
+    index::with_lock(GIT_INDEX, || remove_paths(paths, cached, recursive, force))
+}
+
+fn remove_paths(paths: Vec<&str>, cached: bool, recursive: bool, force: bool) -> io::Result<()> {
+    let mut index = index::read_index(GIT_INDEX)?;
+    let mut names: Vec<String> = Vec::new();
+    for path in paths.iter() {
+        let pathspec = Pathspec::new(&[path]);
+        let matched = index.entries.iter()
+                        .filter(|e| pathspec.matches(&e.name))
+                        .map(|e| e.name.clone())
+                        .collect::<Vec<String>>();
+        if matched.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("pathspec '{}' did not match any files", path)));
+        }
+        // a glob reaches into directories by itself, only a directory named as such needs -r
+        if !recursive && matched.iter().any(|name| pathspec.matches_directory(name)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("not removing '{}' recursively without -r", path)));
+        }
+        names.extend(matched);
+    }
+    names.sort();
+    names.dedup();
+    if !force {
+        check_local_changes(&index, &names, cached)?;
+    }
+
+    for name in names.iter() {
+        println!("rm '{}'", name);
+        index = index::remove_index(index, name)?;
+    }
+    index::write_index(GIT_INDEX, &index)?;
+    if !cached {
+        for name in names.iter() {
+            worktree::remove_file(name)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_local_changes(index: &index::Index, names: &[String], cached: bool) -> io::Result<()> {
+    let head_tree = match refs::rev_parse("HEAD") {
+        Ok(head) => Some(graph::read_commit(&head)?.tree),
+        Err(_) => None,
+    };
+    let head = merge::read_tree_files(head_tree.as_deref())?;
+    let changed = |entry: &Entry| {
+        let hash = worktree::file_hash(&entry.name)?;
+        Some(hash != entry.hash || !worktree::has_mode(&entry.name, entry.mode))
+    };
+    refuse_removal(index, &head, names, cached, &changed)
+}
+
+fn refuse_removal(
+    index: &index::Index,
+    head: &HashMap<String, Version>,
+    names: &[String],
+    cached: bool,
+    changed: &dyn Fn(&Entry) -> Option<bool>,
+) -> io::Result<()> {
+    // refuse to lose what only the index or only the working tree holds,
+    // changed tells whether the file differs from its entry, None when it is gone
+    let mut both: Vec<&str> = Vec::new();
+    let mut staged: Vec<&str> = Vec::new();
+    let mut local: Vec<&str> = Vec::new();
+    for name in names.iter() {
+        let entry = match index.entries.iter().find(|e| &e.name == name && e.stage == 0) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        // a file already gone from the working tree has nothing to lose
+        let local_changes = match changed(entry) {
+            Some(local_changes) => local_changes,
+            None => continue,
+        };
+        let staged_changes = head.get(name).is_none_or(|v| v.hash != entry.hash || v.mode != entry.mode);
+        if local_changes && staged_changes {
+            both.push(name);
+        } else if !cached && staged_changes {
+            staged.push(name);
+        } else if !cached && local_changes {
+            local.push(name);
+        }
+    }
+    let mut messages: Vec<String> = Vec::new();
+    if !both.is_empty() {
+        messages.push(describe(&both, "has staged content different from both the\nfile and the HEAD", "have staged content different from both the\nfile and the HEAD", "(use -f to force removal)"));
+    }
+    if !staged.is_empty() {
+        messages.push(describe(&staged, "has changes staged in the index", "have changes staged in the index", "(use --cached to keep the file, or -f to force removal)"));
+    }
+    if !local.is_empty() {
+        messages.push(describe(&local, "has local modifications", "have local modifications", "(use --cached to keep the file, or -f to force removal)"));
+    }
+    if !messages.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, messages.join("\n")));
+    }
+    Ok(())
+}
+
+fn describe(names: &[&str], one: &str, many: &str, hint: &str) -> String {
+    let header = if names.len() == 1 {
+        format!("the following file {}:", one)
+    } else {
+        format!("the following files {}:", many)
+    };
+    let files = names.iter().map(|n| format!("    {}", n)).collect::<Vec<String>>().join("\n");
+    format!("{}\n{}\n{}", header, files, hint)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::index::{Entry, Index};
+    use crate::merge::Version;
+
+    fn refuse(names: &[&str], cached: bool) -> Result<(), String> {
+        // HEAD has every file as 1, the index stages 2 for staged.txt and both.txt, and
+        // the working tree differs for local.txt and both.txt; gone.txt is deleted
+        let head = ["clean.txt", "staged.txt", "local.txt", "both.txt", "gone.txt"].iter()
+                    .map(|name| (String::from(*name), Version::new(100644, &[1; 20])))
+                    .collect::<HashMap<String, Version>>();
+        let index = Index::new(vec![
+            Entry::from_stage(vec![2; 20], "both.txt", 100644, 0),
+            Entry::from_stage(vec![1; 20], "clean.txt", 100644, 0),
+            Entry::from_stage(vec![1; 20], "gone.txt", 100644, 0),
+            Entry::from_stage(vec![1; 20], "local.txt", 100644, 0),
+            Entry::from_stage(vec![2; 20], "new.txt", 100644, 0),
+            Entry::from_stage(vec![2; 20], "staged.txt", 100644, 0),
+        ], vec![]);
+        let changed = |entry: &Entry| match entry.name.as_str() {
+            "gone.txt" => None,
+            name => Some(name == "local.txt" || name == "both.txt"),
+        };
+        let names = names.iter().map(|name| String::from(*name)).collect::<Vec<String>>();
+        super::refuse_removal(&index, &head, &names, cached, &changed).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_refuse_removal() {
+        assert_eq!(refuse(&["clean.txt", "gone.txt"], false), Ok(()));
+        assert_eq!(refuse(&["both.txt"], false), Err(String::from("the following file has staged content different from both the\nfile and the HEAD:\n    both.txt\n(use -f to force removal)")));
+        assert_eq!(refuse(&["local.txt"], false), Err(String::from("the following file has local modifications:\n    local.txt\n(use --cached to keep the file, or -f to force removal)")));
+        // a file HEAD does not have counts as staged
+        assert_eq!(refuse(&["new.txt", "staged.txt"], false), Err(String::from("the following files have changes staged in the index:\n    new.txt\n    staged.txt\n(use --cached to keep the file, or -f to force removal)")));
+        assert_eq!(
+            refuse(&["both.txt", "local.txt", "staged.txt"], false),
+            Err(String::from("the following file has staged content different from both the\nfile and the HEAD:\n    both.txt\n(use -f to force removal)\nthe following file has changes staged in the index:\n    staged.txt\n(use --cached to keep the file, or -f to force removal)\nthe following file has local modifications:\n    local.txt\n(use --cached to keep the file, or -f to force removal)")),
+        );
+    }
+    #[test]
+    fn test_refuse_removal_cached() {
+        // with --cached the file stays, only content found nowhere else is refused
+        assert_eq!(refuse(&["local.txt", "staged.txt", "new.txt"], true), Ok(()));
+        assert!(refuse(&["both.txt"], true).unwrap_err().starts_with("the following file has staged content different from both"));
+    }
+}
@@ -0,0 +1,34 @@
+use std::io;
+use crate::refs;
+use crate::refs::Ref;
+
+pub fn show_ref(patterns: Vec<&str>, heads: bool, tags: bool, verify: bool) -> io::Result<()> {
+    if verify {
+        // --verify takes exact ref names
+        for pattern in patterns.iter() {
+            if !pattern.starts_with("refs/") && *pattern != "HEAD" {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' - not a valid ref", pattern)));
+            }
+            let hash = refs::resolve_ref(pattern)
+                .or(Err(io::Error::new(io::ErrorKind::NotFound, format!("'{}' - not a valid ref", pattern))))?;
+            println!("{} {}", hash, pattern);
+        }
+        return Ok(());
+    }
+    let found = refs::read_refs()?.into_iter()
+        .filter(|r| (!heads && !tags) || (heads && r.name.starts_with("refs/heads/")) || (tags && r.name.starts_with("refs/tags/")))
+        .filter(|r| patterns.is_empty() || patterns.iter().any(|p| is_match(r, p)))
+        .collect::<Vec<Ref>>();
+    if found.is_empty() {
+        return Err(io::Error::from(io::ErrorKind::NotFound));
+    }
+    for r in found.iter() {
+        println!("{} {}", r.hash, r.name);
+    }
+    Ok(())
+}
+
+fn is_match(r: &Ref, pattern: &str) -> bool {
+    // "master" matches refs/heads/master and refs/remotes/origin/master, but not refs/heads/mymaster
+    r.name == pattern || r.name.ends_with(&format!("/{}", pattern.trim_start_matches('/')))
+}
@@ -0,0 +1,373 @@
+use std::io;
+use std::path::Path;
+use crate::refs;
+use crate::refs::ReflogEntry;
+use crate::merge;
+use crate::graph;
+use crate::index;
+use crate::index::Index;
+use crate::worktree;
+use crate::object::{Object, ObjectType};
+use crate::object::tree;
+use crate::object::tree::File;
+use crate::object::commit;
+use crate::object::commit::{Commit, User};
+use crate::cmd::diff;
+use crate::cmd::switch;
+use crate::cmd::switch::SwitchMode;
+use crate::cmd::restore;
+use crate::cmd::commit::{USER_NAME, USER_EMAIL};
+use crate::cmd::cat_file::hash_key_to_path;
+use crate::cmd::{GIT_BASE_DIR, GIT_INDEX, GIT_MERGE_HEAD};
+
+const STASH_REF: &str = "refs/stash";
+
+pub enum StashOption {
+    Push {
+        paths: Vec<String>,
+        message: Option<String>,
+        untracked: bool,
+    },
+    Pop {
+        stash: Option<String>,
+        index: bool,
+    },
+    Apply {
+        stash: Option<String>,
+        index: bool,
+    },
+    List,
+    Show {
+        stash: Option<String>,
+        patch: bool,
+    },
+    Drop {
+        stash: Option<String>,
+    },
+    Clear,
+    Branch {
+        name: String,
+        stash: Option<String>,
+    },
+}
+
+pub fn stash(option: StashOption) -> io::Result<()> {
+    index::with_lock(GIT_INDEX, || run(option))
+}
+
+fn run(option: StashOption) -> io::Result<()> {
+    match option {
+        StashOption::Push { paths, message, untracked } => push(paths.iter().map(|p| p.as_str()).collect(), message.as_deref(), untracked),
+        StashOption::Pop { stash, index } => {
+            let (n, hash) = resolve_stash(stash.as_deref())?;
+            if apply(&hash, index)? {
+                drop_stash(stash.as_deref(), n, &hash)
+            } else {
+                println!("The stash entry is kept in case you need it again.");
+                Ok(())
+            }
+        },
+        StashOption::Apply { stash, index } => {
+            let (_, hash) = resolve_stash(stash.as_deref())?;
+            apply(&hash, index)?;
+            Ok(())
+        },
+        StashOption::List => {
+            for (n, entry) in refs::read_reflog(STASH_REF)?.iter().rev().enumerate() {
+                println!("stash@{{{}}}: {}", n, entry.message);
+            }
+            Ok(())
+        },
+        StashOption::Show { stash, patch } => {
+            let (_, hash) = resolve_stash(stash.as_deref())?;
+            let commit = graph::read_commit(&hash)?;
+            let base = graph::read_commit(&commit.parents[0])?;
+            let entries = index::diff_trees(Some(&base.tree), Some(&commit.tree))?;
+            if patch {
+                entries.iter().try_for_each(|entry| diff::print_entry(entry, diff::DEFAULT_CONTEXT))?;
+            } else if !entries.is_empty() {
+                diff::print_stat(&entries)?;
+            }
+            Ok(())
+        },
+        StashOption::Drop { stash } => {
+            let (n, hash) = resolve_stash(stash.as_deref())?;
+            drop_stash(stash.as_deref(), n, &hash)
+        },
+        StashOption::Clear => refs::delete_ref(STASH_REF),
+        StashOption::Branch { name, stash } => {
+            let (n, hash) = resolve_stash(stash.as_deref())?;
+            let commit = graph::read_commit(&hash)?;
+            switch::switch(&name, Some(&commit.parents[0]), SwitchMode::Create)?;
+            if apply(&hash, true)? {
+                drop_stash(stash.as_deref(), n, &hash)?;
+            }
+            Ok(())
+        },
+    }
+}
+
+fn push(paths: Vec<&str>, message: Option<&str>, untracked: bool) -> io::Result<()> {
+    let head = refs::rev_parse("HEAD").or(Err(io::Error::new(io::ErrorKind::NotFound, "You do not have the initial commit yet")))?;
+    let head_commit = graph::read_commit(&head)?;
+    let branch = refs::read_head_branch().unwrap_or_else(|_| String::from("(no branch)"));
+    let description = format!("{}: {} {}", branch, &head[..7], head_commit.message.lines().next().unwrap_or(""));
+    let index = index::read_index(GIT_INDEX)?;
+    if !paths.is_empty() {
+        if let Some(path) = paths.iter().find(|p| !index.entries.iter().any(|e| index::match_pathspec(&e.name, &[p]))) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("pathspec '{}' did not match any file(s) known to git", path)));
+        }
+    }
+
+    // the index commit records the whole index, the working tree commit the
+    // files the pathspec selects on top of it
+    let i_tree = Object::Tree(tree::write_tree()?).write()?;
+    let mut files: Vec<File> = Vec::new();
+    for entry in index.entries.iter() {
+        if paths.is_empty() || index::match_pathspec(&entry.name, &paths) {
+            if let Some(file) = worktree_file(&entry.name)? {
+                files.push(file);
+            }
+        } else {
+            files.push(File::new(entry.mode as usize, &entry.hash, &entry.name, ObjectType::Blob));
+        }
+    }
+    let w_tree = Object::Tree(tree::build_tree(files)?).write()?;
+    let untracked_files = if untracked {
+        index.untracked_files()?.into_iter()
+            .filter(|name| paths.is_empty() || index::match_pathspec(name, &paths))
+            .collect::<Vec<String>>()
+    } else {
+        Vec::new()
+    };
+    if i_tree == head_commit.tree && w_tree == i_tree && untracked_files.is_empty() {
+        println!("No local changes to save");
+        return Ok(());
+    }
+
+    let i_commit = write_commit(&i_tree, &format!("index on {}", description), vec![&head])?;
+    let u_commit = if untracked_files.is_empty() {
+        None
+    } else {
+        let mut files: Vec<File> = Vec::new();
+        for name in untracked_files.iter() {
+            if let Some(file) = worktree_file(name)? {
+                files.push(file);
+            }
+        }
+        let u_tree = Object::Tree(tree::build_tree(files)?).write()?;
+        Some(write_commit(&u_tree, &format!("untracked files on {}", description), Vec::new())?)
+    };
+    let message = stash_message(&branch, &description, message);
+    let w_commit = write_commit(&w_tree, &message, stash_parents(&head, &i_commit, u_commit.as_deref()))?;
+    let old = refs::resolve_ref(STASH_REF).ok();
+    refs::update_ref(&format!("{}/{}", GIT_BASE_DIR, STASH_REF), &w_commit)?;
+    refs::append_reflog(STASH_REF, old.as_deref(), &w_commit, User::now(USER_NAME, USER_EMAIL), &message)?;
+    println!("Saved working directory and index state {}", message);
+
+    // put the stashed paths back the way HEAD has them
+    if paths.is_empty() {
+        worktree::checkout_tree(&head_commit.tree)?;
+    } else {
+        restore::restore(paths, Some("HEAD"), true, true)?;
+    }
+    for name in untracked_files.iter() {
+        worktree::remove_file(name)?;
+    }
+    Ok(())
+}
+
+fn stash_message(branch: &str, description: &str, message: Option<&str>) -> String {
+    match message {
+        Some(message) => format!("On {}: {}", branch, message),
+        None => format!("WIP on {}", description),
+    }
+}
+
+fn stash_parents<'a>(head: &'a str, i_commit: &'a str, u_commit: Option<&'a str>) -> Vec<&'a str> {
+    // the stash commit sits on HEAD, with the index and then the untracked files as further parents
+    let mut parents = vec![head, i_commit];
+    parents.extend(u_commit);
+    parents
+}
+
+fn apply(hash: &str, restore_index: bool) -> io::Result<bool> {
+    // true when the stash applied without conflicts
+    if Path::new(GIT_MERGE_HEAD).exists() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot apply a stash in the middle of a merge"));
+    }
+    let w_commit = graph::read_commit(hash)?;
+    let base_tree = graph::read_commit(&w_commit.parents[0])?.tree;
+    let i_tree = graph::read_commit(&w_commit.parents[1])?.tree;
+    let c_tree = Object::Tree(tree::write_tree()?).write()?;
+
+    // with --index the staged changes are merged into the index first, and must merge cleanly
+    let index_tree = if restore_index && i_tree != base_tree {
+        let entries = merge::merge_trees(Some(&base_tree), &c_tree, &i_tree, "Updated upstream", "Stashed changes")?;
+        if entries.iter().any(|e| !e.is_clean()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Conflicts in index. Try without --index."));
+        }
+        let files = entries.iter()
+                    .filter_map(|e| e.result.as_ref().map(|v| File::new(v.mode as usize, &v.hash, &e.name, ObjectType::Blob)))
+                    .collect::<Vec<File>>();
+        Some(Object::Tree(tree::build_tree(files)?).write()?)
+    } else {
+        None
+    };
+    let untracked = match w_commit.parents.get(2) {
+        Some(u_commit) => merge::read_tree_files(Some(&graph::read_commit(u_commit)?.tree))?,
+        None => Default::default(),
+    };
+    let mut names = untracked.keys().collect::<Vec<&String>>();
+    names.sort();
+    if let Some(name) = names.iter().find(|name| Path::new(name.as_str()).exists()) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists, no checkout\ncould not restore untracked files from stash", name)));
+    }
+
+    let entries = merge::merge_trees(Some(&base_tree), &c_tree, &w_commit.tree, "Updated upstream", "Stashed changes")?;
+    merge::check_worktree(&entries)?;
+    merge::checkout_entries(&entries)?;
+    for message in merge::describe(&entries) {
+        println!("{}", message);
+    }
+    for name in names {
+        let version = &untracked[name];
+        worktree::write_blob(name, &version.hash, version.mode)?;
+    }
+    if entries.iter().any(|e| !e.is_clean()) {
+        return Ok(false);
+    }
+
+    // without --index only the files the stash adds stay staged
+    let old = index::read_index(GIT_INDEX)?;
+    let mut staged = tree_index(index_tree.as_deref().unwrap_or(&c_tree))?;
+    if index_tree.is_none() {
+        let added = entries.iter()
+                    .filter(|e| e.ours.is_none() && e.base.is_none())
+                    .filter_map(|e| e.result.as_ref().map(|v| index::Entry::from_stage(v.hash.clone(), &e.name, v.mode, 0)))
+                    .collect::<Vec<index::Entry>>();
+        staged.invalidate_trees(added.iter().map(|e| &e.name));
+        staged.entries.extend(added);
+        staged.entries.sort_by(|a, b| (&a.name, a.stage).cmp(&(&b.name, b.stage)));
+    }
+    index::keep_stat(&old, &mut staged.entries);
+    index::write_index(GIT_INDEX, &Index { entries: staged.entries, tree_entries: staged.tree_entries, ..old })?;
+    Ok(true)
+}
+
+fn tree_index(tree: &str) -> io::Result<Index> {
+    tree::Tree::from_hash_file(&hash_key_to_path(tree))?.to_index()
+}
+
+fn drop_stash(name: Option<&str>, n: usize, hash: &str) -> io::Result<()> {
+    let entries = drop_entry(refs::read_reflog(STASH_REF)?, n);
+    match entries.last() {
+        Some(last) => {
+            refs::update_ref(&format!("{}/{}", GIT_BASE_DIR, STASH_REF), &last.new)?;
+            refs::write_reflog(STASH_REF, &entries)?;
+        },
+        None => refs::delete_ref(STASH_REF)?,
+    }
+    let name = match name {
+        Some(name) => String::from(name),
+        None => format!("{}@{{{}}}", STASH_REF, n),
+    };
+    println!("Dropped {} ({})", name, hash);
+    Ok(())
+}
+
+fn drop_entry(mut entries: Vec<ReflogEntry>, n: usize) -> Vec<ReflogEntry> {
+    // stash@{n} counts from the newest entry, the last in the reflog
+    let i = entries.len() - 1 - n;
+    entries.remove(i);
+    // the entry after the dropped one now follows the one before it
+    if i < entries.len() {
+        entries[i].old = if i == 0 { String::from(refs::NULL_HASH) } else { entries[i - 1].new.clone() };
+    }
+    entries
+}
+
+fn resolve_stash(name: Option<&str>) -> io::Result<(usize, String)> {
+    let entries = refs::read_reflog(STASH_REF)?;
+    if entries.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No stash entries found."));
+    }
+    let n = match name {
+        None => 0,
+        Some(name) => parse_stash(name)?,
+    };
+    let entry = entries.iter().rev().nth(n)
+        .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("stash@{{{}}} is not a valid reference", n)))?;
+    Ok((n, entry.new.clone()))
+}
+
+fn parse_stash(name: &str) -> io::Result<usize> {
+    // stash@{<n>}, or just <n>
+    name.strip_prefix("stash@{").and_then(|n| n.strip_suffix('}')).unwrap_or(name)
+        .parse::<usize>()
+        .or(Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a stash-like commit", name))))
+}
+
+fn worktree_file(name: &str) -> io::Result<Option<File>> {
+    // the file as it is in the working tree, None when it is gone
+    let mode = match worktree::file_mode(name) {
+        Some(mode) => mode,
+        None => return Ok(None),
+    };
+    let hash = worktree::stage_file(name)?;
+    Ok(Some(File::new(mode as usize, &hash, name, ObjectType::Blob)))
+}
+
+fn write_commit(tree: &str, message: &str, parents: Vec<&str>) -> io::Result<String> {
+    let commit: Commit = commit::commit_tree(USER_NAME, USER_EMAIL, tree, message, parents)?;
+    Object::Commit(commit).write()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::refs::ReflogEntry;
+    use crate::object::commit::User;
+
+    fn entry(old: &str, new: &str, message: &str) -> ReflogEntry {
+        let committer = User::from("A U Thor <author@example.com> 1616834749 +0900").unwrap();
+        ReflogEntry { old: old.repeat(40), new: new.repeat(40), committer, message: String::from(message) }
+    }
+
+    #[test]
+    fn test_parse_stash() {
+        assert_eq!(super::parse_stash("stash@{0}").unwrap(), 0);
+        assert_eq!(super::parse_stash("stash@{12}").unwrap(), 12);
+        assert_eq!(super::parse_stash("3").unwrap(), 3);
+        let err = super::parse_stash("stash@{x}").unwrap_err();
+        assert_eq!(err.to_string(), "'stash@{x}' is not a stash-like commit");
+        assert!(super::parse_stash("stash@{1").is_err());
+        assert!(super::parse_stash("main").is_err());
+    }
+    #[test]
+    fn test_drop_entry() {
+        // oldest first, as the reflog stores them: stash@{2}, stash@{1}, stash@{0}
+        let entries = vec![entry("0", "a", "first"), entry("a", "b", "second"), entry("b", "c", "third")];
+        // dropping stash@{1} links the newest entry to the oldest
+        let dropped = super::drop_entry(entries.clone(), 1);
+        let links = dropped.iter().map(|e| (&e.old[..1], &e.new[..1], e.message.as_str())).collect::<Vec<_>>();
+        assert_eq!(links, vec![("0", "a", "first"), ("a", "c", "third")]);
+        // the oldest one leaves the next starting from nothing
+        let dropped = super::drop_entry(entries.clone(), 2);
+        let links = dropped.iter().map(|e| (&e.old[..1], &e.new[..1])).collect::<Vec<_>>();
+        assert_eq!(links, vec![("0", "b"), ("b", "c")]);
+        // the newest one touches nothing else
+        let dropped = super::drop_entry(entries, 0);
+        let links = dropped.iter().map(|e| (&e.old[..1], &e.new[..1])).collect::<Vec<_>>();
+        assert_eq!(links, vec![("0", "a"), ("a", "b")]);
+    }
+    #[test]
+    fn test_stash_parents() {
+        assert_eq!(super::stash_parents("head", "index", None), vec!["head", "index"]);
+        assert_eq!(super::stash_parents("head", "index", Some("untracked")), vec!["head", "index", "untracked"]);
+    }
+    #[test]
+    fn test_stash_message() {
+        assert_eq!(super::stash_message("main", "main: 1234567 first", None), "WIP on main: 1234567 first");
+        assert_eq!(super::stash_message("main", "main: 1234567 first", Some("wip")), "On main: wip");
+    }
+}
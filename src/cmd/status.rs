@@ -1,27 +1,724 @@
-
 use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use crate::index;
-use crate::cmd::GIT_INDEX;
+use crate::index::{DiffEntry, Index, UntrackedCache};
+use crate::refs;
+use crate::refs::NULL_HASH;
+use crate::graph;
+use crate::merge;
+use crate::merge::Version;
+use crate::config::Config;
+use crate::object::blob::Blob;
+use crate::cmd::cat_file::hash_key_to_path;
+use crate::worktree;
+use crate::cmd::{GIT_INDEX, GIT_CHERRY_PICK_HEAD, GIT_REVERT_HEAD};
+
+// git pairs a deleted and an added path as a rename from 50% similarity
+const RENAME_SCORE: u32 = 50;
 
-pub fn status() -> io::Result<()> {
-    let index = index::read_index(GIT_INDEX)?;
-    let diff_entries = index.diff().unwrap();
-    if diff_entries.len() != 0 {
-        println!("Changes not staged for commit:");
-        for d in diff_entries.iter() {
-            println!("\tmodified:\t{}", d.name);
+#[derive(Debug, Clone)]
+pub struct Change {
+    // A, M, D, R or T
+    pub status: char,
+    pub name: String,
+    // where a renamed path came from, and how similar the two are
+    pub orig_name: Option<String>,
+    pub score: u32,
+    pub old: Option<Version>,
+    pub new: Option<Version>,
+}
+
+impl Change {
+    fn new(status: char, name: &str, old: Option<Version>, new: Option<Version>) -> Change {
+        Change {
+            status,
+            name: String::from(name),
+            orig_name: None,
+            score: 0,
+            old,
+            new,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self.status {
+            'A' => "new file:",
+            'D' => "deleted:",
+            'R' => "renamed:",
+            'T' => "typechange:",
+            _ => "modified:",
+        }
+    }
+
+    fn display_name(&self) -> String {
+        match &self.orig_name {
+            Some(orig) => format!("{} -> {}", quote_path(orig, false), quote_path(&self.name, false)),
+            None => quote_path(&self.name, false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatusFormat {
+    Long,
+    Short,
+    Porcelain,
+    PorcelainV2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UntrackedMode {
+    No,
+    Normal,
+    All,
+}
+
+struct Status {
+    head: Option<String>,
+    branch: Option<String>,
+    staged: Vec<Change>,
+    unstaged: Vec<Change>,
+    unmerged: Vec<(String, u8)>,
+    untracked: Vec<String>,
+    ignored: Vec<String>,
+}
+
+pub fn status(format: StatusFormat, branch: bool, nul: bool, untracked: UntrackedMode, ignored: bool) -> io::Result<()> {
+    // -z alone means porcelain
+    let format = if nul && format == StatusFormat::Long { StatusFormat::Porcelain } else { format };
+    // status only writes back what it learned, another process holding the index lock just means it does not
+    let lock = index::lock_index(GIT_INDEX).ok();
+    let mut index = index::read_index(GIT_INDEX)?;
+    // core.untrackedCache adds or drops the cache, one already there is kept up to date
+    let cached = index.untracked_cache.as_ref().map(|cache| cache.as_bytes());
+    // only the files whose stat data changed get hashed, the ones that did not change get it refreshed
+    let (stale, refreshed) = index.refresh(false)?;
+    match Config::read_config()?.get("core.untrackedCache") {
+        Some("true") if index.untracked_cache.is_none() => index.untracked_cache = Some(UntrackedCache::new()?),
+        Some("false") => index.untracked_cache = None,
+        _ => {},
+    }
+    // -uno hides the ignored paths as well
+    let (untracked_paths, ignored_paths) = match untracked {
+        UntrackedMode::No => (Vec::new(), Vec::new()),
+        untracked => index.untracked_paths(untracked == UntrackedMode::Normal, ignored)?,
+    };
+    if let Some(lock) = lock {
+        if refreshed || index.untracked_cache.as_ref().map(|cache| cache.as_bytes()) != cached {
+            index::write_index(GIT_INDEX, &index)?;
+        }
+        lock.commit()?;
+    }
+    let head = refs::rev_parse("HEAD").ok();
+    let head_tree = match &head {
+        Some(head) => Some(graph::read_commit(head)?.tree),
+        None => None,
+    };
+    let status = Status {
+        branch: refs::read_head_branch().ok(),
+        staged: staged_changes(head_tree.as_deref(), &index)?,
+        unstaged: unstaged_changes(&index, &stale),
+        unmerged: index.unmerged(),
+        untracked: untracked_paths,
+        ignored: ignored_paths,
+        head,
+    };
+    let term = if nul { '\0' } else { '\n' };
+    let mut out = io::stdout();
+    match format {
+        StatusFormat::Long => write_long(&mut out, &status, untracked != UntrackedMode::No),
+        StatusFormat::Short | StatusFormat::Porcelain => write_short(&mut out, &status, branch, term),
+        StatusFormat::PorcelainV2 => write_v2(&mut out, &status, &index, branch, term),
+    }
+}
+
+fn write_long<W: Write>(out: &mut W, status: &Status, show_untracked: bool) -> io::Result<()> {
+    match &status.branch {
+        Some(branch) => {
+            writeln!(out, "On branch {}", branch)?;
+            if status.head.is_some() {
+                write_tracking(out, branch)?;
+            }
+        },
+        None => writeln!(out, "HEAD detached at {}", &status.head.as_deref().unwrap_or_default()[..7])?,
+    }
+    if status.head.is_none() {
+        writeln!(out)?;
+        writeln!(out, "No commits yet")?;
+        writeln!(out)?;
+    }
+
+    let merging = merge::read_merge_head()?.is_some();
+    let picking = write_in_progress(out, merging, !status.unmerged.is_empty())?;
+
+    if !status.staged.is_empty() {
+        writeln!(out, "Changes to be committed:")?;
+        match status.head {
+            None => writeln!(out, "  (use \"git rm --cached <file>...\" to unstage)")?,
+            // a merge or a pick in progress commits everything staged
+            Some(_) if !merging && !picking => writeln!(out, "  (use \"git restore --staged <file>...\" to unstage)")?,
+            Some(_) => {},
+        }
+        for change in status.staged.iter() {
+            writeln!(out, "\t{:<12}{}", change.label(), change.display_name())?;
+        }
+        writeln!(out)?;
+    }
+    if !status.unmerged.is_empty() {
+        writeln!(out, "Unmerged paths:")?;
+        // a conflict where one side deleted the path is resolved by adding or removing it
+        if status.unmerged.iter().any(|(_, stages)| *stages & 0b001 != 0 && *stages != 0b111) {
+            writeln!(out, "  (use \"git add/rm <file>...\" as appropriate to mark resolution)")?;
+        } else {
+            writeln!(out, "  (use \"git add <file>...\" to mark resolution)")?;
         }
+        for (name, stages) in status.unmerged.iter() {
+            writeln!(out, "\t{:<17}{}", format!("{}:", unmerged_label(*stages)), quote_path(name, false))?;
+        }
+        writeln!(out)?;
+    }
+    if !status.unstaged.is_empty() {
+        writeln!(out, "Changes not staged for commit:")?;
+        if status.unstaged.iter().any(|c| c.status == 'D') {
+            writeln!(out, "  (use \"git add/rm <file>...\" to update what will be committed)")?;
+        } else {
+            writeln!(out, "  (use \"git add <file>...\" to update what will be committed)")?;
+        }
+        writeln!(out, "  (use \"git restore <file>...\" to discard changes in working directory)")?;
+        for change in status.unstaged.iter() {
+            writeln!(out, "\t{:<12}{}", change.label(), change.display_name())?;
+        }
+        writeln!(out)?;
+    }
+    if !status.untracked.is_empty() {
+        writeln!(out, "Untracked files:")?;
+        writeln!(out, "  (use \"git add <file>...\" to include in what will be committed)")?;
+        for f in status.untracked.iter() {
+            writeln!(out, "\t{}", quote_path(f, false))?;
+        }
+        writeln!(out)?;
+    }
+    if !status.ignored.is_empty() {
+        writeln!(out, "Ignored files:")?;
+        writeln!(out, "  (use \"git add -f <file>...\" to include in what will be committed)")?;
+        for f in status.ignored.iter() {
+            writeln!(out, "\t{}", quote_path(f, false))?;
+        }
+        writeln!(out)?;
+    }
+    if !show_untracked && !status.staged.is_empty() {
+        writeln!(out, "Untracked files not listed (use -u option to show untracked files)")?;
+    }
+
+    if !status.staged.is_empty() {
+        return Ok(());
+    }
+    if !status.unstaged.is_empty() || !status.unmerged.is_empty() {
+        writeln!(out, "no changes added to commit (use \"git add\" and/or \"git commit -a\")")?;
+    } else if !status.untracked.is_empty() {
+        writeln!(out, "nothing added to commit but untracked files present (use \"git add\" to track)")?;
+    } else if status.head.is_none() {
+        writeln!(out, "nothing to commit (create/copy files and use \"git add\" to track)")?;
+    } else if !show_untracked {
+        writeln!(out, "nothing to commit (use -u to show untracked files)")?;
     } else {
-        // println!("nothing to commit, working tree clean");
+        writeln!(out, "nothing to commit, working tree clean")?;
     }
+    Ok(())
+}
 
-    let untracked = index.untracked_files()?;
-    if untracked.len() != 0 {
-        println!("Untracket fules:");
-        for f in untracked {
-            println!("\t{}", f);
+fn write_short<W: Write>(out: &mut W, status: &Status, branch: bool, term: char) -> io::Result<()> {
+    // XY path, with the index in X and the working tree in Y
+    let quote = |name: &str| if term == '\0' { String::from(name) } else { quote_path(name, true) };
+    if branch {
+        let line = match (&status.branch, &status.head) {
+            (Some(branch), None) => format!("No commits yet on {}", branch),
+            (Some(branch), Some(_)) => match tracking(branch)? {
+                Some((upstream, ab)) => format!("{}...{}{}", branch, upstream, match ab {
+                    None => String::from(" [gone]"),
+                    Some((0, 0)) => String::new(),
+                    Some((a, 0)) => format!(" [ahead {}]", a),
+                    Some((0, b)) => format!(" [behind {}]", b),
+                    Some((a, b)) => format!(" [ahead {}, behind {}]", a, b),
+                }),
+                None => branch.clone(),
+            },
+            (None, _) => String::from("HEAD (no branch)"),
+        };
+        write!(out, "## {}{}", line, term)?;
+    }
+    for (name, (staged, unstaged, stages)) in by_path(status) {
+        let xy = match stages {
+            Some(stages) => String::from(unmerged_code(stages)),
+            None => format!("{}{}", staged.map_or(' ', |c| c.status), unstaged.map_or(' ', |c| c.status)),
+        };
+        match staged.and_then(|c| c.orig_name.as_ref()) {
+            Some(orig) if term == '\0' => write!(out, "{} {}\0{}\0", xy, name, orig)?,
+            Some(orig) => writeln!(out, "{} {} -> {}", xy, quote(orig), quote(name))?,
+            None => write!(out, "{} {}{}", xy, quote(name), term)?,
         }
     }
+    for f in status.untracked.iter() {
+        write!(out, "?? {}{}", quote(f), term)?;
+    }
+    for f in status.ignored.iter() {
+        write!(out, "!! {}{}", quote(f), term)?;
+    }
+    Ok(())
+}
 
+fn write_v2<W: Write>(out: &mut W, status: &Status, index: &Index, branch: bool, term: char) -> io::Result<()> {
+    let quote = |name: &str| if term == '\0' { String::from(name) } else { quote_path(name, false) };
+    let hash = |v: Option<&Version>| v.map_or(String::from(NULL_HASH), |v| hex::encode(&v.hash));
+    let mode = |v: Option<&Version>| v.map_or(0, |v| v.mode);
+    if branch {
+        write!(out, "# branch.oid {}{}", status.head.as_deref().unwrap_or("(initial)"), term)?;
+        write!(out, "# branch.head {}{}", status.branch.as_deref().unwrap_or("(detached)"), term)?;
+        if let (Some(branch), Some(_)) = (&status.branch, &status.head) {
+            if let Some((upstream, ab)) = tracking(branch)? {
+                write!(out, "# branch.upstream {}{}", upstream, term)?;
+                if let Some((a, b)) = ab {
+                    write!(out, "# branch.ab +{} -{}{}", a, b, term)?;
+                }
+            }
+        }
+    }
+    for (name, (staged, unstaged, stages)) in by_path(status) {
+        if let Some(stages) = stages {
+            let versions = (1..=3).map(|stage| index.entries.iter()
+                            .find(|e| e.name == name && e.stage == stage)
+                            .map(|e| Version::new(e.mode, &e.hash)))
+                            .collect::<Vec<Option<Version>>>();
+            write!(out, "u {} N... {:06} {:06} {:06} {:06} {} {} {} {}{}", unmerged_code(stages),
+                mode(versions[0].as_ref()), mode(versions[1].as_ref()), mode(versions[2].as_ref()), worktree::file_mode(name).unwrap_or(0),
+                hash(versions[0].as_ref()), hash(versions[1].as_ref()), hash(versions[2].as_ref()), quote(name), term)?;
+            continue;
+        }
+        // the index side is what HEAD has when nothing is staged
+        let (head, staged_version) = match staged {
+            Some(change) => (change.old.as_ref(), change.new.as_ref()),
+            None => (unstaged.and_then(|c| c.old.as_ref()), unstaged.and_then(|c| c.old.as_ref())),
+        };
+        let worktree_mode = match unstaged {
+            Some(change) => mode(change.new.as_ref()),
+            None => mode(staged_version),
+        };
+        let xy = format!("{}{}", staged.map_or('.', |c| c.status), unstaged.map_or('.', |c| c.status));
+        let fields = format!("{} N... {:06} {:06} {:06} {} {}", xy, mode(head), mode(staged_version), worktree_mode, hash(head), hash(staged_version));
+        match staged.and_then(|c| c.orig_name.as_ref().map(|orig| (orig, c.score))) {
+            Some((orig, score)) => write!(out, "2 {} R{} {}{}{}{}", fields, score, quote(name), if term == '\0' { '\0' } else { '\t' }, quote(orig), term)?,
+            None => write!(out, "1 {} {}{}", fields, quote(name), term)?,
+        }
+    }
+    for f in status.untracked.iter() {
+        write!(out, "? {}{}", quote(f), term)?;
+    }
+    for f in status.ignored.iter() {
+        write!(out, "! {}{}", quote(f), term)?;
+    }
     Ok(())
 }
+
+// staged change, unstaged change and unmerged stages of a path
+type PathState<'a> = (Option<&'a Change>, Option<&'a Change>, Option<u8>);
+
+fn by_path(status: &Status) -> BTreeMap<&str, PathState<'_>> {
+    let mut paths: BTreeMap<&str, PathState<'_>> = BTreeMap::new();
+    for change in status.staged.iter() {
+        paths.entry(&change.name).or_default().0 = Some(change);
+    }
+    for change in status.unstaged.iter() {
+        paths.entry(&change.name).or_default().1 = Some(change);
+    }
+    for (name, stages) in status.unmerged.iter() {
+        paths.entry(name).or_default().2 = Some(*stages);
+    }
+    paths
+}
+
+pub fn staged_changes(head_tree: Option<&str>, index: &Index) -> io::Result<Vec<Change>> {
+    // what a commit would record on top of HEAD, unmerged paths aside
+    let head = merge::read_tree_files(head_tree)?;
+    detect_renames(diff_staged(&head, index), read_blob)
+}
+
+fn diff_staged(head: &HashMap<String, Version>, index: &Index) -> Vec<Change> {
+    let unmerged = index.entries.iter()
+                    .filter(|e| e.stage != 0)
+                    .map(|e| e.name.as_str())
+                    .collect::<BTreeSet<&str>>();
+    // a path added with -N is not staged yet
+    let staged = index.entries.iter()
+                    .filter(|e| e.stage == 0 && !e.intent_to_add)
+                    .map(|e| (e.name.as_str(), Version::new(e.mode, &e.hash)))
+                    .collect::<HashMap<&str, Version>>();
+    let names = head.keys().map(|n| n.as_str())
+                    .chain(staged.keys().copied())
+                    .filter(|n| !unmerged.contains(n))
+                    .collect::<BTreeSet<&str>>();
+    let mut changes: Vec<Change> = Vec::new();
+    for name in names {
+        let (old, new) = (head.get(name), staged.get(name));
+        let status = match (old, new) {
+            (None, Some(_)) => 'A',
+            (Some(_), None) => 'D',
+            (Some(old), Some(new)) if old == new => continue,
+            (Some(old), Some(new)) if is_typechange(old.mode, new.mode) => 'T',
+            _ => 'M',
+        };
+        changes.push(Change::new(status, name, old.cloned(), new.cloned()));
+    }
+    changes
+}
+
+pub fn unstaged_changes(index: &Index, stale: &[String]) -> Vec<Change> {
+    // how the working tree differs from the index, for the paths a refresh found stale
+    let mut changes: Vec<Change> = Vec::new();
+    for entry in index.entries.iter().filter(|e| e.stage == 0 && stale.binary_search(&e.name).is_ok()) {
+        let staged = Version::new(entry.mode, &entry.hash);
+        let mode = match worktree::file_mode(&entry.name) {
+            Some(mode) => mode,
+            None => {
+                changes.push(Change::new('D', &entry.name, Some(staged), None));
+                continue;
+            },
+        };
+        let hash = worktree::file_hash(&entry.name).unwrap_or_default();
+        if entry.intent_to_add {
+            changes.push(Change::new('A', &entry.name, None, Some(Version::new(mode, &hash))));
+            continue;
+        }
+        let status = match is_typechange(entry.mode, mode) {
+            true => 'T',
+            false if hash != entry.hash || mode != entry.mode => 'M',
+            false => continue,
+        };
+        changes.push(Change::new(status, &entry.name, Some(staged), Some(Version::new(mode, &hash))));
+    }
+    changes
+}
+
+fn is_typechange(old: u32, new: u32) -> bool {
+    // regular files, symlinks and submodules; the executable bit is a plain modification
+    let kind = |mode: u32| if mode == 100755 { 100644 } else { mode };
+    kind(old) != kind(new)
+}
+
+fn read_blob(version: &Version) -> io::Result<Blob> {
+    Blob::from_hash_file(&hash_key_to_path(&hex::encode(&version.hash)))
+}
+
+fn detect_renames<F>(changes: Vec<Change>, read_blob: F) -> io::Result<Vec<Change>> where F: Fn(&Version) -> io::Result<Blob> {
+    // pair each deleted path with the most similar added one, identical contents first
+    let read = |v: &Option<Version>| match v {
+        Some(v) => read_blob(v),
+        None => Ok(Blob::new("")),
+    };
+    let (mut added, rest): (Vec<Change>, Vec<Change>) = changes.into_iter().partition(|c| c.status == 'A');
+    let (deleted, mut result): (Vec<Change>, Vec<Change>) = rest.into_iter().partition(|c| c.status == 'D');
+    let mut candidates: Vec<(u32, usize, usize)> = Vec::new();
+    for (d, del) in deleted.iter().enumerate() {
+        for (a, add) in added.iter().enumerate() {
+            let score = match (&del.old, &add.new) {
+                (Some(old), Some(new)) if is_typechange(old.mode, new.mode) => continue,
+                (Some(old), Some(new)) if old.hash == new.hash => 100,
+                _ => DiffEntry::new(&add.name, read(&add.new)?, read(&del.old)?, 0, 0).similarity(),
+            };
+            if score >= RENAME_SCORE {
+                candidates.push((score, d, a));
+            }
+        }
+    }
+    candidates.sort_by(|x, y| y.0.cmp(&x.0).then(x.1.cmp(&y.1)).then(x.2.cmp(&y.2)));
+    let mut paired = vec![false; deleted.len()];
+    for (score, d, a) in candidates {
+        if paired[d] || added[a].status != 'A' {
+            continue;
+        }
+        paired[d] = true;
+        added[a].status = 'R';
+        added[a].orig_name = Some(deleted[d].name.clone());
+        added[a].score = score;
+        added[a].old = deleted[d].old.clone();
+    }
+    result.extend(deleted.into_iter().zip(paired).filter(|(_, p)| !p).map(|(c, _)| c));
+    result.extend(added);
+    result.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(result)
+}
+
+// the upstream's short name and, unless it is gone, the commits ahead and behind it
+type Tracking = (String, Option<(usize, usize)>);
+
+fn tracking(branch: &str) -> io::Result<Option<Tracking>> {
+    let config = Config::read_config()?;
+    let upstream = match refs::upstream_of(&format!("refs/heads/{}", branch), &config) {
+        Some(upstream) => upstream,
+        None => return Ok(None),
+    };
+    let short = String::from(upstream.strip_prefix("refs/heads/")
+                    .or_else(|| upstream.strip_prefix("refs/remotes/"))
+                    .unwrap_or(&upstream));
+    let ab = match refs::resolve_ref(&upstream) {
+        Ok(theirs) => Some(graph::ahead_behind(&refs::rev_parse("HEAD")?, &theirs)?),
+        Err(_) => None,
+    };
+    Ok(Some((short, ab)))
+}
+
+fn write_tracking<W: Write>(out: &mut W, branch: &str) -> io::Result<()> {
+    let (short, ab) = match tracking(branch)? {
+        Some(tracking) => tracking,
+        None => return Ok(()),
+    };
+    let plural = |n: usize| if n == 1 { "commit" } else { "commits" };
+    match ab {
+        None => {
+            writeln!(out, "Your branch is based on '{}', but the upstream is gone.", short)?;
+            writeln!(out, "  (use \"git branch --unset-upstream\" to fixup)")?;
+        },
+        Some((0, 0)) => writeln!(out, "Your branch is up to date with '{}'.", short)?,
+        Some((ahead, 0)) => {
+            writeln!(out, "Your branch is ahead of '{}' by {} {}.", short, ahead, plural(ahead))?;
+            writeln!(out, "  (use \"git push\" to publish your local commits)")?;
+        },
+        Some((0, behind)) => {
+            writeln!(out, "Your branch is behind '{}' by {} {}, and can be fast-forwarded.", short, behind, plural(behind))?;
+            writeln!(out, "  (use \"git pull\" to update your local branch)")?;
+        },
+        Some((ahead, behind)) => {
+            writeln!(out, "Your branch and '{}' have diverged,", short)?;
+            writeln!(out, "and have {} and {} different commits each, respectively.", ahead, behind)?;
+            writeln!(out, "  (use \"git pull\" to merge the remote branch into yours)")?;
+        },
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+fn write_in_progress<W: Write>(out: &mut W, merging: bool, conflicted: bool) -> io::Result<bool> {
+    // the operation in progress, true for a cherry-pick or revert
+    if merging {
+        if conflicted {
+            writeln!(out, "You have unmerged paths.")?;
+            writeln!(out, "  (fix conflicts and run \"git commit\")")?;
+        } else {
+            writeln!(out, "All conflicts fixed but you are still merging.")?;
+            writeln!(out, "  (use \"git commit\" to conclude merge)")?;
+        }
+        writeln!(out)?;
+        return Ok(false);
+    }
+    for (path, action, noun) in [(GIT_CHERRY_PICK_HEAD, "cherry-pick", "cherry-picking"), (GIT_REVERT_HEAD, "revert", "reverting")] {
+        if !Path::new(path).exists() {
+            continue;
+        }
+        let hash = fs::read_to_string(path)?;
+        writeln!(out, "You are currently {} commit {}.", noun, &hash.trim()[..7])?;
+        if conflicted {
+            writeln!(out, "  (fix conflicts and run \"git {} --continue\")", action)?;
+        } else {
+            writeln!(out, "  (all conflicts fixed: run \"git {} --continue\")", action)?;
+        }
+        writeln!(out, "  (use \"git {} --abort\" to cancel the {} operation)", action, action)?;
+        writeln!(out)?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+fn unmerged_code(stages: u8) -> &'static str {
+    match stages {
+        0b111 => "UU",
+        0b110 => "AA",
+        0b011 => "UD",
+        0b101 => "DU",
+        0b001 => "DD",
+        0b010 => "AU",
+        _ => "UA",
+    }
+}
+
+fn quote_path(name: &str, quote_space: bool) -> String {
+    // C-style quoting of names with special or non-ASCII bytes
+    let special = |b: u8| b == b'"' || b == b'\\' || !(0x20..0x7f).contains(&b) || (quote_space && b == b' ');
+    if !name.bytes().any(special) {
+        return String::from(name);
+    }
+    let mut quoted = String::from("\"");
+    for b in name.bytes() {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b if !(0x20..0x7f).contains(&b) => quoted.push_str(&format!("\\{:03o}", b)),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn unmerged_label(stages: u8) -> &'static str {
+    // which of base (1), ours (2) and theirs (3) are present
+    match stages {
+        0b111 => "both modified",
+        0b110 => "both added",
+        0b011 => "deleted by them",
+        0b101 => "deleted by us",
+        0b001 => "both deleted",
+        0b010 => "added by us",
+        _ => "added by them",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::collections::HashMap;
+    use super::Change;
+    use crate::index::{Entry, Index};
+    use crate::merge::Version;
+    use crate::object::blob::Blob;
+
+    fn version(mode: u32, hash: u8) -> Version {
+        Version::new(mode, &[hash; 20])
+    }
+
+    fn statuses(changes: &[Change]) -> Vec<(char, &str, Option<&str>, u32)> {
+        changes.iter().map(|c| (c.status, c.name.as_str(), c.orig_name.as_deref(), c.score)).collect()
+    }
+
+    #[test]
+    fn test_diff_staged() {
+        let head = [("same", 100644, 1), ("modified", 100644, 2), ("exec", 100644, 3), ("link", 100644, 4), ("deleted", 100644, 5), ("conflict", 100644, 6)].iter()
+                    .map(|(name, mode, hash)| (String::from(*name), version(*mode, *hash)))
+                    .collect::<HashMap<String, Version>>();
+        let mut entries = vec![
+            Entry::from_stage(vec![9; 20], "added", 100644, 0),
+            Entry::from_stage(vec![6; 20], "conflict", 100644, 1),
+            Entry::from_stage(vec![7; 20], "conflict", 100644, 2),
+            Entry::from_stage(vec![3; 20], "exec", 100755, 0),
+            Entry::from_stage(vec![8; 20], "intent", 100644, 0),
+            Entry::from_stage(vec![4; 20], "link", 120000, 0),
+            Entry::from_stage(vec![12; 20], "modified", 100644, 0),
+            Entry::from_stage(vec![1; 20], "same", 100644, 0),
+        ];
+        entries[4].intent_to_add = true;
+        let changes = super::diff_staged(&head, &Index::new(entries, Vec::new()));
+        // unmerged paths and paths added with -N are left out
+        assert_eq!(statuses(&changes), vec![
+            ('A', "added", None, 0),
+            ('D', "deleted", None, 0),
+            ('M', "exec", None, 0),
+            ('T', "link", None, 0),
+            ('M', "modified", None, 0),
+        ]);
+    }
+    #[test]
+    fn test_detect_renames() {
+        let contents: HashMap<u8, &str> = [(1, "a\nb\nc\nd\n"), (2, "a\nb\nc\nx\n"), (3, "w\nx\ny\nz\n"), (4, "a\nq\nr\ns\n")].iter().cloned().collect();
+        let read = |v: &Version| Ok(Blob::new(contents[&v.hash[0]]));
+        let deleted = |name: &str, hash: u8| Change::new('D', name, Some(version(100644, hash)), None);
+        let added = |name: &str, hash: u8| Change::new('A', name, None, Some(version(100644, hash)));
+
+        // identical contents score 100, three of four lines 75
+        let changes = super::detect_renames(vec![deleted("old", 1), added("new", 1), deleted("b", 1), added("c", 2)], read).unwrap();
+        assert_eq!(statuses(&changes), vec![('R', "c", Some("b"), 75), ('R', "new", Some("old"), 100)]);
+        // below 50% the paths stay a deletion and an addition
+        let changes = super::detect_renames(vec![deleted("a", 1), added("b", 4)], read).unwrap();
+        assert_eq!(statuses(&changes), vec![('D', "a", None, 0), ('A', "b", None, 0)]);
+        // each deleted path is taken by one added path only, the best match first
+        let changes = super::detect_renames(vec![deleted("a", 1), added("b", 2), added("c", 1), added("d", 3)], read).unwrap();
+        assert_eq!(statuses(&changes), vec![('A', "b", None, 0), ('R', "c", Some("a"), 100), ('A', "d", None, 0)]);
+        // a symlink does not take over a regular file
+        let changes = super::detect_renames(vec![deleted("a", 1), Change::new('A', "b", None, Some(version(120000, 1)))], |_: &Version| -> io::Result<Blob> { unreachable!() }).unwrap();
+        assert_eq!(statuses(&changes), vec![('D', "a", None, 0), ('A', "b", None, 0)]);
+    }
+    fn status(staged: Vec<Change>, unmerged: Vec<(&str, u8)>, untracked: Vec<&str>) -> super::Status {
+        super::Status {
+            head: Some("1".repeat(40)),
+            branch: Some(String::from("main")),
+            staged,
+            unstaged: Vec::new(),
+            unmerged: unmerged.into_iter().map(|(name, stages)| (String::from(name), stages)).collect(),
+            untracked: untracked.into_iter().map(String::from).collect(),
+            ignored: Vec::new(),
+        }
+    }
+
+    fn renamed(name: &str, orig: &str, score: u32) -> Change {
+        let mut change = Change::new('R', name, Some(version(100644, 1)), Some(version(100644, 2)));
+        change.orig_name = Some(String::from(orig));
+        change.score = score;
+        change
+    }
+
+    fn short(status: &super::Status, term: char) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        super::write_short(&mut out, status, false, term).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_quote_path() {
+        assert_eq!(super::quote_path("a b", false), "a b");
+        assert_eq!(super::quote_path("a b", true), "\"a b\"");
+        assert_eq!(super::quote_path("caf\u{e9}", false), "\"caf\\303\\251\"");
+        assert_eq!(super::quote_path("a\"b\\c", false), "\"a\\\"b\\\\c\"");
+        assert_eq!(super::quote_path("tab\there\n", false), "\"tab\\there\\n\"");
+        assert_eq!(super::quote_path("\x7f", false), "\"\\177\"");
+    }
+    #[test]
+    fn test_unmerged_code() {
+        let codes = [0b001, 0b010, 0b011, 0b100, 0b101, 0b110, 0b111].iter().map(|s| super::unmerged_code(*s)).collect::<Vec<&str>>();
+        assert_eq!(codes, vec!["DD", "AU", "UD", "UA", "DU", "AA", "UU"]);
+        let status = status(Vec::new(), vec![("both", 0b111), ("ours", 0b010)], Vec::new());
+        assert_eq!(short(&status, '\n'), "UU both\nAU ours\n");
+    }
+    #[test]
+    fn test_write_short() {
+        let status = status(vec![renamed("new name", "old", 100)], Vec::new(), vec!["un tracked"]);
+        assert_eq!(short(&status, '\n'), "R  old -> \"new name\"\n?? \"un tracked\"\n");
+        // with -z the new path comes first and nothing is quoted
+        assert_eq!(short(&status, '\0'), "R  new name\0old\0?? un tracked\0");
+    }
+    #[test]
+    fn test_write_v2() {
+        let modified = Change::new('M', "mod", Some(version(100644, 1)), Some(version(100755, 2)));
+        let status = status(vec![modified, renamed("new", "old", 87)], vec![("x.conflict", 0b111)], vec!["a b"]);
+        let index = Index::new(vec![
+            Entry::from_stage(vec![3; 20], "x.conflict", 100644, 1),
+            Entry::from_stage(vec![4; 20], "x.conflict", 100644, 2),
+            Entry::from_stage(vec![5; 20], "x.conflict", 100755, 3),
+        ], Vec::new());
+        let (one, two) = ("01".repeat(20), "02".repeat(20));
+        let mut out: Vec<u8> = Vec::new();
+        super::write_v2(&mut out, &status, &index, false, '\n').unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), format!("1 M. N... 100644 100755 100755 {one} {two} mod
+2 R. N... 100644 100644 100644 {one} {two} R87 new\told
+u UU N... 100644 100644 100755 000000 {} {} {} x.conflict
+? a b
+", "03".repeat(20), "04".repeat(20), "05".repeat(20), one = one, two = two));
+        let mut out: Vec<u8> = Vec::new();
+        super::write_v2(&mut out, &status, &index, false, '\0').unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("R87 new\0old\0u UU"));
+    }
+    #[test]
+    fn test_is_typechange() {
+        assert!(!super::is_typechange(100644, 100755));
+        assert!(!super::is_typechange(100755, 100644));
+        assert!(super::is_typechange(100644, 120000));
+        assert!(super::is_typechange(120000, 160000));
+        assert!(!super::is_typechange(120000, 120000));
+    }
+}
@@ -0,0 +1,147 @@
+use std::io;
+use crate::refs;
+use crate::graph;
+use crate::worktree;
+use crate::index;
+use crate::cmd::GIT_INDEX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SwitchMode {
+    // an existing branch
+    Branch,
+    // -c, a new branch
+    Create,
+    // -C, a new branch, or an existing one moved to the start point
+    ForceCreate,
+    // --detach, any commit
+    Detach,
+}
+
+pub fn switch(name: &str, start_point: Option<&str>, mode: SwitchMode) -> io::Result<()> {
+    index::with_lock(GIT_INDEX, || switch_to(name, start_point, mode))
+}
+
+fn switch_to(name: &str, start_point: Option<&str>, mode: SwitchMode) -> io::Result<()> {
+    let head = refs::rev_parse("HEAD").ok();
+    let current = refs::read_head_branch().ok();
+    if mode == SwitchMode::Branch && current.as_deref() == Some(name) {
+        let tree = match &head {
+            Some(head) => Some(graph::read_commit(head)?.tree),
+            None => None,
+        };
+        print_local_changes(tree.as_deref())?;
+        println!("Already on '{}'", name);
+        return Ok(());
+    }
+    // a new branch on an unborn HEAD only renames it
+    if head.is_none() && start_point.is_none() && is_new_branch(mode) {
+        refs::update_head(name)?;
+        println!("Switched to a new branch '{}'", name);
+        return Ok(());
+    }
+    let target = match mode {
+        SwitchMode::Branch => match refs::resolve_ref(&format!("refs/heads/{}", name)) {
+            Ok(hash) => hash,
+            Err(_) if refs::rev_parse(name).is_ok() => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("a branch is expected, got commit '{}'", name)));
+            },
+            Err(_) => return Err(io::Error::new(io::ErrorKind::NotFound, format!("invalid reference: {}", name))),
+        },
+        _ => refs::rev_parse(start_rev(name, start_point, mode))?,
+    };
+    let tree = graph::read_commit(&target)?.tree;
+    let head_tree = match &head {
+        Some(head) => Some(graph::read_commit(head)?.tree),
+        None => None,
+    };
+    worktree::switch_tree(head_tree.as_deref(), &tree)?;
+
+    let existed = refs::resolve_ref(&format!("refs/heads/{}", name)).is_ok();
+    match mode {
+        SwitchMode::Create | SwitchMode::ForceCreate => {
+            refs::create_branch_at(name, &target, mode == SwitchMode::ForceCreate)?;
+            refs::update_head(name)?;
+        },
+        SwitchMode::Branch => {
+            refs::update_head(name)?;
+        },
+        SwitchMode::Detach => refs::detach_head(&target)?,
+    }
+
+    print_local_changes(Some(&tree))?;
+    if let (None, Some(head)) = (&current, &head) {
+        if head != &target {
+            println!("Previous HEAD position was {} {}", &head[..7], subject(head)?);
+        }
+    }
+    let subject = match mode {
+        SwitchMode::Detach => subject(&target)?,
+        _ => String::new(),
+    };
+    println!("{}", switched_message(name, mode, existed, &target, &subject));
+    Ok(())
+}
+
+fn is_new_branch(mode: SwitchMode) -> bool {
+    mode == SwitchMode::Create || mode == SwitchMode::ForceCreate
+}
+
+fn start_rev<'a>(name: &'a str, start_point: Option<&'a str>, mode: SwitchMode) -> &'a str {
+    // what a new branch starts from, or the commit to detach at
+    match mode {
+        SwitchMode::Detach => name,
+        _ => start_point.unwrap_or("HEAD"),
+    }
+}
+
+fn switched_message(name: &str, mode: SwitchMode, existed: bool, target: &str, subject: &str) -> String {
+    match mode {
+        SwitchMode::Branch => format!("Switched to branch '{}'", name),
+        SwitchMode::ForceCreate if existed => format!("Reset branch '{}'", name),
+        SwitchMode::Create | SwitchMode::ForceCreate => format!("Switched to a new branch '{}'", name),
+        SwitchMode::Detach => format!("HEAD is now at {} {}", &target[..7], subject),
+    }
+}
+
+fn print_local_changes(tree: Option<&str>) -> io::Result<()> {
+    for (status, name) in worktree::local_changes(tree)? {
+        println!("{}\t{}", status, name);
+    }
+    Ok(())
+}
+
+fn subject(hash: &str) -> io::Result<String> {
+    let commit = graph::read_commit(hash)?;
+    Ok(String::from(commit.message.lines().next().unwrap_or("")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SwitchMode;
+
+    #[test]
+    fn test_start_rev() {
+        assert_eq!(super::start_rev("topic", None, SwitchMode::Create), "HEAD");
+        assert_eq!(super::start_rev("topic", Some("main~1"), SwitchMode::Create), "main~1");
+        assert_eq!(super::start_rev("topic", None, SwitchMode::ForceCreate), "HEAD");
+        assert_eq!(super::start_rev("topic", Some("v1.0"), SwitchMode::ForceCreate), "v1.0");
+        assert_eq!(super::start_rev("main~2", None, SwitchMode::Detach), "main~2");
+    }
+    #[test]
+    fn test_is_new_branch() {
+        assert!(super::is_new_branch(SwitchMode::Create));
+        assert!(super::is_new_branch(SwitchMode::ForceCreate));
+        assert!(!super::is_new_branch(SwitchMode::Branch));
+        assert!(!super::is_new_branch(SwitchMode::Detach));
+    }
+    #[test]
+    fn test_switched_message() {
+        let target = "1234567890abcdef1234567890abcdef12345678";
+        assert_eq!(super::switched_message("main", SwitchMode::Branch, true, target, ""), "Switched to branch 'main'");
+        assert_eq!(super::switched_message("topic", SwitchMode::Create, false, target, ""), "Switched to a new branch 'topic'");
+        // -C only says it reset the branch when there was one
+        assert_eq!(super::switched_message("topic", SwitchMode::ForceCreate, false, target, ""), "Switched to a new branch 'topic'");
+        assert_eq!(super::switched_message("topic", SwitchMode::ForceCreate, true, target, ""), "Reset branch 'topic'");
+        assert_eq!(super::switched_message("main~1", SwitchMode::Detach, false, target, "first commit"), "HEAD is now at 1234567 first commit");
+    }
+}
@@ -4,6 +4,7 @@ use crate::index;
 use crate::cmd::GIT_INDEX;
 
 pub fn update_index(name: &str, mode: Option<&str>, hash: Option<&str>) -> io::Result<()> {
+    let lock = index::lock_index(GIT_INDEX)?;
     let blob = Blob::from_name(name)?;
     let index = index::read_index(GIT_INDEX)?;
     match mode {
@@ -22,5 +23,37 @@ pub fn update_index(name: &str, mode: Option<&str>, hash: Option<&str>) -> io::R
             index::write_index(GIT_INDEX, &new_index)?;
         }
     }
-    Ok(())
+    lock.commit()
+}
+
+pub fn refresh(really: bool) -> io::Result<bool> {
+    let lock = index::lock_index(GIT_INDEX)?;
+    // true when every entry matches its file, which is the exit status git reports
+    let mut index = index::read_index(GIT_INDEX)?;
+    let (stale, _) = index.refresh(really)?;
+    let unmerged = index.unmerged();
+    let mut paths = stale.iter()
+                    .map(|name| (name.as_str(), "needs update"))
+                    .chain(unmerged.iter().map(|(name, _)| (name.as_str(), "needs merge")))
+                    .collect::<Vec<(&str, &str)>>();
+    paths.sort();
+    for (name, state) in paths.iter() {
+        println!("{}: {}", name, state);
+    }
+    // the racy entries that still match are written out again, so they stop being racy
+    index::write_index(GIT_INDEX, &index)?;
+    lock.commit()?;
+    Ok(paths.is_empty())
+}
+
+pub fn index_version(version: &str) -> io::Result<()> {
+    let lock = index::lock_index(GIT_INDEX)?;
+    // rewrite the index in another format, 3 only sticks while an entry needs extended flags
+    let version = match version.parse::<u32>() {
+        Ok(version) if (2..=4).contains(&version) => version,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("index-version {} not in range: 2..4", version))),
+    };
+    let index = index::read_index(GIT_INDEX)?;
+    index::write_index(GIT_INDEX, &index::Index { version, ..index })?;
+    lock.commit()
 }
@@ -0,0 +1,128 @@
+use std::io;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use crate::cmd::GIT_CONFIG;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // (section[.subsection].key, value) in file order
+    pub entries: Vec<(String, String)>,
+}
+
+impl Config {
+    pub fn new(entries: Vec<(String, String)>) -> Config {
+        Config {
+            entries
+        }
+    }
+
+    pub fn from(data: &str) -> Config {
+        let mut entries: Vec<(String, String)> = Vec::new();
+        let mut section = String::new();
+        for line in data.lines() {
+            let line = strip_comment(line.trim());
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                let header = line.trim_start_matches('[').trim_end_matches(']');
+                section = match header.find(|c: char| c.is_whitespace()) {
+                    // [branch "feature/foo"]
+                    Some(i) => format!("{}.{}", header[..i].to_lowercase(), header[i..].trim().trim_matches('"')),
+                    // [core]
+                    None => header.to_lowercase(),
+                };
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[(i + 1)..].trim().trim_matches('"')),
+                // a key without value means true
+                None => (line, "true"),
+            };
+            entries.push((format!("{}.{}", section, key.to_lowercase()), String::from(value)));
+        }
+        Config::new(entries)
+    }
+
+    pub fn read_config() -> io::Result<Config> {
+        // the repository config overrides the global one
+        let mut entries: Vec<(String, String)> = Vec::new();
+        let mut paths: Vec<PathBuf> = Vec::new();
+        if let Some(home) = env::var_os("HOME") {
+            paths.push(PathBuf::from(home).join(".gitconfig"));
+        }
+        paths.push(PathBuf::from(GIT_CONFIG));
+        for path in paths {
+            let mut file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let mut buf = String::new();
+            file.read_to_string(&mut buf)?;
+            entries.append(&mut Config::from(&buf).entries);
+        }
+        Ok(Config::new(entries))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        // section and key names are case insensitive, subsection names are not
+        let key = normalize_key(key);
+        self.entries.iter().rev()
+            .find(|(k, _)| normalize_key(k) == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn normalize_key(key: &str) -> String {
+    let first = key.find('.').unwrap_or(0);
+    let last = key.rfind('.').unwrap_or(key.len());
+    if first == last {
+        return key.to_lowercase();
+    }
+    format!("{}{}{}", key[..first].to_lowercase(), &key[first..last], key[last..].to_lowercase())
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => return line[..i].trim_end(),
+            _ => {},
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    const CONFIG: &str = "[core]
+\trepositoryformatversion = 0
+\tbare = false ; comment
+[remote \"origin\"]
+\turl = https://github.com/terassyi/rusgit.git
+\tfetch = +refs/heads/*:refs/remotes/origin/*
+[branch \"feature/Login\"]
+\tremote = origin
+\tmerge = refs/heads/feature/Login
+";
+
+    #[test]
+    fn test_config_from() {
+        let config = Config::from(CONFIG);
+        assert_eq!(config.entries.len(), 6);
+        assert_eq!(config.get("core.bare"), Some("false"));
+        assert_eq!(config.get("remote.origin.url"), Some("https://github.com/terassyi/rusgit.git"));
+    }
+    #[test]
+    fn test_config_get_case() {
+        let config = Config::from(CONFIG);
+        assert_eq!(config.get("Branch.feature/Login.Merge"), Some("refs/heads/feature/Login"));
+        assert_eq!(config.get("branch.feature/login.merge"), None);
+    }
+}
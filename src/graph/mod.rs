@@ -0,0 +1,359 @@
+use std::io;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use crate::object::commit::Commit;
+use crate::cmd::cat_file::hash_key_to_path;
+
+// flags painted on commits while looking for common ancestors
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+// reads a commit by hash, the object store outside of tests
+pub type ReadCommit<'a> = &'a dyn Fn(&str) -> io::Result<Commit>;
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub hash: String,
+    pub commit: Commit,
+    // insertion order breaks ties between commits made in the same second
+    seq: usize,
+}
+
+impl Node {
+    fn date(&self) -> i64 {
+        self.commit.commiter.timestamp.timestamp()
+    }
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Node {}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // newest first, then first in
+        self.date().cmp(&other.date()).then(other.seq.cmp(&self.seq))
+    }
+}
+
+pub struct RevWalk<'a> {
+    queue: BinaryHeap<Node>,
+    seen: HashSet<String>,
+    seq: usize,
+    read: ReadCommit<'a>,
+}
+
+impl RevWalk<'static> {
+    pub fn new() -> RevWalk<'static> {
+        RevWalk::with_reader(&read_commit)
+    }
+}
+
+impl<'a> RevWalk<'a> {
+    pub fn with_reader(read: ReadCommit<'a>) -> RevWalk<'a> {
+        RevWalk { queue: BinaryHeap::new(), seen: HashSet::new(), seq: 0, read }
+    }
+
+    pub fn push(&mut self, hash: &str) -> io::Result<()> {
+        if !self.seen.insert(String::from(hash)) {
+            return Ok(());
+        }
+        let commit = (self.read)(hash)?;
+        self.queue.push(Node { hash: String::from(hash), commit, seq: self.seq });
+        self.seq += 1;
+        Ok(())
+    }
+}
+
+impl Iterator for RevWalk<'_> {
+    // commits reachable from the pushed ones, newest first
+    type Item = io::Result<Node>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.queue.pop()?;
+        for parent in node.commit.parents.iter() {
+            if let Err(e) = self.push(parent) {
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(node))
+    }
+}
+
+pub fn read_commit(hash: &str) -> io::Result<Commit> {
+    Commit::from_hash_file(&hash_key_to_path(hash))
+}
+
+
+fn paint_down_to_common(one: &str, twos: &[&str], read: ReadCommit) -> io::Result<(Vec<String>, HashMap<String, u8>)> {
+    // walk from all tips at once, newest first, marking which side reaches each commit.
+    // a commit reached from both sides is a candidate, and everything below it is stale.
+    let mut flags: HashMap<String, u8> = HashMap::new();
+    let mut queue: BinaryHeap<Node> = BinaryHeap::new();
+    let mut seq = 0;
+    let mut put = |queue: &mut BinaryHeap<Node>, hash: &str| -> io::Result<()> {
+        queue.push(Node { hash: String::from(hash), commit: read(hash)?, seq });
+        seq += 1;
+        Ok(())
+    };
+    *flags.entry(String::from(one)).or_insert(0) |= PARENT1;
+    put(&mut queue, one)?;
+    for two in twos {
+        *flags.entry(String::from(*two)).or_insert(0) |= PARENT2;
+        put(&mut queue, two)?;
+    }
+    let mut result: Vec<String> = Vec::new();
+    while queue.iter().any(|n| flags.get(&n.hash).map(|f| f & STALE == 0).unwrap_or(true)) {
+        let node = match queue.pop() {
+            Some(node) => node,
+            None => break,
+        };
+        let current = flags.get(&node.hash).copied().unwrap_or(0);
+        let mut paint = current & (PARENT1 | PARENT2 | STALE);
+        if paint == PARENT1 | PARENT2 {
+            if current & RESULT == 0 {
+                flags.insert(node.hash.clone(), current | RESULT);
+                result.push(node.hash.clone());
+            }
+            paint |= STALE;
+        }
+        for parent in node.commit.parents.iter() {
+            let f = flags.entry(parent.clone()).or_insert(0);
+            if *f & paint == paint {
+                continue;
+            }
+            *f |= paint;
+            put(&mut queue, parent)?;
+        }
+    }
+    Ok((result, flags))
+}
+
+pub fn merge_bases(one: &str, twos: &[&str]) -> io::Result<Vec<String>> {
+    merge_bases_with(one, twos, &read_commit)
+}
+
+fn merge_bases_with(one: &str, twos: &[&str], read: ReadCommit) -> io::Result<Vec<String>> {
+    // best common ancestors of one and the (hypothetical) merge of twos, newest first
+    if twos.contains(&one) {
+        return Ok(vec![String::from(one)]);
+    }
+    let (result, flags) = paint_down_to_common(one, twos, read)?;
+    let result = result.into_iter()
+        .filter(|hash| flags.get(hash).map(|f| f & STALE == 0).unwrap_or(true))
+        .collect::<Vec<String>>();
+    if result.len() <= 1 {
+        return Ok(result);
+    }
+    remove_redundant(result, read)
+}
+
+pub fn merge_base(one: &str, two: &str) -> io::Result<Option<String>> {
+    Ok(merge_bases(one, &[two])?.into_iter().next())
+}
+
+pub fn octopus_merge_bases(commits: &[&str]) -> io::Result<Vec<String>> {
+    // fold the merge bases of each commit into the ones found so far
+    let mut result: Vec<String> = match commits.first() {
+        Some(first) => vec![String::from(*first)],
+        None => return Ok(Vec::new()),
+    };
+    for commit in commits[1..].iter() {
+        let mut next: Vec<String> = Vec::new();
+        for base in result.iter() {
+            for b in merge_bases(commit, &[base])? {
+                if !next.contains(&b) {
+                    next.push(b);
+                }
+            }
+        }
+        result = next;
+    }
+    Ok(result)
+}
+
+pub fn is_ancestor(ancestor: &str, descendant: &str) -> io::Result<bool> {
+    is_ancestor_with(ancestor, descendant, &read_commit)
+}
+
+fn is_ancestor_with(ancestor: &str, descendant: &str, read: ReadCommit) -> io::Result<bool> {
+    Ok(merge_bases_with(ancestor, &[descendant], read)?.iter().any(|b| b == ancestor))
+}
+
+pub fn independent(commits: &[&str]) -> io::Result<Vec<String>> {
+    independent_with(commits, &read_commit)
+}
+
+fn independent_with(commits: &[&str], read: ReadCommit) -> io::Result<Vec<String>> {
+    // drop the commits reachable from any of the others
+    let mut unique: Vec<&str> = Vec::new();
+    for commit in commits {
+        if !unique.contains(commit) {
+            unique.push(commit);
+        }
+    }
+    let mut result: Vec<String> = Vec::new();
+    for commit in unique.iter() {
+        let others = unique.iter().filter(|c| *c != commit).copied().collect::<Vec<&str>>();
+        if !is_reachable_from_any(commit, &others, read)? {
+            result.push(String::from(*commit));
+        }
+    }
+    Ok(result)
+}
+
+fn remove_redundant(candidates: Vec<String>, read: ReadCommit) -> io::Result<Vec<String>> {
+    let mut result: Vec<String> = Vec::new();
+    for candidate in candidates.iter() {
+        let others = candidates.iter().filter(|c| *c != candidate).map(|c| c.as_str()).collect::<Vec<&str>>();
+        if !is_reachable_from_any(candidate, &others, read)? {
+            result.push(candidate.clone());
+        }
+    }
+    Ok(result)
+}
+
+fn is_reachable_from_any(commit: &str, others: &[&str], read: ReadCommit) -> io::Result<bool> {
+    if others.is_empty() {
+        return Ok(false);
+    }
+    // the commit itself becomes a common ancestor when the others reach it
+    Ok(paint_down_to_common(commit, others, read)?.0.iter().any(|b| b == commit))
+}
+
+pub fn ahead_behind(local: &str, upstream: &str) -> io::Result<(usize, usize)> {
+    ahead_behind_with(local, upstream, &read_commit)
+}
+
+fn ahead_behind_with(local: &str, upstream: &str, read: ReadCommit) -> io::Result<(usize, usize)> {
+    // commits only on the local side, and only on the upstream side
+    let local = reachable_with(local, read)?;
+    let upstream = reachable_with(upstream, read)?;
+    Ok((local.difference(&upstream).count(), upstream.difference(&local).count()))
+}
+
+pub fn reachable(hash: &str) -> io::Result<HashSet<String>> {
+    reachable_with(hash, &read_commit)
+}
+
+pub fn reachable_with(hash: &str, read: ReadCommit) -> io::Result<HashSet<String>> {
+    // order does not matter here, so a plain stack will do
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut stack = vec![String::from(hash)];
+    while let Some(hash) = stack.pop() {
+        if seen.contains(&hash) {
+            continue;
+        }
+        stack.extend(read(&hash)?.parents);
+        seen.insert(hash);
+    }
+    Ok(seen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Node;
+    use std::io;
+    use std::collections::{BinaryHeap, HashMap};
+    use crate::object::commit::Commit;
+
+    fn node(hash: &str, date: i64, seq: usize) -> Node {
+        let data = format!("tree bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c\nauthor A U Thor <author@example.com> {} +0900\ncommitter A U Thor <author@example.com> {} +0900\n\n{}\n", date, date, hash);
+        Node { hash: String::from(hash), commit: Commit::from(data.as_bytes()).unwrap(), seq }
+    }
+
+    fn commit(parents: &[&str], date: i64) -> Commit {
+        let parents = parents.iter().map(|p| format!("parent {}\n", p)).collect::<String>();
+        let data = format!("tree bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c\n{}author A U Thor <author@example.com> {} +0900\ncommitter A U Thor <author@example.com> {} +0900\n\nmessage\n", parents, date, date);
+        Commit::from(data.as_bytes()).unwrap()
+    }
+
+    fn criss_cross() -> HashMap<String, Commit> {
+        // a - b - d - f
+        //   \   X
+        //     c - e - g
+        // d merges c into b and e merges b into c, so b and c are both best common ancestors of f and g
+        let commits = vec![
+            ("a", commit(&[], 1616834701)),
+            ("b", commit(&["a"], 1616834702)),
+            ("c", commit(&["a"], 1616834703)),
+            ("d", commit(&["b", "c"], 1616834704)),
+            ("e", commit(&["c", "b"], 1616834705)),
+            ("f", commit(&["d"], 1616834706)),
+            ("g", commit(&["e"], 1616834707)),
+        ];
+        commits.into_iter().map(|(hash, commit)| (String::from(hash), commit)).collect()
+    }
+
+    fn reader(commits: &HashMap<String, Commit>) -> impl Fn(&str) -> io::Result<Commit> + '_ {
+        move |hash| commits.get(hash).cloned().ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    #[test]
+    fn test_paint_down_to_common() {
+        let commits = criss_cross();
+        let (result, flags) = super::paint_down_to_common("f", &["g"], &reader(&commits)).unwrap();
+        assert_eq!(result, vec!["c", "b"]);
+        assert_eq!(flags["f"], super::PARENT1);
+        assert_eq!(flags["g"], super::PARENT2);
+        assert_eq!(flags["a"] & super::STALE, super::STALE);
+    }
+    #[test]
+    fn test_merge_bases() {
+        let commits = criss_cross();
+        let read = reader(&commits);
+        assert_eq!(super::merge_bases_with("f", &["g"], &read).unwrap(), vec!["c", "b"]);
+        assert_eq!(super::merge_bases_with("b", &["c"], &read).unwrap(), vec!["a"]);
+        assert_eq!(super::merge_bases_with("d", &["f"], &read).unwrap(), vec!["d"]);
+        assert_eq!(super::merge_bases_with("f", &["f"], &read).unwrap(), vec!["f"]);
+    }
+    #[test]
+    fn test_is_ancestor() {
+        let commits = criss_cross();
+        let read = reader(&commits);
+        assert!(super::is_ancestor_with("a", "f", &read).unwrap());
+        assert!(super::is_ancestor_with("c", "f", &read).unwrap());
+        assert!(super::is_ancestor_with("f", "f", &read).unwrap());
+        assert!(!super::is_ancestor_with("f", "a", &read).unwrap());
+        assert!(!super::is_ancestor_with("f", "g", &read).unwrap());
+        assert!(!super::is_ancestor_with("b", "c", &read).unwrap());
+    }
+    #[test]
+    fn test_independent() {
+        let commits = criss_cross();
+        let read = reader(&commits);
+        assert_eq!(super::independent_with(&["f", "g", "d", "b", "f"], &read).unwrap(), vec!["f", "g"]);
+        assert_eq!(super::independent_with(&["b", "c"], &read).unwrap(), vec!["b", "c"]);
+        assert_eq!(super::independent_with(&["a", "e"], &read).unwrap(), vec!["e"]);
+    }
+    #[test]
+    fn test_ahead_behind() {
+        let commits = criss_cross();
+        let read = reader(&commits);
+        assert_eq!(super::ahead_behind_with("f", "g", &read).unwrap(), (2, 2));
+        assert_eq!(super::ahead_behind_with("f", "a", &read).unwrap(), (4, 0));
+        assert_eq!(super::ahead_behind_with("b", "g", &read).unwrap(), (0, 3));
+        assert_eq!(super::ahead_behind_with("f", "f", &read).unwrap(), (0, 0));
+    }
+    #[test]
+    fn test_node_order() {
+        let mut queue = BinaryHeap::new();
+        queue.push(node("old", 1616834749, 0));
+        queue.push(node("first", 1616834800, 1));
+        queue.push(node("second", 1616834800, 2));
+        let order = std::iter::from_fn(|| queue.pop()).map(|n| n.hash).collect::<Vec<String>>();
+        assert_eq!(order, vec!["first", "second", "old"]);
+    }
+}
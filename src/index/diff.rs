@@ -34,11 +34,44 @@ impl DiffEntry {
         self.new_mode != self.old_mode
     }
     
+    pub fn is_binary(&self) -> bool {
+        self.old.text().is_none() || self.new.text().is_none()
+    }
+
     pub fn compare(&self) -> Vec<wu_diff::DiffResult> {
-        let new: Vec<&str> = self.new.content.split('\n').collect();
-        let old: Vec<&str> = self.old.content.split('\n').collect();
-        wu_diff::diff(&old, &new)
+        // lines keep their newline, a last line without one differs from the same line with it
+        wu_diff::diff(&self.old_lines(), &self.new_lines())
+    }
+
+    pub fn old_lines(&self) -> Vec<&str> {
+        text_lines(&self.old)
+    }
+
+    pub fn new_lines(&self) -> Vec<&str> {
+        text_lines(&self.new)
     }
+
+    pub fn similarity(&self) -> u32 {
+        // percentage of the larger side made of lines both sides share, as rename detection scores it
+        let size = self.old.content.len().max(self.new.content.len());
+        if size == 0 {
+            return 100;
+        }
+        let old: Vec<&[u8]> = self.old.content.split_inclusive(|&b| b == b'\n').collect();
+        let new: Vec<&[u8]> = self.new.content.split_inclusive(|&b| b == b'\n').collect();
+        let common: usize = wu_diff::diff(&old, &new).iter()
+                        .filter_map(|r| match r {
+                            wu_diff::DiffResult::Common(elm) => elm.old_index.map(|i| old[i].len()),
+                            _ => None,
+                        })
+                        .sum();
+        (common * 100 / size) as u32
+    }
+}
+
+fn text_lines(blob: &Blob) -> Vec<&str> {
+    // a binary blob has no lines to show
+    blob.text().unwrap_or_default().split_inclusive('\n').collect()
 }
 
 // impl fmt::Display for DiffEntry {
@@ -1,80 +1,226 @@
-
+use std::fmt;
 use std::io;
+use std::env;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::io::Read;
+use crate::config::Config;
+use crate::index::pathspec::wildmatch;
 use crate::cmd::GITIGNORE;
 use crate::cmd::GIT_BASE_DIR;
-use crate::cmd::cat_file::{file_to_object, hash_key_to_path};
+use crate::cmd::GIT_INFO_EXCLUDE;
 
 #[derive(Debug, Clone)]
+pub struct Pattern {
+    pub pattern: String,
+    // directory of the .gitignore the pattern came from, "" for the top
+    pub base: String,
+    pub negative: bool,
+    pub dir_only: bool,
+    pub anchored: bool,
+    // where the rule was written, as check-ignore -v reports it
+    pub source: String,
+    pub line: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct GitIgnore {
-    pub files: Vec<String>,
+    // lowest precedence first, the last matching pattern decides
+    pub(super) patterns: Vec<Pattern>,
+    per_directory: bool,
 }
 
-impl GitIgnore {
-    pub fn new(files: Vec<String>) -> GitIgnore {
-        GitIgnore {
-            files
+impl Pattern {
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
         }
+        let rel = match self.base.as_str() {
+            "" => path,
+            base => match path.strip_prefix(base).and_then(|p| p.strip_prefix('/')) {
+                Some(rel) => rel,
+                None => return false,
+            },
+        };
+        // a pattern without a slash matches the name at any depth
+        let text = if self.anchored { rel } else { rel.rsplit('/').next().unwrap_or(rel) };
+        wildmatch(self.pattern.as_bytes(), text.as_bytes(), true)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source, self.line, self.text)
+    }
+}
+
+impl GitIgnore {
+    pub fn new() -> GitIgnore {
+        // ignores nothing but the repository itself
+        GitIgnore::default()
     }
 
     pub fn read_gitignore() -> io::Result<GitIgnore> {
-        let mut file = File::open(GITIGNORE)?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        let data = String::from_utf8(buf).or(Err(io::Error::from(io::ErrorKind::InvalidInput)))?;
-        let mut lines = data.split('\n')
-                        .filter(|&l| l != "")
-                        .map(|l| {
-                            if l.chars().next().unwrap() == '/' { format!(".{}", l) } else { format!("./{}", l) }
-                            // String::from(l)
-                        })
-                        .collect::<Vec<String>>();
-        if lines.len() != 0 && lines[lines.len()-1] == "" {
-            lines.pop().ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+        // core.excludesFile, then .git/info/exclude, then every .gitignore from the top down
+        let mut patterns = Vec::new();
+        if let Some(path) = excludes_file()? {
+            patterns.append(&mut read_patterns(&path, "")?);
         }
-        lines.push(format!("./{}", GIT_BASE_DIR));
-        Ok(GitIgnore::new(lines))
+        patterns.append(&mut read_patterns(Path::new(GIT_INFO_EXCLUDE), "")?);
+        Ok(GitIgnore { patterns, per_directory: true })
     }
 
-    pub fn is_ignored(&self, entry: &Path) -> bool {
-        if entry.eq(Path::new(".git")) {
-            return true
+    #[cfg(test)]
+    pub fn from_rules(data: &str, source: &str) -> GitIgnore {
+        // rules given as text, no .gitignore file is looked for
+        GitIgnore { patterns: parse_patterns(data, "", source), per_directory: false }
+    }
+
+    pub fn is_ignored(&self, entry: &Path) -> Option<Pattern> {
+        // the rule that decides the path, a negated one when it is explicitly kept
+        let components = entry.iter().filter_map(|c| c.to_str()).filter(|c| *c != ".").collect::<Vec<&str>>();
+        let mut patterns = self.patterns.clone();
+        let mut dir = String::new();
+        for (i, name) in components.iter().enumerate() {
+            // the repository itself is never subject to rules
+            if *name == GIT_BASE_DIR {
+                return None;
+            }
+            if self.per_directory {
+                patterns.append(&mut read_patterns(&Path::new(&dir).join(GITIGNORE), &dir).unwrap_or_default());
+            }
+            dir = if dir.is_empty() { String::from(*name) } else { format!("{}/{}", dir, name) };
+            let last = i + 1 == components.len();
+            let is_dir = !last || Path::new(&dir).is_dir();
+            match last_match(&patterns, &dir, is_dir) {
+                // a file inside an ignored directory stays ignored whatever follows
+                Some(pattern) if last || !pattern.negative => return Some(pattern.clone()),
+                _ => {},
+            }
         }
-        self.files.iter()
-            .map(|f| entry.starts_with(f))
-            .fold(false, |b, is_ignored| b || is_ignored )
+        None
     }
 
     pub fn walk_dir(&self) -> io::Result<Vec<String>> {
-        let files = self.walk_dir_recursive(".", Vec::new())?
-            .iter()
-            .map(|f| f.replacen("./", "", 1))
-            .collect::<Vec<String>>();
+        let mut patterns = self.patterns.clone();
+        let mut files = Vec::new();
+        self.walk_dir_recursive("", &mut patterns, &mut files)?;
+        files.sort();
         Ok(files)
     }
 
-    fn walk_dir_recursive(&self, path: &str, mut files: Vec<String>) -> io::Result<Vec<String>> {
-        let list = fs::read_dir(path)?
-                        .flat_map(|f| f)
-                        .filter(|file| !self.is_ignored(file.path().as_path()));
-        for l in list {
-            let path_buf = l.path();
-            let path = path_buf.as_path();
-            let path_str = String::from(path.to_str().ok_or(io::Error::from(io::ErrorKind::InvalidInput))?);
-            if path.is_dir() {
-                files = self.walk_dir_recursive(&path_str, files.clone())?;
+    fn walk_dir_recursive(&self, dir: &str, patterns: &mut Vec<Pattern>, files: &mut Vec<String>) -> io::Result<()> {
+        let count = patterns.len();
+        if self.per_directory {
+            patterns.append(&mut read_patterns(&Path::new(dir).join(GITIGNORE), dir)?);
+        }
+        for entry in fs::read_dir(if dir.is_empty() { "." } else { dir })? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().or(Err(io::Error::from(io::ErrorKind::InvalidData)))?;
+            if name == GIT_BASE_DIR {
+                continue;
+            }
+            let path = if dir.is_empty() { name } else { format!("{}/{}", dir, name) };
+            // symlinks are files, even when they point at a directory
+            let is_dir = entry.file_type()?.is_dir();
+            if is_excluded(patterns, &path, is_dir) {
+                continue;
+            }
+            // a nested repository looks after its own files
+            if is_dir && is_repository(&path) {
+                continue;
+            }
+            if is_dir {
+                self.walk_dir_recursive(&path, patterns, files)?;
             } else {
-                files.push(path_str);
+                files.push(path);
             }
         }
-        Ok(files)
+        patterns.truncate(count);
+        Ok(())
     }
 }
 
+fn last_match<'a>(patterns: &'a [Pattern], path: &str, is_dir: bool) -> Option<&'a Pattern> {
+    patterns.iter().rev().find(|p| p.matches(path, is_dir))
+}
+
+pub(super) fn is_excluded(patterns: &[Pattern], path: &str, is_dir: bool) -> bool {
+    last_match(patterns, path, is_dir).is_some_and(|p| !p.negative)
+}
+
+pub(super) fn is_repository(dir: &str) -> bool {
+    Path::new(dir).join(GIT_BASE_DIR).exists()
+}
+
+fn read_patterns(path: &Path, base: &str) -> io::Result<Vec<Pattern>> {
+    // a missing file has no patterns
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    Ok(parse_patterns(&String::from_utf8_lossy(&buf), base, &path.to_string_lossy()))
+}
+
+pub(super) fn parse_patterns(data: &str, base: &str, source: &str) -> Vec<Pattern> {
+    let mut patterns = Vec::new();
+    for (number, line) in data.lines().enumerate() {
+        if line.starts_with('#') {
+            continue;
+        }
+        // trailing spaces go unless the last one is escaped
+        let mut end = line.trim_end_matches(' ').len();
+        if end < line.len() && line[..end].ends_with('\\') {
+            end += 1;
+        }
+        let text = &line[..end];
+        let (negative, line) = match text.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // a slash anywhere but at the end ties the pattern to its directory
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+        patterns.push(Pattern {
+            pattern: String::from(line),
+            base: String::from(base),
+            negative,
+            dir_only,
+            anchored,
+            source: String::from(source),
+            line: number + 1,
+            text: String::from(text),
+        });
+    }
+    patterns
+}
 
+pub(super) fn excludes_file() -> io::Result<Option<PathBuf>> {
+    // core.excludesFile, by default $XDG_CONFIG_HOME/git/ignore
+    let home = env::var_os("HOME").map(PathBuf::from);
+    if let Some(path) = Config::read_config()?.get("core.excludesFile") {
+        return Ok(match (path.strip_prefix("~/"), &home) {
+            (Some(rest), Some(home)) => Some(home.join(rest)),
+            _ => Some(PathBuf::from(path)),
+        });
+    }
+    Ok(match env::var_os("XDG_CONFIG_HOME").filter(|x| !x.is_empty()) {
+        Some(xdg) => Some(PathBuf::from(xdg).join("git/ignore")),
+        None => home.map(|home| home.join(".config/git/ignore")),
+    })
+}
 
 #[cfg(test)]
 mod tests {
@@ -82,31 +228,58 @@ mod tests {
 
     #[test]
     fn test_read_gitignore() {
-        let line = super::GitIgnore::read_gitignore().unwrap();
-        assert_eq!(line.files[0], "./target");
-        assert_eq!(line.files[line.files.len()-1], format!("./{}", super::GIT_BASE_DIR));
+        let gitignore = super::GitIgnore::read_gitignore().unwrap();
+        assert_eq!(gitignore.is_ignored(Path::new("target")).unwrap().to_string(), ".gitignore:1:target/");
+        assert_eq!(gitignore.is_ignored(Path::new("Cargo.lock")).unwrap().pattern, "Cargo.lock");
+        assert!(gitignore.is_ignored(Path::new("src/main.rs")).is_none());
     }
     #[test]
     fn test_is_ignored() {
         let gitignore = super::GitIgnore::read_gitignore().unwrap();
         let git = Path::new(".git");
         let target = Path::new("./target/hoge/fuga");
-        assert_eq!(gitignore.is_ignored(git), true);
-        assert_eq!(gitignore.is_ignored(target), true);
+        assert!(gitignore.is_ignored(git).is_none());
+        assert_eq!(gitignore.is_ignored(target).unwrap().pattern, "target");
     }
     #[test]
-    fn test_walk_dir_recursive() {
-        let gitignore = super::GitIgnore::read_gitignore().unwrap();
-        let files: Vec<String> = Vec::new();
-        let f = gitignore.walk_dir_recursive(".", files).unwrap();
-        assert_eq!(f[0], "./src/main.rs");
+    fn test_parse_patterns() {
+        let patterns = super::parse_patterns("# comment\n\n*.log\n!keep.log\n/build/\ndoc/*.html\ntrail\\ \\ \nsp  \n\\#hash\n", "", ".gitignore");
+        let p = patterns.iter().map(|p| (p.pattern.as_str(), p.negative, p.dir_only, p.anchored)).collect::<Vec<_>>();
+        assert_eq!(patterns[1].to_string(), ".gitignore:4:!keep.log");
+        assert_eq!(patterns[2].to_string(), ".gitignore:5:/build/");
+        assert_eq!(p, vec![
+            ("*.log", false, false, false),
+            ("keep.log", true, false, false),
+            ("build", false, true, true),
+            ("doc/*.html", false, false, true),
+            ("trail\\ \\ ", false, false, false),
+            ("sp", false, false, false),
+            ("\\#hash", false, false, false),
+        ]);
+    }
+    #[test]
+    fn test_is_excluded() {
+        let mut patterns = super::parse_patterns("*.log\n!keep.log\n/build/\ndoc/**/*.html\n", "", ".gitignore");
+        patterns.append(&mut super::parse_patterns("*.tmp\n!keep.log\n", "sub", "sub/.gitignore"));
+        let excluded = |path: &str, is_dir: bool| super::is_excluded(&patterns, path, is_dir);
+        assert!(excluded("a.log", false));
+        assert!(excluded("x/y/a.log", false));
+        assert!(!excluded("keep.log", false));
+        assert!(excluded("build", true));
+        assert!(!excluded("build", false));
+        assert!(!excluded("x/build", true));
+        assert!(excluded("doc/index.html", false));
+        assert!(excluded("doc/api/index.html", false));
+        assert!(!excluded("x/doc/index.html", false));
+        assert!(excluded("sub/a.tmp", false));
+        assert!(!excluded("a.tmp", false));
     }
     #[test]
     fn test_walk_dir() {
         let gitignore = super::GitIgnore::read_gitignore().unwrap();
         let files = gitignore.walk_dir().unwrap();
-        assert_eq!(files[0], "src/main.rs");
-
+        assert!(files.iter().any(|f| f == "src/main.rs"));
+        assert!(!files.iter().any(|f| f.starts_with("target/") || f.starts_with(".git/")));
     }
     #[test]
     fn test_start_with() {
@@ -5,6 +5,8 @@ use std::io::Read;
 use std::io::Write;
 use std::fs::File;
 use std::fs;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
 #[cfg(target_os = "macos")]
 use std::os::macos::fs::MetadataExt;
 #[cfg(target_os = "linux")]
@@ -13,11 +15,25 @@ use chrono::{DateTime, TimeZone, Utc};
 use sha1::{Sha1, Digest};
 use crate::object::Object;
 use crate::object::blob::Blob;
-use crate::index::diff::DiffEntry;
+pub use crate::index::diff::DiffEntry;
+pub use crate::index::pathspec::Pathspec;
+pub use crate::index::ignore::{GitIgnore, Pattern};
+pub use crate::index::untracked::UntrackedCache;
+use crate::merge;
+use crate::merge::Version;
 use crate::cmd::cat_file::hash_key_to_path;
+use crate::worktree;
 
 mod diff;
 mod ignore;
+mod pathspec;
+mod untracked;
+
+// index files whose lock this process holds, and whether new content waits in the lock file
+static LOCKS: Mutex<Vec<(String, bool)>> = Mutex::new(Vec::new());
+
+// the hash of a blob with no content
+const EMPTY_BLOB: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
 
 #[derive(Debug, Clone)]
 pub struct Entry {
@@ -30,7 +46,16 @@ pub struct Entry {
     pub gid: u32,
     pub size: u32,
     pub hash: Vec<u8>,
+    // the 16 bit flags word is assume-valid, extended, 2 bits of stage and 12 bits of name length
+    pub assume_valid: bool,
+    // 0 for a normal entry, 1 (base), 2 (ours) and 3 (theirs) while a merge conflicts
+    pub stage: u16,
+    // the extended flags word that follows in version 3 and later, set by sparse checkout and add -N
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
     pub name: String,
+    // not stored: the stat data was just checked against the file, so it cannot hide a change
+    pub up_to_date: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -43,8 +68,16 @@ pub struct TreeEntry {
 
 #[derive(Debug, Clone)]
 pub struct Index {
+    // 2, 3 or 4, 0 for an index put together from scratch, which keeps the version of the one it replaces
+    pub version: u32,
     pub entries: Vec<Entry>,
     pub tree_entries: Vec<TreeEntry>,
+    // the UNTR extension, what each directory held the last time status looked
+    pub untracked_cache: Option<UntrackedCache>,
+    // optional extensions rusgit does not read, written back as they came
+    pub extensions: Vec<(Vec<u8>, Vec<u8>)>,
+    // when the index file was written, an entry modified at or after it may be racily clean
+    pub timestamp: Option<DateTime<Utc>>,
 }
 
 impl Entry {
@@ -61,25 +94,71 @@ impl Entry {
             gid,
             size,
             hash,
+            assume_valid: false,
+            stage: 0,
+            skip_worktree: false,
+            intent_to_add: false,
             name,
+            up_to_date: false,
         }
     }
 
+    pub fn from_stage(hash: Vec<u8>, name: &str, mode: u32, stage: u16) -> Entry {
+        // unmerged entries have no file behind them, so there is nothing to stat
+        let epoch = Utc.timestamp(0, 0);
+        let mut entry = Entry::new(epoch, epoch, 0, 0, mode, 0, 0, 0, hash, String::from(name));
+        entry.stage = stage;
+        entry
+    }
+
     pub fn from(data: &[u8]) -> Option<Entry> {
-        let c_time = hex_to_num(&data[0..4]);
-        let c_time_nano = hex_to_num(&data[4..8]);
-        let m_time = hex_to_num(&data[8..12]);
-        let m_time_nano = hex_to_num(&data[12..16]);
-        let dev = hex_to_num(&data[16..20]);
-        let inode = hex_to_num(&data[20..24]);
-        let mode = num_to_mode_num(hex_to_num(&data[24..28])).ok()?;
-        let uid = hex_to_num(&data[28..32]);
-        let gid = hex_to_num(&data[32..36]);
-        let size = hex_to_num(&data[36..40]);
-        let hash = Vec::from(&data[40..60]);
-        let name_size = hex_to_num(&data[60..62]);
-        let name = String::from_utf8(Vec::from(&data[62..(62 + name_size as usize)])).ok()?;
-        Some(Entry {
+        // a version 2 or 3 entry
+        Entry::from_version(data, 3, "").map(|(entry, _)| entry)
+    }
+
+    fn from_version(data: &[u8], version: u32, previous: &str) -> Option<(Entry, usize)> {
+        // the entry and the bytes it took; version 4 names only store what differs from the previous one
+        let data_head = data.get(0..62)?;
+        let c_time = hex_to_num(&data_head[0..4]);
+        let c_time_nano = hex_to_num(&data_head[4..8]);
+        let m_time = hex_to_num(&data_head[8..12]);
+        let m_time_nano = hex_to_num(&data_head[12..16]);
+        let dev = hex_to_num(&data_head[16..20]);
+        let inode = hex_to_num(&data_head[20..24]);
+        let mode = num_to_mode_num(hex_to_num(&data_head[24..28])).ok()?;
+        let uid = hex_to_num(&data_head[28..32]);
+        let gid = hex_to_num(&data_head[32..36]);
+        let size = hex_to_num(&data_head[36..40]);
+        let hash = Vec::from(&data_head[40..60]);
+        let flags = hex_to_num(&data_head[60..62]);
+        let assume_valid = flags & 0x8000 != 0;
+        let stage = ((flags >> 12) & 0x3) as u16;
+        let (skip_worktree, intent_to_add, head) = match flags & 0x4000 != 0 {
+            true => {
+                let extended = hex_to_num(data.get(62..64)?);
+                (extended & 0x4000 != 0, extended & 0x2000 != 0, 64)
+            },
+            false => (false, false, 62),
+        };
+        let (name, entry_size) = match version {
+            4 => {
+                let (strip, len) = decode_varint(data.get(head..)?)?;
+                let start = head + len;
+                let end = start + data.get(start..)?.iter().position(|&b| b == b'\0')?;
+                let kept = &previous.as_bytes()[..previous.len().checked_sub(strip)?];
+                (String::from_utf8([kept, &data[start..end]].concat()).ok()?, end + 1)
+            },
+            _ => {
+                // names of 0xfff bytes or longer are only terminated by \0
+                let name_size = match flags & 0x0fff {
+                    0x0fff => data.get(head..)?.iter().position(|&b| b == b'\0')?,
+                    size => size as usize,
+                };
+                let name = String::from_utf8(Vec::from(data.get(head..(head + name_size))?)).ok()?;
+                (name, padded_size(head + name_size))
+            },
+        };
+        let entry = Entry {
             c_time: Utc.timestamp(c_time.into(), c_time_nano),
             m_time: Utc.timestamp(m_time.into(), m_time_nano),
             dev,
@@ -89,13 +168,19 @@ impl Entry {
             gid,
             size,
             hash,
+            assume_valid,
+            stage,
+            skip_worktree,
+            intent_to_add,
             name,
-        })
+            up_to_date: false,
+        };
+        Some((entry, entry_size))
     }
 
     #[cfg(target_os = "linux")]
     pub fn from_name(hash: Vec<u8>, name: &str) -> io::Result<Entry> {
-        let metadata = fs::metadata(name)?;
+        let metadata = fs::symlink_metadata(name)?;
         let c_time = metadata.st_ctime() as u32;
         let c_time_nano = metadata.st_ctime_nsec() as u32;
         let m_time = metadata.st_mtime() as u32;
@@ -105,18 +190,23 @@ impl Entry {
             m_time: Utc.timestamp(m_time.into(), m_time_nano),
             dev: metadata.st_dev() as u32,
             inode: metadata.st_ino() as u32,
-            mode: metadata.st_mode(),
+            mode: normalize_mode(metadata.st_mode()),
             uid: metadata.st_uid(),
             gid: metadata.st_gid(),
             size: metadata.st_size() as u32,
             hash,
+            assume_valid: false,
+            stage: 0,
+            skip_worktree: false,
+            intent_to_add: false,
             name: String::from(name),
+            up_to_date: true,
         })
     }
 
     #[cfg(target_os = "macos")]
     pub fn from_name(hash: Vec<u8>, name: &str) -> io::Result<Entry> {
-        let metadata = fs::metadata(name)?;
+        let metadata = fs::symlink_metadata(name)?;
         let c_time = metadata.st_ctime() as u32;
         let c_time_nano = metadata.st_ctime_nsec() as u32;
         let m_time = metadata.st_mtime() as u32;
@@ -126,41 +216,88 @@ impl Entry {
             m_time: Utc.timestamp(m_time.into(), m_time_nano),
             dev: metadata.st_dev() as u32,
             inode: metadata.st_ino() as u32,
-            mode: metadata.st_mode(),
+            mode: normalize_mode(metadata.st_mode()),
             uid: metadata.st_uid(),
             gid: metadata.st_gid(),
             size: metadata.st_size() as u32,
             hash,
+            assume_valid: false,
+            stage: 0,
+            skip_worktree: false,
+            intent_to_add: false,
             name: String::from(name),
+            up_to_date: true,
         })
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
+        // as a version 2 or 3 entry
+        self.as_bytes_version(3, "")
+    }
+
+    fn as_bytes_version(&self, version: u32, previous: &str) -> Vec<u8> {
         let c_time = self.c_time.timestamp() as u32;
         let c_time_nano = self.c_time.timestamp_subsec_nanos();
         let m_time = self.m_time.timestamp() as u32;
         let m_time_nano = self.m_time.timestamp_subsec_nanos();
+        let mode = mode_to_num(&self.mode.to_string()).unwrap_or(0);
         let metadata = [c_time, c_time_nano, m_time, m_time_nano, 
-                        self.dev, self.inode, self.mode, self.uid, self.gid, self.size]
+                        self.dev, self.inode, mode, self.uid, self.gid, self.size]
                 .iter()
                 .flat_map(|&d| Vec::from(d.to_be_bytes()))
                 .collect::<Vec<u8>>();
-        let name_size = self.name.len() as u16;
-        let name = self.name.as_bytes();
-        let name_offset = 62 + name_size as usize;
-        let padding = (0..(8 - name_offset % 8)).map(|_| b'\0').collect::<Vec<u8>>();
-        [metadata, self.hash.clone(), Vec::from(name_size.to_be_bytes()), name.to_vec(), padding].concat()
+        let name_size = self.name.len();
+        let flags = (self.assume_valid as u16) << 15
+                    | (self.is_extended() as u16) << 14
+                    | (self.stage & 0x3) << 12
+                    | name_size.min(0x0fff) as u16;
+        let extended = match self.is_extended() {
+            true => Vec::from(((self.skip_worktree as u16) << 14 | (self.intent_to_add as u16) << 13).to_be_bytes()),
+            false => Vec::new(),
+        };
+        let name = match version {
+            4 => {
+                // how much of the previous name to drop, then the rest of this one
+                let common = previous.bytes().zip(self.name.bytes()).take_while(|(a, b)| a == b).count();
+                [encode_varint(previous.len() - common), Vec::from(&self.name.as_bytes()[common..]), vec![b'\0']].concat()
+            },
+            _ => {
+                let name_offset = 62 + extended.len() + name_size;
+                let padding = vec![b'\0'; padded_size(name_offset) - name_offset];
+                [self.name.as_bytes(), &padding].concat()
+            },
+        };
+        [metadata, self.hash.clone(), Vec::from(flags.to_be_bytes()), extended, name].concat()
     }
 
     pub fn size(&self) -> usize {
-        let size = 62 + self.name.len();
-        size + (8 - size % 8)
+        // as a version 2 or 3 entry
+        padded_size(62 + if self.is_extended() { 2 } else { 0 } + self.name.len())
+    }
+
+    pub fn is_extended(&self) -> bool {
+        // only an entry with an extended flag set needs the word, and version 3
+        self.skip_worktree || self.intent_to_add
+    }
+
+    pub fn matches_stat(&self, metadata: &fs::Metadata) -> bool {
+        // what git compares by default: both times, inode, owner, size and the kind of file
+        let time = |t: &DateTime<Utc>| (t.timestamp() as u32, t.timestamp_subsec_nanos());
+        time(&self.m_time) == (metadata.st_mtime() as u32, metadata.st_mtime_nsec() as u32)
+            && time(&self.c_time) == (metadata.st_ctime() as u32, metadata.st_ctime_nsec() as u32)
+            && self.inode == metadata.st_ino() as u32
+            && self.uid == metadata.st_uid()
+            && self.gid == metadata.st_gid()
+            && self.size == metadata.st_size() as u32
+            && self.mode == normalize_mode(metadata.st_mode())
+            // a size of 0 is stat data never taken, or smudged because it was racy
+            && (self.size != 0 || hex::encode(&self.hash) == EMPTY_BLOB)
     }
 }
 
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} {} 0\t{}", self.mode, hex::encode(&self.hash), self.name)
+        write!(f, "{} {} {}\t{}", self.mode, hex::encode(&self.hash), self.stage, self.name)
     }
 }
 
@@ -175,40 +312,16 @@ impl TreeEntry {
     }
 
     pub fn from(data: &[u8]) -> Option<TreeEntry> {
-        // let path_tail = data.iter_mut().position(|&mut d| d == b'\0')? as usize;
-
-        let mut path_tail = 0;
-        let mut entries_tail = 0;
-        let mut subtrees_tail = 0;
-        for (i, d) in data.iter().enumerate() {
-            if *d == b'\0' {
-                path_tail = i;
-                break;
-            }
-        }
-        for (i, d) in data.iter().enumerate() {
-            if *d == 0x20 {
-                entries_tail = i;
-                break;
-            }
-        }
-        for (i, d) in data.iter().enumerate() {
-            if *d == 0x0a {
-                subtrees_tail = i;
-                break;
-            }
-        }
+        // <path>\0<entries> <subtrees>\n<hash>, counted from the end of the path, which may hold spaces
+        let path_tail = data.iter().position(|&d| d == b'\0')?;
+        let counts_tail = path_tail + data[path_tail..].iter().position(|&d| d == b'\n')?;
+        let (entries, subtrees) = str::from_utf8(&data[(path_tail + 1)..counts_tail]).ok()?.split_once(' ')?;
         let path = if path_tail == 0 { "." } else { str::from_utf8(&data[0..path_tail]).ok()? };
-        let entries = str::from_utf8(&data[(path_tail+1)..entries_tail]).ok()?
-                        .parse::<i32>().ok()?;
-        let subtrees = str::from_utf8(&data[(entries_tail+1)..subtrees_tail]).ok()?
-                    .parse::<usize>().ok()?;
-        let hash = data[(subtrees_tail+1)..].to_vec();
         Some(TreeEntry {
             path: String::from(path),
-            entries,
-            subtrees,
-            hash,
+            entries: entries.parse::<i32>().ok()?,
+            subtrees: subtrees.parse::<usize>().ok()?,
+            hash: data[(counts_tail + 1)..].to_vec(),
         })
     }
 
@@ -230,65 +343,104 @@ impl TreeEntry {
         let entries_counter = self.entries.to_string().len();
         let sub_trees_counter = self.subtrees.to_string().len();
         let path_len = if self.path == "." { 0 } else { self.path.len() };
-        path_len + 23 + entries_counter + sub_trees_counter
+        // an invalidated tree has no hash
+        path_len + 3 + entries_counter + sub_trees_counter + self.hash.len()
     }
 }
 
 impl Index {
     pub fn new(entries: Vec<Entry>, tree_entries: Vec<TreeEntry>) -> Index {
         Index {
+            version: 0,
             entries,
             tree_entries,
+            untracked_cache: None,
+            extensions: Vec::new(),
+            timestamp: None,
         }
     }
 
-    pub fn from(data: &[u8]) -> Option<Index> {
-        if &data[0..4] != b"DIRC" { 
-            return None;
+    pub fn from(data: &[u8]) -> io::Result<Index> {
+        let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "index file corrupt");
+        if data.len() < 32 || &data[0..4] != b"DIRC" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad index file signature"));
+        }
+        let version = hex_to_num(&data[4..8]);
+        if !(2..=4).contains(&version) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad index version {}", version)));
         }
-        if hex_to_num(&data[4..8]) != 2 {
-            return None;
+        // a checksum of zeros was never computed, as git writes it with index.skipHash
+        let (content, checksum) = data.split_at(data.len() - 20);
+        if checksum.iter().any(|&b| b != 0) && Sha1::digest(content).as_slice() != checksum {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad index file sha1 signature"));
         }
         // entry
-        let entry_size = hex_to_num(&data[8..12]);
-        let entries = (0..entry_size).try_fold((0, Vec::new()), |(offset, mut entries), _| {
-            let entry = Entry::from(&data[(12 + offset)..])?;
-            let size = entry.size();
+        let entry_size = hex_to_num(&content[8..12]);
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut total = 12;
+        for _ in 0..entry_size {
+            let previous = entries.last().map_or("", |e| e.name.as_str());
+            let (entry, size) = content.get(total..)
+                                .and_then(|data| Entry::from_version(data, version, previous))
+                                .ok_or_else(corrupt)?;
             entries.push(entry);
-            Some((offset + size, entries))
-        })
-        .map(|(_, entries)| entries)?;
-        let total = entries.iter().fold(0, |total, entry| total + entry.size()) + 12;
-        if data.len() == total {
-            return Some(Index::new(entries, Vec::new()));
+            total += size;
         }
-        if &data[total..(total+4)] != b"TREE" {
-            return Some(Index::new(entries, Vec::new()));
+        let mut index = Index { version, ..Index::new(entries, Vec::new()) };
+        // extensions follow the entries, each a signature and a length, up to the checksum
+        let mut offset = total;
+        while offset < content.len() {
+            let header = content.get(offset..(offset + 8)).ok_or_else(corrupt)?;
+            let size = hex_to_num(&header[4..8]) as usize;
+            let ext = content.get((offset + 8)..(offset + 8 + size)).ok_or_else(corrupt)?;
+            match &header[0..4] {
+                b"TREE" => index.tree_entries = tree_entrties_from_bytes(ext).ok_or_else(corrupt)?,
+                b"UNTR" => index.untracked_cache = UntrackedCache::from(ext),
+                // these point at entries by position, which a rewrite moves, so git builds them again
+                b"EOIE" | b"IEOT" | b"FSMN" => {},
+                // an upper case letter marks an extension a reader may do without
+                signature if signature[0].is_ascii_uppercase() => index.extensions.push((Vec::from(signature), Vec::from(ext))),
+                signature => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("index uses {} extension, which we do not understand", String::from_utf8_lossy(signature)))),
+            }
+            offset += 8 + size;
         }
-        let tree_entry_size = hex_to_num(&data[(total+4)..(total+8)]);
-        let tree_entries = tree_entrties_from_bytes(&data[(total+8)..])?;
-        Some(Index::new(entries, tree_entries))
+        Ok(index)
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        let hdr = [*b"DIRC", [0x00, 0x00, 0x00, 0x02], (self.entries.len() as u32).to_be_bytes()].concat();
+        // version 2 turns into 3 and back as entries with extended flags come and go, 4 stays
+        let version: u32 = match self.version {
+            4 => 4,
+            _ if self.entries.iter().any(|e| e.is_extended()) => 3,
+            _ => 2,
+        };
+        let hdr = [*b"DIRC", version.to_be_bytes(), (self.entries.len() as u32).to_be_bytes()].concat();
         let entries = self.entries.iter()
-                        .flat_map(|d| d.as_bytes())
+                        .scan("", |previous, e| {
+                            let bytes = e.as_bytes_version(version, previous);
+                            *previous = &e.name;
+                            Some(bytes)
+                        })
+                        .flatten()
                         .collect::<Vec<u8>>();
-        let content = [hdr, entries].concat();
-        if self.tree_entries.len() == 0 {
-            let hash = Vec::from(Sha1::digest(&content).as_slice());
-            return [content, hash].concat();
-        }
+        let mut content = [hdr, entries].concat();
         // if tree section is exist.
-        let tree_entry_hdr = [*b"TREE", (self.tree_entries_size() as u32).to_be_bytes()].concat();
-        let tree_entrries = self.tree_entries.iter()
-                            .flat_map(|e| e.as_bytes())
-                            .collect::<Vec<u8>>();
-        let tree_entries_content = [tree_entry_hdr, tree_entrries].concat();
-        let total = [content, tree_entries_content].concat();
-        let hash = Vec::from(Sha1::digest(&total).as_slice());
-        [total, hash].concat()
+        if !self.tree_entries.is_empty() {
+            let tree_entry_hdr = [*b"TREE", (self.tree_entries_size() as u32).to_be_bytes()].concat();
+            let tree_entrries = self.tree_entries.iter()
+                                .flat_map(|e| e.as_bytes())
+                                .collect::<Vec<u8>>();
+            content.extend([tree_entry_hdr, tree_entrries].concat());
+        }
+        if let Some(cache) = &self.untracked_cache {
+            let untracked = cache.as_bytes();
+            content.extend([Vec::from(*b"UNTR"), Vec::from((untracked.len() as u32).to_be_bytes()), untracked].concat());
+        }
+        for (signature, ext) in self.extensions.iter() {
+            content.extend([signature.clone(), Vec::from((ext.len() as u32).to_be_bytes()), ext.clone()].concat());
+        }
+        let hash = Vec::from(Sha1::digest(&content).as_slice());
+        [content, hash].concat()
     }
 
     fn tree_entries_size(&self) -> usize {
@@ -296,65 +448,165 @@ impl Index {
     }
 
     pub fn diff(&self) -> io::Result<Vec<DiffEntry>> {
-        let new_blobs = self.entries.iter()
-                        .map(|e| Blob::from_name(&e.name).unwrap())
-                        .collect::<Vec<Blob>>();
-        let old_blobs = self.entries.iter()
-                        .map(|e| {
-                            let hash = hex::encode(e.hash.clone());
-                            Blob::from_hash_file(&hash_key_to_path(&hash)).unwrap()
-                        })
-                        .collect::<Vec<Blob>>();
-        let names = self.entries.iter()
-                        .map(|e| e.name.clone())
-                        .collect::<Vec<String>>();
-        let diff_entries: Vec<DiffEntry> = (0..(names.len()))
-                        .map(|i| {
-                            let metadata = fs::metadata(&self.entries[i].name).unwrap();
-                            let new_mode = num_to_mode_num(metadata.st_mode()).unwrap();
-                            let old_mode = self.entries[i].mode;
-                            DiffEntry::new(&names[i], new_blobs[i].clone(), old_blobs[i].clone(), new_mode, old_mode)
-                        })
-                        .filter(|e| e.is_modified())
-                        .collect();
+        // unmerged paths have no single version to compare against
+        let mut diff_entries: Vec<DiffEntry> = Vec::new();
+        for entry in self.entries.iter().filter(|e| e.stage == 0 && !e.skip_worktree) {
+            // a deleted file is an empty side with mode 0
+            let (new, new_mode) = match fs::symlink_metadata(&entry.name) {
+                Ok(metadata) if self.is_unchanged(entry, &metadata) => continue,
+                Ok(metadata) => (Blob::from_name(&entry.name)?, normalize_mode(metadata.st_mode())),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (Blob::new(""), 0),
+                Err(e) => return Err(e),
+            };
+            // a path added with -N is new next to an empty side
+            let (old, old_mode) = match entry.intent_to_add {
+                true => (Blob::new(""), 0),
+                false => (Blob::from_hash_file(&hash_key_to_path(&hex::encode(&entry.hash)))?, entry.mode),
+            };
+            let diff_entry = DiffEntry::new(&entry.name, new, old, new_mode, old_mode);
+            if diff_entry.is_modified() {
+                diff_entries.push(diff_entry);
+            }
+        }
         Ok(diff_entries)
     }
 
-    pub fn tracked_files(&self) -> io::Result<Vec<String>> {
-        let gitignore = ignore::GitIgnore::read_gitignore()?;
-        gitignore.walk_dir()
+    pub fn refresh(&mut self, really: bool) -> io::Result<(Vec<String>, bool)> {
+        // hash only the files whose stat data cannot vouch for them, or every file when really;
+        // the ones that still match get fresh stat data, the paths returned no longer match
+        // their entries and the flag tells whether any entry changed
+        let mut stale: Vec<String> = Vec::new();
+        let mut refreshed = false;
+        for i in 0..self.entries.len() {
+            let entry = &self.entries[i];
+            if entry.stage != 0 || entry.mode == 160000 || entry.skip_worktree {
+                continue;
+            }
+            // a path added with -N has nothing staged yet, whatever the file holds
+            if entry.intent_to_add {
+                stale.push(entry.name.clone());
+                continue;
+            }
+            let metadata = match fs::symlink_metadata(&entry.name) {
+                Ok(metadata) => metadata,
+                Err(_) if entry.assume_valid && !really => continue,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    stale.push(entry.name.clone());
+                    continue;
+                },
+                Err(e) => return Err(e),
+            };
+            if !really && self.is_unchanged(entry, &metadata) {
+                continue;
+            }
+            if entry.mode != normalize_mode(metadata.st_mode()) || worktree::file_hash(&entry.name).as_ref() != Some(&entry.hash) {
+                stale.push(entry.name.clone());
+                continue;
+            }
+            if !entry.matches_stat(&metadata) {
+                self.entries[i] = Entry { assume_valid: entry.assume_valid, ..Entry::from_name(entry.hash.clone(), &entry.name)? };
+                refreshed = true;
+            }
+            self.entries[i].up_to_date = true;
+        }
+        Ok((stale, refreshed))
+    }
+
+    pub fn is_racy(&self, entry: &Entry) -> bool {
+        // a file changed within the timestamp granularity of the index write keeps matching stat data
+        self.timestamp.is_some_and(|timestamp| entry.m_time >= timestamp)
     }
 
-    pub fn untracked_files(&self) -> io::Result<Vec<String>> {
-        let mut files_index = 0;
-        let mut staged_index = 0;
-        let gitignore = ignore::GitIgnore::read_gitignore()?;
-        let mut files = gitignore.walk_dir()?;
-        let mut staged = self.entries.iter().map(|e| e.name.clone()).collect::<Vec<String>>();
-        staged.sort();
-        files.sort();
-        let mut untracked: Vec<String> = Vec::new();
-        for _i in 0..files.len() {
-            if staged.len() > staged_index {
-                if files[files_index] == self.entries[staged_index].name {
-                    files_index += 1;
-                    staged_index += 1;
-                } else {
-                    untracked.push(files[files_index].clone());
-                    files_index += 1;
-                }
+    pub fn is_unchanged(&self, entry: &Entry, metadata: &fs::Metadata) -> bool {
+        // the stat data vouches for the file unless it is as new as the index
+        if entry.intent_to_add {
+            return false;
+        }
+        entry.assume_valid || entry.skip_worktree || entry.mode == 160000 || (entry.matches_stat(metadata) && !self.is_racy(entry))
+    }
+
+    pub fn unmerged(&self) -> Vec<(String, u8)> {
+        // each unmerged path with a bit set for every stage it has, 0b001 for stage 1 to 0b100 for stage 3
+        let mut unmerged: Vec<(String, u8)> = Vec::new();
+        for entry in self.entries.iter().filter(|e| e.stage != 0) {
+            let bit = 1 << (entry.stage - 1);
+            match unmerged.last_mut() {
+                Some((name, stages)) if *name == entry.name => *stages |= bit,
+                _ => unmerged.push((entry.name.clone(), bit)),
             }
-            untracked.push(files[files_index].clone());
-            files_index += 1;
-            if files_index >= files.len() {
-                break;
+        }
+        unmerged
+    }
+
+    pub fn invalidate_trees<I, S>(&mut self, names: I) where I: IntoIterator<Item = S>, S: AsRef<str> {
+        // only the trees above a path that changed have to be written again
+        for name in names {
+            self.invalidate_tree(name.as_ref());
+        }
+    }
+
+    pub fn invalidate_tree(&mut self, name: &str) {
+        // the root and every directory above the path no longer match their tree objects,
+        // a cached directory of the same name is dropped as the path now stands in its place
+        let mut node = 0;
+        let mut components = name.split('/').peekable();
+        while let Some(component) = components.next() {
+            let tree_entry = match self.tree_entries.get_mut(node) {
+                Some(tree_entry) => tree_entry,
+                None => return,
+            };
+            tree_entry.entries = -1;
+            tree_entry.hash.clear();
+            let child = match self.find_subtree(node, component) {
+                Some(child) => child,
+                None => return,
+            };
+            if components.peek().is_none() {
+                let span = self.subtree_span(child);
+                self.tree_entries.drain(child..(child + span));
+                self.tree_entries[node].subtrees -= 1;
+                return;
             }
+            node = child;
         }
-        Ok(untracked)
     }
-}
 
+    fn find_subtree(&self, node: usize, name: &str) -> Option<usize> {
+        // cached trees are listed in preorder, a sibling follows everything below the one before it
+        let mut child = node + 1;
+        for _ in 0..self.tree_entries.get(node)?.subtrees {
+            if self.tree_entries.get(child)?.path == name {
+                return Some(child);
+            }
+            child += self.subtree_span(child);
+        }
+        None
+    }
 
+    fn subtree_span(&self, node: usize) -> usize {
+        // how many cached trees the node and its descendants take up
+        let mut end = node;
+        let mut pending = 1;
+        while pending > 0 && end < self.tree_entries.len() {
+            pending += self.tree_entries[end].subtrees;
+            pending -= 1;
+            end += 1;
+        }
+        end - node
+    }
+
+    pub fn untracked_files(&self) -> io::Result<Vec<String>> {
+        // every file in the working tree that no index entry names
+        let names = self.entries.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>();
+        Ok(untracked::untracked_paths(&names, false, false, None)?.0)
+    }
+
+    pub fn untracked_paths(&mut self, collapse: bool, ignored: bool) -> io::Result<(Vec<String>, Vec<String>)> {
+        // untracked and ignored paths as status lists them, a wholly untracked directory as dir/ when collapsed
+        let names = self.entries.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>();
+        untracked::untracked_paths(&names, collapse, ignored, self.untracked_cache.as_mut())
+    }
+}
 
 impl fmt::Display for Index {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -366,76 +618,301 @@ impl fmt::Display for Index {
 
 pub fn tree_entrties_from_bytes(data: &[u8]) -> Option<Vec<TreeEntry>> {
     // <name>\0<entries> <sub trees>\n<hash><name>\0<entries> <sub trees>\n<hash><name>
+    // an invalidated tree has -1 entries and no hash
     let mut tree_entries: Vec<TreeEntry> = Vec::new();
-    let splitter_offsets: Vec<usize> = data.iter().enumerate()
-                            .filter(|(_, &d)| d == b'\n' )
-                            .map(|(off, _)| off + 21)
-                            .collect();
-    if splitter_offsets.len() == 0 {
-        return Some(tree_entries);
-    }
-    let mut tails: Vec<usize> = Vec::new();
-    tails.push(splitter_offsets[0]); // first
-    let mut i = splitter_offsets[0];
-    for o in splitter_offsets {
-        if o - i > 20 && data.len() > o {
-            tails.push(o);
-        }
-        i = o;
-    }
     let mut head = 0;
-    for tail in tails {
-        let tree_entry = TreeEntry::from(&data[head..tail])?;
+    while head < data.len() {
+        let name_tail = head + data[head..].iter().position(|&d| d == b'\0')?;
+        let newline = name_tail + data[name_tail..].iter().position(|&d| d == b'\n')?;
+        let tail = if data[(name_tail + 1)..].starts_with(b"-1 ") { newline + 1 } else { newline + 21 };
+        let tree_entry = TreeEntry::from(data.get(head..tail)?)?;
         tree_entries.push(tree_entry);
         head = tail;
     }
     Some(tree_entries)
 }
 
+pub struct IndexLock {
+    index_path: String,
+    // false when the process already held the lock, the outer holder commits it
+    owner: bool,
+}
+
+impl IndexLock {
+    pub fn commit(mut self) -> io::Result<()> {
+        // what was written to the lock file replaces the index in one rename
+        if !self.owner {
+            return Ok(());
+        }
+        self.owner = false;
+        let lock_path = format!("{}.lock", self.index_path);
+        if unlock(&self.index_path) {
+            fs::rename(&lock_path, &self.index_path)
+        } else {
+            fs::remove_file(&lock_path)
+        }
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        // a lock nobody committed is rolled back, the index stays as it was
+        if self.owner {
+            unlock(&self.index_path);
+            let _ = fs::remove_file(format!("{}.lock", self.index_path));
+        }
+    }
+}
+
+fn unlock(index_path: &str) -> bool {
+    // forget the lock, telling whether new content was waiting in it
+    let mut locks = LOCKS.lock().unwrap();
+    let pending = locks.iter().any(|(path, pending)| path == index_path && *pending);
+    locks.retain(|(path, _)| path != index_path);
+    pending
+}
+
+pub fn with_lock<T, F>(index_path: &str, f: F) -> io::Result<T>
+    where F: FnOnce() -> io::Result<T>
+{
+    // the index is locked while f runs, and what it wrote takes effect only when it succeeds
+    let lock = lock_index(index_path)?;
+    let result = f()?;
+    lock.commit()?;
+    Ok(result)
+}
+
+pub fn lock_index(index_path: &str) -> io::Result<IndexLock> {
+    // index.lock keeps other processes out until the new index is in place,
+    // a caller already holding it may lock again and leaves the commit to the holder
+    let mut locks = LOCKS.lock().unwrap();
+    if locks.iter().any(|(path, _)| path == index_path) {
+        return Ok(IndexLock { index_path: String::from(index_path), owner: false });
+    }
+    let lock_path = format!("{}.lock", index_path);
+    match fs::OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+        Ok(_) => {},
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            let lock_path = std::env::current_dir().map(|dir| dir.join(&lock_path)).unwrap_or_default();
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("Unable to create '{}': File exists.\n\nAnother git process seems to be running in this repository, e.g.\nan editor opened by 'git commit'. Please make sure all processes\nare terminated then try again. If it still fails, a git process\nmay have crashed in this repository earlier:\nremove the file manually to continue.", lock_path.display())));
+        },
+        Err(e) => return Err(e),
+    }
+    locks.push((String::from(index_path), false));
+    Ok(IndexLock { index_path: String::from(index_path), owner: true })
+}
+
+fn locked_path(index_path: &str) -> String {
+    // an index written under a lock still held is read from the lock file
+    let locks = LOCKS.lock().unwrap();
+    if locks.iter().any(|(path, pending)| path == index_path && *pending) {
+        format!("{}.lock", index_path)
+    } else {
+        String::from(index_path)
+    }
+}
+
 pub fn read_index(index_path: &str) -> io::Result<Index> {
-    let mut file = match File::open(index_path) {
+    let mut file = match File::open(locked_path(index_path)) {
         Ok(file) => file,
         Err(err) => {
             if err.kind() != io::ErrorKind::NotFound {
                 return Err(err);
             }
-            File::create(index_path)?;
             return Ok(Index::new(Vec::new(), Vec::new()));
         }
     };
     let mut buf = Vec::new();
     file.read_to_end(&mut buf)?;
-    let index = Index::from(&buf).ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+    if buf.is_empty() {
+        return Ok(Index::new(Vec::new(), Vec::new()));
+    }
+    let mut index = Index::from(&buf)?;
+    index.timestamp = file.metadata()?.modified().ok().map(DateTime::<Utc>::from);
     Ok(index)
 }
 
 pub fn write_index(index_path: &str, index: &Index) -> io::Result<()> {
-    let mut file = File::create(index_path)?;
-    file.write(&mut index.as_bytes())?;
+    let lock = lock_index(index_path)?;
+    let mut index = index.clone();
+    if index.version == 0 {
+        index.version = read_index_version(index_path)?;
+    }
+    // a racy entry nobody checked may hide a change, a size of 0 makes the next look hash the file
+    let timestamp = index.timestamp;
+    for entry in index.entries.iter_mut().filter(|e| e.stage == 0 && !e.up_to_date && e.mode != 160000) {
+        if timestamp.is_some_and(|timestamp| entry.m_time >= timestamp) && worktree::file_hash(&entry.name).as_ref() != Some(&entry.hash) {
+            entry.size = 0;
+        }
+    }
+    if let Some(cache) = index.untracked_cache.as_mut() {
+        // a path that came or went changes what its directories hold
+        let old = read_index(index_path)?.entries.into_iter().map(|e| e.name).collect::<BTreeSet<String>>();
+        let new = index.entries.iter().map(|e| e.name.clone()).collect::<BTreeSet<String>>();
+        old.symmetric_difference(&new).for_each(|name| cache.invalidate(name));
+    }
+    let mut file = File::create(format!("{}.lock", index_path))?;
+    file.write_all(&index.as_bytes())?;
+    file.sync_all()?;
+    for (_, pending) in LOCKS.lock().unwrap().iter_mut().filter(|(path, _)| path == index_path) {
+        *pending = true;
+    }
+    lock.commit()
+}
 
-    Ok(())
+fn read_index_version(index_path: &str) -> io::Result<u32> {
+    // the version in the header of the index file, 2 when there is none yet
+    let mut header = [0u8; 8];
+    match File::open(locked_path(index_path)).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(()) if &header[0..4] == b"DIRC" => Ok(hex_to_num(&header[4..8])),
+        Ok(()) => Ok(2),
+        Err(e) if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::UnexpectedEof => Ok(2),
+        Err(e) => Err(e),
+    }
 }
 
 pub fn update_index(index: Index, hash: Vec<u8>, name: &str) -> io::Result<Index> {
     let entry = Entry::from_name(hash, name)?;
+    // staging a path also resolves its conflict stages
     let mut entries: Vec<Entry> = index.entries.into_iter()
-                    .filter(|e| e.name != entry.name && e.hash != entry.hash)
+                    .filter(|e| e.name != entry.name)
                     .collect();
     entries.push(entry);
-    entries.sort_by(|a, b| a.name.cmp(&b.name));
-    Ok(Index::new(entries, index.tree_entries))
+    entries.sort_by(|a, b| (&a.name, a.stage).cmp(&(&b.name, b.stage)));
+    let mut index = Index { entries, ..index };
+    index.invalidate_tree(name);
+    Ok(index)
 }
 
 pub fn update_index_cacheinfo(index: Index, mode: &str, hash: Vec<u8>, name: &str) -> io::Result<Index> {
     let mut entry = Entry::from_name(hash, name)?;
-    entry.mode = mode_to_num(mode)?;
+    entry.mode = num_to_mode_num(mode_to_num(mode)?)?;
     let mut entries: Vec<Entry> = index.entries.into_iter()
-                    .filter(|e| e.name != entry.name && e.hash != entry.hash)
+                    .filter(|e| e.name != entry.name)
                     .collect();
     entries.push(entry);
-    entries.sort_by(|a, b| a.name.cmp(&b.name));
-    Ok(Index::new(entries, index.tree_entries))
+    entries.sort_by(|a, b| (&a.name, a.stage).cmp(&(&b.name, b.stage)));
+    let mut index = Index { entries, ..index };
+    index.invalidate_tree(name);
+    Ok(index)
+}
+
+pub fn remove_index(index: Index, name: &str) -> io::Result<Index> {
+    // every stage of the path goes
+    if !index.entries.iter().any(|e| e.name == name) {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("'{}' is not in the index", name)));
+    }
+    let entries: Vec<Entry> = index.entries.into_iter()
+                    .filter(|e| e.name != name)
+                    .collect();
+    let mut index = Index { entries, ..index };
+    index.invalidate_tree(name);
+    Ok(index)
+}
+
+pub fn rename_index(index: Index, from: &str, to: &str) -> io::Result<Index> {
+    // the entry keeps its blob, mode and stat data under the new name
+    if index.entries.iter().any(|e| e.name == to) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("'{}' is already in the index", to)));
+    }
+    if !index.entries.iter().any(|e| e.name == from && e.stage == 0) {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("'{}' is not in the index", from)));
+    }
+    let mut entries = index.entries;
+    for entry in entries.iter_mut().filter(|e| e.name == from) {
+        entry.name = String::from(to);
+    }
+    entries.sort_by(|a, b| (&a.name, a.stage).cmp(&(&b.name, b.stage)));
+    let mut index = Index { entries, ..index };
+    index.invalidate_tree(from);
+    index.invalidate_tree(to);
+    Ok(index)
+}
+
+pub fn replace_paths(index: Index, names: &BTreeSet<String>, mut entries: Vec<Entry>) -> Index {
+    // the given entries take the place of every stage of the named paths, the rest of the index stays as it was
+    entries.extend(index.entries.into_iter().filter(|e| !names.contains(&e.name)));
+    entries.sort_by(|a, b| (&a.name, a.stage).cmp(&(&b.name, b.stage)));
+    let mut index = Index { entries, ..index };
+    index.invalidate_trees(names);
+    index
+}
+
+pub fn diff_trees(old: Option<&str>, new: Option<&str>) -> io::Result<Vec<DiffEntry>> {
+    // paths that differ between two trees, a missing side is empty with mode 0
+    let old = merge::read_tree_files(old)?;
+    let new = merge::read_tree_files(new)?;
+    let names = old.keys().chain(new.keys()).collect::<BTreeSet<&String>>();
+    let read = |version: Option<&Version>| match version {
+        Some(version) => Blob::from_hash_file(&hash_key_to_path(&hex::encode(&version.hash))),
+        None => Ok(Blob::new("")),
+    };
+    let mut entries: Vec<DiffEntry> = Vec::new();
+    for name in names {
+        let (o, n) = (old.get(name), new.get(name));
+        if o == n {
+            continue;
+        }
+        entries.push(DiffEntry::new(name, read(n)?, read(o)?, n.map_or(0, |v| v.mode), o.map_or(0, |v| v.mode)));
+    }
+    Ok(entries)
+}
+
+pub fn keep_stat(old: &Index, entries: &mut [Entry]) {
+    // entries that did not change keep their stat data, so they are not seen as modified
+    let old = old.entries.iter()
+                .filter(|e| e.stage == 0)
+                .map(|e| (e.name.as_str(), e))
+                .collect::<HashMap<&str, &Entry>>();
+    for entry in entries.iter_mut() {
+        if let Some(old) = old.get(entry.name.as_str()) {
+            if old.hash == entry.hash && old.mode == entry.mode {
+                *entry = (*old).clone();
+            }
+        }
+    }
+}
+
+pub fn match_pathspec(name: &str, paths: &[&str]) -> bool {
+    // a path names a file, everything below a directory or what a glob matches
+    Pathspec::new(paths).matches(name)
+}
+
+pub fn walk_worktree(ignored: bool) -> io::Result<Vec<String>> {
+    // every file in the working tree, the ignored ones too when asked
+    let gitignore = if ignored { ignore::GitIgnore::new() } else { ignore::GitIgnore::read_gitignore()? };
+    gitignore.walk_dir()
+}
+
+fn encode_varint(value: usize) -> Vec<u8> {
+    // git's offset varint, 7 bits a byte, most significant first, every byte but the last with the top bit set
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut value = value >> 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+fn decode_varint(data: &[u8]) -> Option<(usize, usize)> {
+    // the value and the bytes it took
+    let mut value = 0usize;
+    for (i, &byte) in data.iter().enumerate() {
+        value = (value << 7) | (byte & 0x7f) as usize;
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+        value += 1;
+    }
+    None
+}
 
+fn padded_size(size: usize) -> usize {
+    // version 2 and 3 entries end in 1 to 8 NULs, up to a multiple of 8
+    size + (8 - size % 8)
 }
 
 fn hex_to_num(data: &[u8]) -> u32 {
@@ -445,18 +922,8 @@ fn hex_to_num(data: &[u8]) -> u32 {
 }
 
 pub fn num_to_mode(mode: u32) -> String {
-    let mode = mode as u16;
-    let file_type = mode >> 13;
-    let (user, group, other) = {
-        let permission = mode & 0x01ff;
-        let user = (permission & 0x01c0) >> 6;
-        let group = (permission & 0x0038) >> 3;
-        let other = permission & 0x0007;
-
-        (user, group, other)
-    };
-
-    format!("{:03b}{}{}{}", file_type, user, group, other)
+    // object type and permission bits as octal digits, 100644, 120000 or 160000
+    format!("{:o}", mode as u16)
 }
 
 fn mode_to_num(mode: &str) -> io::Result<u32> {
@@ -464,7 +931,17 @@ fn mode_to_num(mode: &str) -> io::Result<u32> {
     Ok(m)
 }
 
-fn num_to_mode_num(mode: u32) -> io::Result<u32> {
+fn normalize_mode(mode: u32) -> u32 {
+    // git only records regular files, executables, symlinks and gitlinks
+    match mode & 0o170000 {
+        0o120000 => 120000,
+        0o160000 => 160000,
+        _ if mode & 0o100 != 0 => 100755,
+        _ => 100644,
+    }
+}
+
+pub fn num_to_mode_num(mode: u32) -> io::Result<u32> {
     let mode = num_to_mode(mode);
     mode.parse::<u32>().or(Err(io::Error::from(io::ErrorKind::InvalidData)))
 }
@@ -473,6 +950,7 @@ fn num_to_mode_num(mode: u32) -> io::Result<u32> {
 mod tests {
     use super::Entry;
     use super::Index;
+    use sha1::{Sha1, Digest};
     #[test]
     fn test_hex_to_num() {
         assert_eq!(super::hex_to_num(&[0x00, 0x00, 0x81, 0xa4]), 33188);
@@ -480,6 +958,8 @@ mod tests {
     #[test]
     fn test_num_to_mode() {
         assert_eq!(super::num_to_mode(33188), String::from("100644"));
+        assert_eq!(super::num_to_mode(0o120000), String::from("120000"));
+        assert_eq!(super::num_to_mode(0o160000), String::from("160000"));
     }
     #[test]
     fn test_mode_to_num() {
@@ -519,6 +999,73 @@ mod tests {
         assert_eq!(&res, entry_str);
     }
 
+    #[test]
+    fn test_entry_flags() {
+        let mut entry = Entry::from_stage(vec![0xeb; 20], "conflicted.txt", 100644, 2);
+        entry.assume_valid = true;
+        let bytes = entry.as_bytes();
+        assert_eq!(&bytes[60..62], &[0xa0, 0x0e]);
+        let parsed = Entry::from(&bytes).unwrap();
+        assert!(parsed.assume_valid);
+        assert!(!parsed.skip_worktree && !parsed.intent_to_add);
+        assert_eq!(parsed.stage, 2);
+        assert_eq!(parsed.name, "conflicted.txt");
+        assert_eq!(format!("{}", parsed), "100644 ebebebebebebebebebebebebebebebebebebebeb 2\tconflicted.txt");
+    }
+    #[test]
+    fn test_entry_extended_flags() {
+        let mut entry = Entry::from_stage(vec![0xeb; 20], "sparse.txt", 100644, 0);
+        entry.skip_worktree = true;
+        let bytes = entry.as_bytes();
+        assert_eq!(&bytes[60..64], &[0x40, 0x0a, 0x40, 0x00]);
+        assert_eq!(bytes.len(), entry.size());
+        let parsed = Entry::from(&bytes).unwrap();
+        assert!(parsed.skip_worktree && !parsed.intent_to_add);
+        assert_eq!(parsed.name, "sparse.txt");
+    }
+    #[test]
+    fn test_index_versions() {
+        let mut entries = ["src/cmd/add.rs", "src/cmd/diff.rs", "src/main.rs"].iter()
+                        .map(|name| Entry::from_stage(vec![0; 20], name, 100644, 0))
+                        .collect::<Vec<Entry>>();
+        let index = Index::new(entries.clone(), Vec::new());
+        assert_eq!(&index.as_bytes()[4..8], &[0, 0, 0, 2]);
+        entries[1].intent_to_add = true;
+        let index = Index::new(entries, Vec::new());
+        assert_eq!(&index.as_bytes()[4..8], &[0, 0, 0, 3]);
+        let v4 = Index { version: 4, ..index }.as_bytes();
+        assert_eq!(&v4[4..8], &[0, 0, 0, 4]);
+        // the second name drops "add.rs" from the first and adds "diff.rs", after its extended flags
+        assert_eq!(&v4[(12 + 78 + 64)..(12 + 78 + 64 + 9)], b"\x06diff.rs\0");
+        let parsed = Index::from(&v4).unwrap();
+        assert_eq!(parsed.version, 4);
+        assert_eq!(parsed.entries.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>(), vec!["src/cmd/add.rs", "src/cmd/diff.rs", "src/main.rs"]);
+        assert!(parsed.entries[1].intent_to_add);
+        assert_eq!(parsed.as_bytes(), v4);
+    }
+    #[test]
+    fn test_entry_long_name() {
+        let name = "a/".repeat(2100) + "file";
+        let entry = Entry::from_stage(vec![0; 20], &name, 100644, 0);
+        let bytes = entry.as_bytes();
+        assert_eq!(&bytes[60..62], &[0x0f, 0xff]);
+        assert_eq!(bytes.len(), entry.size());
+        assert_eq!(Entry::from(&bytes).unwrap().name, name);
+    }
+    #[test]
+    fn test_index_unmerged() {
+        let entries = vec![
+            Entry::from_stage(vec![0; 20], "a", 100644, 0),
+            Entry::from_stage(vec![1; 20], "b", 100644, 1),
+            Entry::from_stage(vec![2; 20], "b", 100644, 2),
+            Entry::from_stage(vec![3; 20], "b", 100644, 3),
+            Entry::from_stage(vec![1; 20], "c", 100644, 1),
+            Entry::from_stage(vec![3; 20], "c", 100644, 3),
+        ];
+        let index = super::Index::new(entries, Vec::new());
+        assert_eq!(index.unmerged(), vec![(String::from("b"), 0b111), (String::from("c"), 0b101)]);
+    }
+
     const TREE_ENTRY: [u8; 29] = [
         0x73, 0x72, 0x63, 0x00, 0x31, 0x39, 0x20, 0x34, 0x0a, 0x66,
         0x1a, 0xee, 0x10, 0x8c, 0x1a, 0x40, 0x78, 0xb2, 0xfd, 0x85, 0x17, 0x29, 0x58, 0x6d, 0x6a, 0x6c,
@@ -555,6 +1102,14 @@ mod tests {
         assert_eq!(hex::encode(tree_entry.hash), "661aee108c1a4078b2fd851729586d6a6c600bfe");
     }
     #[test]
+    fn test_tree_entry_from_spaced_path() {
+        let data = [&b"my dir\x002 0\n"[..], &[0x0a; 20]].concat();
+        let tree_entry = TreeEntry::from(&data).unwrap();
+        assert_eq!((tree_entry.path.as_str(), tree_entry.entries, tree_entry.subtrees), ("my dir", 2, 0));
+        assert_eq!(tree_entry.hash, vec![0x0a; 20]);
+        assert_eq!(super::tree_entrties_from_bytes(&[data.clone(), data].concat()).unwrap().len(), 2);
+    }
+    #[test]
     fn test_tree_entry_as_bytes() {
         let tree_entry = TreeEntry::from(&TREE_ENTRY).unwrap();
         assert_eq!(tree_entry.as_bytes(), TREE_ENTRY.to_vec());
@@ -586,7 +1141,8 @@ mod tests {
     }
     #[test]
     fn test_tree_entries() {
-        let tree_entries = super::tree_entrties_from_bytes(&TREE_ENTRIES).unwrap();
+        // the extension data without the index checksum after it
+        let tree_entries = super::tree_entrties_from_bytes(&TREE_ENTRIES[..174]).unwrap();
         assert_eq!(tree_entries.len(), 6);
         assert_eq!(tree_entries[0].path, ".");
         assert_eq!(tree_entries[1].path, "src");
@@ -788,6 +1344,81 @@ mod tests {
         assert_eq!(index.tree_entries.len(), 6);
     }
     #[test]
+    fn test_index_lock() {
+        let path = std::env::temp_dir().join(format!("rusgit-index-lock-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let lock_path = format!("{}.lock", path);
+        let index = Index::from(&INDEX).unwrap();
+        super::write_index(path, &index).unwrap();
+        assert!(!std::path::Path::new(&lock_path).exists());
+        // a write under a lock already held waits in the lock file until the holder commits
+        let lock = super::lock_index(path).unwrap();
+        super::write_index(path, &Index { entries: Vec::new(), ..index.clone() }).unwrap();
+        assert!(super::read_index(path).unwrap().entries.is_empty());
+        assert_eq!(Index::from(&std::fs::read(path).unwrap()).unwrap().entries.len(), 32);
+        lock.commit().unwrap();
+        assert!(super::read_index(path).unwrap().entries.is_empty());
+        assert!(!std::path::Path::new(&lock_path).exists());
+        // a lock dropped without a commit, as when a command fails half way, leaves the index alone
+        let res: std::io::Result<()> = super::with_lock(path, || {
+            super::write_index(path, &index)?;
+            Err(std::io::Error::other("failed"))
+        });
+        assert!(res.is_err());
+        assert!(super::read_index(path).unwrap().entries.is_empty());
+        assert!(!std::path::Path::new(&lock_path).exists());
+        // a lock left by another process keeps the index as it is
+        std::fs::write(&lock_path, b"").unwrap();
+        let err = super::write_index(path, &index).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(super::read_index(path).unwrap().entries.is_empty());
+        std::fs::remove_file(&lock_path).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_index_checksum() {
+        let mut data = INDEX.to_vec();
+        data[100] ^= 0xff;
+        assert_eq!(Index::from(&data).unwrap_err().to_string(), "bad index file sha1 signature");
+        // zeros stand for a checksum that was skipped
+        let len = data.len();
+        data[(len - 20)..].fill(0);
+        assert!(Index::from(&data).is_ok());
+    }
+    #[test]
+    fn test_index_extensions() {
+        let with_extension = |signature: &[u8]| {
+            let content = &INDEX[..(INDEX.len() - 20)];
+            let data = [content, signature, &[0, 0, 0, 3], b"abc"].concat();
+            let hash = Vec::from(Sha1::digest(&data).as_slice());
+            Index::from(&[data, hash].concat())
+        };
+        let index = with_extension(b"REUC").unwrap();
+        assert_eq!(index.extensions, vec![(Vec::from(*b"REUC"), Vec::from(*b"abc"))]);
+        assert_eq!(Index::from(&index.as_bytes()).unwrap().extensions, index.extensions);
+        assert_eq!(index.tree_entries.len(), 6);
+        assert!(with_extension(b"EOIE").unwrap().extensions.is_empty());
+        assert_eq!(with_extension(b"link").unwrap_err().to_string(), "index uses link extension, which we do not understand");
+    }
+    #[test]
+    fn test_replace_paths() {
+        let content = &INDEX[..(INDEX.len() - 20)];
+        let data = [content, b"REUC", &[0, 0, 0, 3], b"abc"].concat();
+        let hash = Vec::from(Sha1::digest(&data).as_slice());
+        let mut index = Index::from(&[data, hash].concat()).unwrap();
+        index.version = 4;
+        let names = ["src/cmd/add.rs", "src/cmd/new.rs"].iter().map(|name| String::from(*name)).collect();
+        let index = super::replace_paths(index, &names, vec![Entry::from_stage(vec![1; 20], "src/cmd/new.rs", 100644, 0)]);
+        assert_eq!(index.entries.len(), 32);
+        assert!(index.entries.windows(2).all(|w| w[0].name < w[1].name));
+        assert!(!index.entries.iter().any(|e| e.name == "src/cmd/add.rs"));
+        // what the paths do not touch is kept: the version, the extensions and the trees beside them
+        assert_eq!(index.version, 4);
+        assert_eq!(index.extensions, vec![(Vec::from(*b"REUC"), Vec::from(*b"abc"))]);
+        let invalid = index.tree_entries.iter().map(|t| (t.path.as_str(), t.entries == -1)).collect::<Vec<_>>();
+        assert_eq!(invalid, vec![(".", true), ("src", true), ("cmd", true), ("refs", false), ("index", false), ("object", false)]);
+    }
+    #[test]
     #[cfg(target_os = "macos")]
     fn test_macos_entry_from_name() {
         let hash: Vec<u8> = vec![0x00, 0x00];
@@ -805,11 +1436,39 @@ mod tests {
         assert_eq!(entry.name, "Cargo.toml");
     }
     #[test]
+    fn test_entry_matches_stat() {
+        let metadata = std::fs::symlink_metadata("Cargo.toml").unwrap();
+        let mut entry = Entry::from_name(vec![0x00, 0x00], "Cargo.toml").unwrap();
+        let mut index = Index::new(vec![entry.clone()], Vec::new());
+        assert!(entry.matches_stat(&metadata));
+        assert!(!index.is_racy(&entry));
+        index.timestamp = Some(entry.m_time);
+        assert!(index.is_racy(&entry));
+        index.timestamp = Some(entry.m_time + chrono::Duration::seconds(1));
+        assert!(!index.is_racy(&entry));
+        // a smudged entry never matches a file with content
+        entry.size = 0;
+        assert!(!entry.matches_stat(&metadata));
+    }
+    #[test]
     fn test_tree_entries_size() {
         let index = Index::from(&INDEX).unwrap();
         assert_eq!(index.tree_entries_size(), 0xae);
     }
     #[test]
+    fn test_invalidate_tree() {
+        let mut index = Index::from(&INDEX).unwrap();
+        index.invalidate_tree("src/index/mod.rs");
+        let invalid = index.tree_entries.iter().map(|t| (t.path.as_str(), t.entries, t.hash.is_empty())).collect::<Vec<_>>();
+        assert_eq!(invalid, vec![(".", -1, true), ("src", -1, true), ("cmd", 16, false), ("refs", 1, false), ("index", -1, true), ("object", 4, false)]);
+        // a file taking the place of a directory drops the directory's cached tree
+        index.invalidate_tree("src/object");
+        assert_eq!(index.tree_entries.iter().map(|t| t.path.as_str()).collect::<Vec<_>>(), vec![".", "src", "cmd", "refs", "index"]);
+        assert_eq!(index.tree_entries[1].subtrees, 3);
+        index.invalidate_trees(vec!["src/cmd/add.rs", "src/refs/mod.rs"]);
+        assert!(index.tree_entries[2..].iter().all(|t| t.entries == -1 && t.hash.is_empty()));
+    }
+    #[test]
     fn test_index_as_bytes() {
         let index = Index::from(&INDEX).unwrap();
         let res = index.as_bytes();
@@ -825,15 +1484,23 @@ mod tests {
     #[test]
     fn test_index_untracked_files() {
         let index = Index::from(&INDEX).unwrap();
-        let _untracked = index.untracked_files().unwrap();
-        assert_eq!(true, true);
+        let untracked = index.untracked_files().unwrap();
+        assert!(untracked.windows(2).all(|w| w[0] < w[1]));
+        assert!(!untracked.iter().any(|f| f == "Cargo.toml" || f.ends_with('/')));
+    }
+    #[test]
+    fn test_varint() {
+        for (value, bytes) in [(0, vec![0x00]), (127, vec![0x7f]), (128, vec![0x80, 0x00]), (16511, vec![0xff, 0x7f]), (16512, vec![0x80, 0x80, 0x00])] {
+            assert_eq!(super::encode_varint(value), bytes);
+            assert_eq!(super::decode_varint(&bytes), Some((value, bytes.len())));
+        }
     }
     #[test]
     fn test_update_index() {
         let index = Index::new(vec![], vec![]);
         let new_index = super::update_index(index, Vec::from("hash".as_bytes()), "Cargo.toml").unwrap();
         assert_eq!(new_index.entries.len(), 1);
-        assert_eq!(&super::num_to_mode(new_index.entries[0].mode), "100644");
+        assert_eq!(new_index.entries[0].mode, 100644);
         assert_eq!(&new_index.entries[0].name, "Cargo.toml");
     }
     #[test]
@@ -841,7 +1508,41 @@ mod tests {
         let index = Index::new(vec![], vec![]);
         let new_index = super::update_index_cacheinfo(index, "100755", Vec::from("hash".as_bytes()), "Cargo.toml").unwrap();
         assert_eq!(new_index.entries.len(), 1);
-        assert_eq!(&super::num_to_mode(new_index.entries[0].mode), "100755");
+        assert_eq!(new_index.entries[0].mode, 100755);
         assert_eq!(&new_index.entries[0].name, "Cargo.toml");
     }
+    #[test]
+    fn test_match_pathspec() {
+        assert!(super::match_pathspec("src/main.rs", &["src"]));
+        assert!(super::match_pathspec("src/main.rs", &["src/"]));
+        assert!(super::match_pathspec("src/main.rs", &["."]));
+        assert!(super::match_pathspec("src/main.rs", &["README.md", "src/main.rs"]));
+        assert!(!super::match_pathspec("src/main.rs", &["sr"]));
+        assert!(!super::match_pathspec("srcs/main.rs", &["src"]));
+    }
+    #[test]
+    fn test_remove_index() {
+        let index = Index::new(vec![
+            Entry::from_stage(vec![1; 20], "a", 100644, 1),
+            Entry::from_stage(vec![2; 20], "a", 100644, 2),
+            Entry::from_stage(vec![3; 20], "b", 100644, 0),
+        ], vec![]);
+        let index = super::remove_index(index, "a").unwrap();
+        assert_eq!(index.entries.len(), 1);
+        assert_eq!(&index.entries[0].name, "b");
+        assert!(super::remove_index(index, "a").is_err());
+    }
+    #[test]
+    fn test_rename_index() {
+        let index = Index::new(vec![
+            Entry::from_stage(vec![1; 20], "a", 100755, 0),
+            Entry::from_stage(vec![2; 20], "c", 100644, 0),
+        ], vec![]);
+        let index = super::rename_index(index, "a", "d").unwrap();
+        assert_eq!(index.entries.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>(), vec!["c", "d"]);
+        assert_eq!(index.entries[1].mode, 100755);
+        assert_eq!(index.entries[1].hash, vec![1; 20]);
+        assert!(super::rename_index(index.clone(), "c", "d").is_err());
+        assert!(super::rename_index(index, "a", "e").is_err());
+    }
 }
@@ -0,0 +1,215 @@
+// pathspecs as git takes them on the command line, relative to the top of the working tree
+//   dir, dir/      the path and everything below it
+//   *.rs, d/?.c    shell globs, * also matches /
+//   :(exclude)x    drop what x matches, also :!x and :^x
+//   :(top)x        x from the top, also :/x
+//   :(literal)x    x without glob characters
+
+#[derive(Debug, Clone)]
+struct Item {
+    pattern: String,
+    exclude: bool,
+    literal: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pathspec {
+    items: Vec<Item>,
+}
+
+impl Item {
+    fn parse(spec: &str) -> Item {
+        let mut item = Item { pattern: String::from(spec), exclude: false, literal: false };
+        if let Some(rest) = spec.strip_prefix(":(") {
+            if let Some((magic, pattern)) = rest.split_once(')') {
+                for word in magic.split(',') {
+                    match word {
+                        "exclude" => item.exclude = true,
+                        "literal" => item.literal = true,
+                        // commands run from the top already
+                        _ => {},
+                    }
+                }
+                item.pattern = String::from(pattern);
+            }
+        } else if let Some(rest) = spec.strip_prefix(':') {
+            // short magic is a run of !, ^ and / before the pattern, ending at an optional :
+            let end = rest.find(|c| !"!^/".contains(c)).unwrap_or(rest.len());
+            item.exclude = rest[..end].contains(['!', '^']);
+            item.pattern = String::from(rest[end..].strip_prefix(':').unwrap_or(&rest[end..]));
+        }
+        let pattern = item.pattern.trim_end_matches('/');
+        item.pattern = String::from(if pattern == "." { "" } else { pattern });
+        item
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        name == self.pattern || self.is_below(name) || self.matches_glob(name)
+    }
+
+    fn is_below(&self, name: &str) -> bool {
+        self.pattern.is_empty() || (name.starts_with(&self.pattern) && name.as_bytes().get(self.pattern.len()) == Some(&b'/'))
+    }
+
+    fn matches_glob(&self, name: &str) -> bool {
+        !self.literal && has_wildcard(&self.pattern) && wildmatch(self.pattern.as_bytes(), name.as_bytes(), false)
+    }
+}
+
+impl Pathspec {
+    pub fn new(specs: &[&str]) -> Pathspec {
+        Pathspec { items: specs.iter().map(|s| Item::parse(s)).collect() }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        // nothing but excludes means everything else
+        let mut include = self.items.iter().filter(|i| !i.exclude).peekable();
+        let included = include.peek().is_none() || include.any(|i| i.matches(name));
+        included && !self.items.iter().any(|i| i.exclude && i.matches(name))
+    }
+
+    pub fn matches_directory(&self, name: &str) -> bool {
+        // the path is taken in only as something below a directory a pathspec names, as rm wants -r for
+        self.matches(name) && !self.items.iter().any(|i| !i.exclude && (name == i.pattern || i.matches_glob(name)))
+    }
+}
+
+fn has_wildcard(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '\\'])
+}
+
+pub(super) fn wildmatch(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    // with pathname, wildcards stop at / and only a whole ** segment crosses directories
+    match_from(pattern, text, pathname, true)
+}
+
+fn match_from(pattern: &[u8], text: &[u8], pathname: bool, segment_start: bool) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pathname && segment_start && pattern.starts_with(b"**") && (pattern.len() == 2 || pattern[2] == b'/') => {
+            if pattern.len() == 2 {
+                return true;
+            }
+            // **/ matches no directory or any number of them
+            let rest = &pattern[3..];
+            match_from(rest, text, pathname, true)
+                || text.iter().enumerate().any(|(i, c)| *c == b'/' && match_from(rest, &text[(i + 1)..], pathname, true))
+        },
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if match_from(rest, &text[i..], pathname, false) {
+                    return true;
+                }
+                if pathname && text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        },
+        Some(b'?') => match text.first() {
+            Some(b'/') if pathname => false,
+            Some(_) => match_from(&pattern[1..], &text[1..], pathname, false),
+            None => false,
+        },
+        Some(b'[') => {
+            let c = match text.first() {
+                Some(b'/') if pathname => return false,
+                Some(c) => *c,
+                None => return false,
+            };
+            match match_class(&pattern[1..], c) {
+                Some((true, len)) => match_from(&pattern[(1 + len)..], &text[1..], pathname, false),
+                Some((false, _)) => false,
+                // an unclosed [ is an ordinary character
+                None => c == b'[' && match_from(&pattern[1..], &text[1..], pathname, false),
+            }
+        },
+        Some(b'\\') if pattern.len() > 1 => text.first() == Some(&pattern[1]) && match_from(&pattern[2..], &text[1..], pathname, false),
+        Some(p) => text.first() == Some(p) && match_from(&pattern[1..], &text[1..], pathname, *p == b'/'),
+    }
+}
+
+fn match_class(class: &[u8], c: u8) -> Option<(bool, usize)> {
+    // [abc], [a-z], [!a] or [^a]; returns whether c matched and the bytes up to and including ]
+    let negate = matches!(class.first(), Some(b'!') | Some(b'^'));
+    let mut i = if negate { 1 } else { 0 };
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == b']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if i + 2 < class.len() && class[i + 1] == b'-' && class[i + 2] != b']' {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pathspec;
+
+    #[test]
+    fn test_wildmatch() {
+        assert!(super::wildmatch(b"*.rs", b"src/main.rs", false));
+        assert!(super::wildmatch(b"src/?ain.rs", b"src/main.rs", false));
+        assert!(super::wildmatch(b"[a-c]*", b"b.txt", false));
+        assert!(super::wildmatch(b"[!a-c]*", b"d.txt", false));
+        assert!(!super::wildmatch(b"[!a-c]*", b"a.txt", false));
+        assert!(super::wildmatch(b"\\*", b"*", false));
+        assert!(!super::wildmatch(b"*.rs", b"main.rc", false));
+    }
+    #[test]
+    fn test_wildmatch_pathname() {
+        assert!(!super::wildmatch(b"*.rs", b"src/main.rs", true));
+        assert!(!super::wildmatch(b"src/?", b"src//", true));
+        assert!(super::wildmatch(b"**/main.rs", b"main.rs", true));
+        assert!(super::wildmatch(b"**/main.rs", b"src/cmd/main.rs", true));
+        assert!(super::wildmatch(b"src/**", b"src/cmd/main.rs", true));
+        assert!(super::wildmatch(b"src/**/main.rs", b"src/main.rs", true));
+        assert!(super::wildmatch(b"src/**/main.rs", b"src/a/b/main.rs", true));
+        assert!(!super::wildmatch(b"src/**/main.rs", b"srcx/main.rs", true));
+        assert!(!super::wildmatch(b"a**b", b"a/b", true));
+    }
+    #[test]
+    fn test_pathspec_prefix() {
+        let spec = Pathspec::new(&["src/"]);
+        assert!(spec.matches("src/main.rs"));
+        assert!(!spec.matches("srcs/main.rs"));
+        assert!(Pathspec::new(&["."]).matches("a/b/c"));
+    }
+    #[test]
+    fn test_pathspec_magic() {
+        let spec = Pathspec::new(&["*.rs", ":(exclude)src/cmd", ":!*test*"]);
+        assert!(spec.matches("src/main.rs"));
+        assert!(!spec.matches("src/cmd/add.rs"));
+        assert!(!spec.matches("src/test.rs"));
+        assert!(!spec.matches("Cargo.toml"));
+        // excludes alone keep everything else
+        let spec = Pathspec::new(&[":^target"]);
+        assert!(spec.matches("src/main.rs"));
+        assert!(!spec.matches("target/debug/rusgit"));
+        assert!(Pathspec::new(&[":(top)src"]).matches("src/main.rs"));
+        assert!(Pathspec::new(&[":/src"]).matches("src/main.rs"));
+        assert!(!Pathspec::new(&[":(literal)*.rs"]).matches("main.rs"));
+        assert!(Pathspec::new(&[":(literal)*.rs"]).matches("*.rs"));
+    }
+    #[test]
+    fn test_pathspec_matches_directory() {
+        assert!(Pathspec::new(&["src"]).matches_directory("src/main.rs"));
+        assert!(Pathspec::new(&["src/"]).matches_directory("src/main.rs"));
+        assert!(Pathspec::new(&["."]).matches_directory("main.rs"));
+        assert!(Pathspec::new(&[":/src"]).matches_directory("src/main.rs"));
+        assert!(!Pathspec::new(&["src/main.rs"]).matches_directory("src/main.rs"));
+        assert!(!Pathspec::new(&["*.rs"]).matches_directory("src/main.rs"));
+        assert!(!Pathspec::new(&["src/*"]).matches_directory("src/cmd/add.rs"));
+        assert!(!Pathspec::new(&["src"]).matches_directory("srcs/main.rs"));
+    }
+}
@@ -0,0 +1,633 @@
+// untracked paths, found by walking the working tree in index order next to the sorted index names
+//
+// status normally shows a directory without a single tracked file as dir/, and the UNTR
+// extension remembers what every directory held, so one whose stat data is unchanged is not read again
+//   <varint ident size><ident>                 "Location <work tree>, system <os>\0"
+//   <stat data of info/exclude><stat data of core.excludesFile><dir flags>
+//   <hash of info/exclude><hash of core.excludesFile><per directory exclude file>\0
+//   <varint directory count>
+//   depth first, per directory: <varint untracked count><varint sub directory count><name>\0<untracked>\0...
+//   <ewah valid><ewah check only><ewah .gitignore hash>
+//   <stat data of every valid directory><hash of every .gitignore>
+//   \0
+
+use std::io;
+use std::fs;
+use std::env;
+use std::iter;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(target_os = "macos")]
+use std::os::macos::fs::MetadataExt;
+#[cfg(target_os = "linux")]
+use std::os::linux::fs::MetadataExt;
+use crate::object::blob::Blob;
+use crate::index::{encode_varint, decode_varint, hex_to_num};
+use crate::index::ignore::{self, GitIgnore, Pattern};
+use crate::cmd::{GITIGNORE, GIT_BASE_DIR, GIT_INFO_EXCLUDE};
+
+// show other directories and hide empty ones, the walk status does and the cache stands for
+const DIR_FLAGS: u32 = 6;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct StatData {
+    c_time: u32,
+    c_time_nano: u32,
+    m_time: u32,
+    m_time_nano: u32,
+    dev: u32,
+    inode: u32,
+    uid: u32,
+    gid: u32,
+    size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ExcludeFile {
+    stat: StatData,
+    // the blob hash of the file, all zeros when there is none
+    hash: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CacheDir {
+    name: String,
+    // files and collapsed directories, those with a trailing /
+    untracked: Vec<String>,
+    dirs: Vec<CacheDir>,
+    valid: bool,
+    // a directory without tracked files, only read until the first untracked path
+    check_only: bool,
+    stat: StatData,
+    exclude_hash: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UntrackedCache {
+    ident: String,
+    info_exclude: ExcludeFile,
+    excludes_file: ExcludeFile,
+    dir_flags: u32,
+    exclude_per_dir: String,
+    root: Option<CacheDir>,
+}
+
+struct Walk<'a> {
+    // sorted, and met in step with the walk
+    names: &'a [&'a str],
+    cursor: usize,
+    collapse: bool,
+    show_ignored: bool,
+    // a directory changed within this second may change again unnoticed
+    now: u32,
+}
+
+impl StatData {
+    fn from_metadata(metadata: &fs::Metadata) -> StatData {
+        StatData {
+            c_time: metadata.st_ctime() as u32,
+            c_time_nano: metadata.st_ctime_nsec() as u32,
+            m_time: metadata.st_mtime() as u32,
+            m_time_nano: metadata.st_mtime_nsec() as u32,
+            dev: metadata.st_dev() as u32,
+            inode: metadata.st_ino() as u32,
+            uid: metadata.st_uid(),
+            gid: metadata.st_gid(),
+            size: metadata.st_size() as u32,
+        }
+    }
+
+    fn from(data: &[u8]) -> Option<StatData> {
+        let data = data.get(0..36)?;
+        let field = |i: usize| hex_to_num(&data[(i * 4)..(i * 4 + 4)]);
+        Some(StatData {
+            c_time: field(0),
+            c_time_nano: field(1),
+            m_time: field(2),
+            m_time_nano: field(3),
+            dev: field(4),
+            inode: field(5),
+            uid: field(6),
+            gid: field(7),
+            size: field(8),
+        })
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        [self.c_time, self.c_time_nano, self.m_time, self.m_time_nano,
+         self.dev, self.inode, self.uid, self.gid, self.size]
+            .iter()
+            .flat_map(|d| d.to_be_bytes())
+            .collect()
+    }
+}
+
+impl ExcludeFile {
+    fn read(path: Option<&Path>) -> io::Result<ExcludeFile> {
+        let missing = ExcludeFile { stat: StatData::default(), hash: vec![0; 20] };
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(missing),
+        };
+        match fs::read(path) {
+            Ok(data) => Ok(ExcludeFile { stat: StatData::from_metadata(&fs::metadata(path)?), hash: blob_hash(&data) }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(missing),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl UntrackedCache {
+    pub fn new() -> io::Result<UntrackedCache> {
+        // nothing cached yet, the next status fills it
+        Ok(UntrackedCache {
+            ident: ident()?,
+            info_exclude: ExcludeFile::read(Some(Path::new(GIT_INFO_EXCLUDE)))?,
+            excludes_file: ExcludeFile::read(ignore::excludes_file()?.as_deref())?,
+            dir_flags: DIR_FLAGS,
+            exclude_per_dir: String::from(GITIGNORE),
+            root: None,
+        })
+    }
+
+    pub fn from(data: &[u8]) -> Option<UntrackedCache> {
+        // the last \0 only guards the strings
+        let data = data.strip_suffix(&[0])?;
+        let (ident_size, mut offset) = decode_varint(data)?;
+        let ident = String::from_utf8(data.get(offset..(offset + ident_size))?.to_vec()).ok()?;
+        offset += ident_size;
+        let header = data.get(offset..(offset + 116))?;
+        let name_size = data[(offset + 116)..].iter().position(|&b| b == b'\0')?;
+        let exclude_per_dir = String::from_utf8(data[(offset + 116)..(offset + 116 + name_size)].to_vec()).ok()?;
+        let mut cache = UntrackedCache {
+            ident,
+            info_exclude: ExcludeFile { stat: StatData::from(&header[0..36])?, hash: header[76..96].to_vec() },
+            excludes_file: ExcludeFile { stat: StatData::from(&header[36..72])?, hash: header[96..116].to_vec() },
+            dir_flags: hex_to_num(&header[72..76]),
+            exclude_per_dir,
+            root: None,
+        };
+        offset += 116 + name_size + 1;
+        if offset >= data.len() {
+            return Some(cache);
+        }
+        let (count, size) = decode_varint(&data[offset..])?;
+        offset += size;
+        if count == 0 {
+            return Some(cache);
+        }
+        // every directory with the number of sub directories that follow it
+        let mut dirs: Vec<(CacheDir, usize)> = Vec::new();
+        for _ in 0..count {
+            let (untracked_count, size) = decode_varint(data.get(offset..)?)?;
+            offset += size;
+            let (dir_count, size) = decode_varint(data.get(offset..)?)?;
+            offset += size;
+            let mut names = Vec::new();
+            for _ in 0..=untracked_count {
+                let size = data.get(offset..)?.iter().position(|&b| b == b'\0')?;
+                names.push(String::from_utf8(data[offset..(offset + size)].to_vec()).ok()?);
+                offset += size + 1;
+            }
+            let name = names.remove(0);
+            dirs.push((CacheDir { name, untracked: names, ..CacheDir::default() }, dir_count));
+        }
+        let (valid, size) = ewah_from(data.get(offset..)?)?;
+        offset += size;
+        let (check_only, size) = ewah_from(data.get(offset..)?)?;
+        offset += size;
+        let (hashed, size) = ewah_from(data.get(offset..)?)?;
+        offset += size;
+        for i in check_only {
+            dirs.get_mut(i)?.0.check_only = true;
+        }
+        for i in valid {
+            let dir = &mut dirs.get_mut(i)?.0;
+            dir.valid = true;
+            dir.stat = StatData::from(data.get(offset..)?)?;
+            offset += 36;
+        }
+        for i in hashed {
+            dirs.get_mut(i)?.0.exclude_hash = Some(data.get(offset..(offset + 20))?.to_vec());
+            offset += 20;
+        }
+        cache.root = Some(build_tree(&mut dirs.into_iter())?);
+        Some(cache)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = encode_varint(self.ident.len());
+        bytes.extend(self.ident.as_bytes());
+        bytes.extend(self.info_exclude.stat.as_bytes());
+        bytes.extend(self.excludes_file.stat.as_bytes());
+        bytes.extend(self.dir_flags.to_be_bytes());
+        bytes.extend(&self.info_exclude.hash);
+        bytes.extend(&self.excludes_file.hash);
+        bytes.extend(self.exclude_per_dir.as_bytes());
+        bytes.push(b'\0');
+        let mut dirs = Vec::new();
+        if let Some(root) = &self.root {
+            flatten(root, &mut dirs);
+        }
+        bytes.extend(encode_varint(dirs.len()));
+        if !dirs.is_empty() {
+            for dir in dirs.iter() {
+                // what an invalid directory held is read again anyway
+                let untracked = if dir.valid { dir.untracked.as_slice() } else { &[] };
+                bytes.extend(encode_varint(untracked.len()));
+                bytes.extend(encode_varint(dir.dirs.len()));
+                for name in iter::once(&dir.name).chain(untracked) {
+                    bytes.extend(name.as_bytes());
+                    bytes.push(b'\0');
+                }
+            }
+            bytes.extend(ewah_as_bytes(&dirs.iter().map(|d| d.valid).collect::<Vec<bool>>()));
+            bytes.extend(ewah_as_bytes(&dirs.iter().map(|d| d.valid && d.check_only).collect::<Vec<bool>>()));
+            bytes.extend(ewah_as_bytes(&dirs.iter().map(|d| d.exclude_hash.is_some()).collect::<Vec<bool>>()));
+            dirs.iter().filter(|d| d.valid).for_each(|d| bytes.extend(d.stat.as_bytes()));
+            dirs.iter().filter_map(|d| d.exclude_hash.as_ref()).for_each(|hash| bytes.extend(hash));
+        }
+        bytes.push(b'\0');
+        bytes
+    }
+
+    pub fn invalidate(&mut self, path: &str) {
+        // a path came or went, its directory and every one above it are read again
+        if let Some(root) = self.root.as_mut() {
+            invalidate_dir(root, path);
+        }
+    }
+
+    fn refresh(&mut self) -> io::Result<()> {
+        // another work tree, other flags or changed exclude files make everything cached stale
+        let fresh = UntrackedCache::new()?;
+        let same = self.ident == fresh.ident
+                    && self.dir_flags == fresh.dir_flags
+                    && self.exclude_per_dir == fresh.exclude_per_dir
+                    && self.info_exclude.hash == fresh.info_exclude.hash
+                    && self.excludes_file.hash == fresh.excludes_file.hash;
+        let root = if same { self.root.take() } else { None };
+        *self = UntrackedCache { root, ..fresh };
+        Ok(())
+    }
+}
+
+impl<'a> Walk<'a> {
+    fn seek(&mut self, path: &str) -> Option<&'a str> {
+        // the first name not before path, the walk only ever moves forward
+        while self.cursor < self.names.len() && self.names[self.cursor] < path {
+            self.cursor += 1;
+        }
+        self.names.get(self.cursor).copied()
+    }
+
+    fn is_tracked(&mut self, path: &str) -> bool {
+        self.seek(path) == Some(path)
+    }
+
+    fn has_tracked(&mut self, dir: &str) -> bool {
+        let prefix = format!("{}/", dir);
+        self.seek(&prefix).is_some_and(|name| name.starts_with(&prefix))
+    }
+
+    fn is_gitlink(&self, dir: &str) -> bool {
+        // a directory sorts after its gitlink entry, which the cursor may have passed
+        self.names.binary_search(&dir).is_ok()
+    }
+
+    fn read_dir(&mut self, dir: &str, patterns: &mut Vec<Pattern>, check_only: bool, old: Option<CacheDir>) -> io::Result<CacheDir> {
+        let (mut own, exclude_hash) = dir_patterns(dir)?;
+        let count = patterns.len();
+        patterns.append(&mut own);
+        let stat = StatData::from_metadata(&fs::metadata(if dir.is_empty() { "." } else { dir })?);
+        // a changed .gitignore can change the answer anywhere below
+        let old = old.filter(|old| old.exclude_hash == exclude_hash);
+        let mut block = match old {
+            Some(old) if old.valid && old.check_only == check_only && old.stat == stat => self.reuse(dir, patterns, old)?,
+            old => self.scan(dir, patterns, check_only, old.map(|old| old.dirs).unwrap_or_default())?,
+        };
+        patterns.truncate(count);
+        block.name = String::from(dir.rsplit('/').next().unwrap_or(dir));
+        block.valid = stat.m_time < self.now;
+        block.check_only = check_only;
+        block.stat = stat;
+        block.exclude_hash = exclude_hash;
+        // in name order, the order git looks them up in
+        block.dirs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(block)
+    }
+
+    fn scan(&mut self, dir: &str, patterns: &mut Vec<Pattern>, check_only: bool, mut old_dirs: Vec<CacheDir>) -> io::Result<CacheDir> {
+        let mut block = CacheDir::default();
+        for (name, is_dir) in read_entries(dir)? {
+            let path = join(dir, &name);
+            let found = if !is_dir {
+                !self.is_tracked(&path) && !ignore::is_excluded(patterns, &path, false)
+            } else if self.is_gitlink(&path) || ignore::is_excluded(patterns, &path, true) {
+                false
+            } else {
+                let tracked = self.has_tracked(&path);
+                if !tracked && ignore::is_repository(&path) {
+                    true
+                } else {
+                    let old = old_dirs.iter().position(|d| d.name == name).map(|i| old_dirs.swap_remove(i));
+                    let sub = self.read_dir(&path, patterns, !tracked, old)?;
+                    let found = !tracked && !sub.untracked.is_empty();
+                    block.dirs.push(sub);
+                    found
+                }
+            };
+            if found {
+                block.untracked.push(if is_dir { format!("{}/", name) } else { name });
+                if check_only {
+                    break;
+                }
+            }
+        }
+        Ok(block)
+    }
+
+    fn reuse(&mut self, dir: &str, patterns: &mut Vec<Pattern>, old: CacheDir) -> io::Result<CacheDir> {
+        // nothing came or went here, only what lies below may have changed
+        let mut block = CacheDir { untracked: old.untracked, ..CacheDir::default() };
+        let mut dirs = old.dirs;
+        dirs.sort_by_cached_key(|d| format!("{}/", d.name));
+        for old in dirs {
+            let path = join(dir, &old.name);
+            let entry = format!("{}/", old.name);
+            let tracked = self.has_tracked(&path);
+            let sub = self.read_dir(&path, patterns, !tracked, Some(old))?;
+            let found = !tracked && !sub.untracked.is_empty();
+            match (found, block.untracked.contains(&entry)) {
+                (true, false) => block.untracked.push(entry),
+                (false, true) => block.untracked.retain(|name| *name != entry),
+                _ => {},
+            }
+            block.dirs.push(sub);
+        }
+        let names = self.names;
+        block.untracked.retain(|name| name.ends_with('/') || names.binary_search(&join(dir, name).as_str()).is_err());
+        Ok(block)
+    }
+
+    fn collect(&mut self, dir: &str, excluded: bool, patterns: &mut Vec<Pattern>, untracked: &mut Vec<String>, ignored: &mut Vec<String>) -> io::Result<()> {
+        // every path below dir, a directory without tracked files as dir/ when collapsing
+        let (mut own, _) = dir_patterns(dir)?;
+        let count = patterns.len();
+        patterns.append(&mut own);
+        for (name, is_dir) in read_entries(dir)? {
+            let path = join(dir, &name);
+            if !is_dir {
+                if self.is_tracked(&path) {
+                    continue;
+                }
+                match excluded || ignore::is_excluded(patterns, &path, false) {
+                    true => ignored.push(path),
+                    false => untracked.push(path),
+                }
+                continue;
+            }
+            if self.is_gitlink(&path) {
+                continue;
+            }
+            // nothing below an ignored directory can be kept
+            let excluded = excluded || ignore::is_excluded(patterns, &path, true);
+            let tracked = self.has_tracked(&path);
+            if !tracked && ignore::is_repository(&path) {
+                match excluded {
+                    true => ignored.push(format!("{}/", path)),
+                    false => untracked.push(format!("{}/", path)),
+                }
+            } else if excluded && !tracked && !self.show_ignored {
+                continue;
+            } else if tracked || !self.collapse {
+                self.collect(&path, excluded, patterns, untracked, ignored)?;
+            } else {
+                let (mut inner_untracked, mut inner_ignored) = (Vec::new(), Vec::new());
+                self.collect(&path, excluded, patterns, &mut inner_untracked, &mut inner_ignored)?;
+                // the ignored files of an untracked directory are still listed one by one
+                match (inner_untracked.is_empty(), inner_ignored.is_empty()) {
+                    (false, _) => {
+                        untracked.push(format!("{}/", path));
+                        ignored.append(&mut inner_ignored);
+                    },
+                    (true, false) => ignored.push(format!("{}/", path)),
+                    (true, true) => {},
+                }
+            }
+        }
+        patterns.truncate(count);
+        Ok(())
+    }
+}
+
+pub(super) fn untracked_paths(names: &[&str], collapse: bool, show_ignored: bool, mut cache: Option<&mut UntrackedCache>) -> io::Result<(Vec<String>, Vec<String>)> {
+    // untracked and ignored paths, sorted
+    let mut patterns = GitIgnore::read_gitignore()?.patterns;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as u32);
+    let mut walk = Walk { names, cursor: 0, collapse, show_ignored, now };
+    let (mut untracked, mut ignored) = (Vec::new(), Vec::new());
+    if collapse && !show_ignored {
+        // the only walk the cache stands for
+        let old = match cache.as_mut() {
+            Some(cache) => {
+                cache.refresh()?;
+                cache.root.take()
+            },
+            None => None,
+        };
+        let root = walk.read_dir("", &mut patterns, false, old)?;
+        collect_block(&root, "", &mut untracked);
+        if let Some(cache) = cache {
+            cache.root = Some(root);
+        }
+    } else {
+        walk.collect("", false, &mut patterns, &mut untracked, &mut ignored)?;
+        if !show_ignored {
+            ignored.clear();
+        }
+    }
+    untracked.sort();
+    untracked.dedup();
+    ignored.sort();
+    Ok((untracked, ignored))
+}
+
+fn collect_block(block: &CacheDir, dir: &str, untracked: &mut Vec<String>) {
+    // a directory with tracked files lists what it holds, a collapsed one is listed by its parent
+    untracked.extend(block.untracked.iter().map(|name| join(dir, name)));
+    for sub in block.dirs.iter().filter(|d| !d.check_only) {
+        collect_block(sub, &join(dir, &sub.name), untracked);
+    }
+}
+
+fn read_entries(dir: &str) -> io::Result<Vec<(String, bool)>> {
+    // names and whether they are directories, in index order where a directory sorts as name/
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(if dir.is_empty() { "." } else { dir })? {
+        let entry = entry?;
+        let name = entry.file_name().into_string().or(Err(io::Error::from(io::ErrorKind::InvalidData)))?;
+        if name == GIT_BASE_DIR {
+            continue;
+        }
+        // symlinks are files, even when they point at a directory
+        entries.push((name, entry.file_type()?.is_dir()));
+    }
+    entries.sort_by_cached_key(|(name, is_dir)| if *is_dir { format!("{}/", name) } else { name.clone() });
+    Ok(entries)
+}
+
+fn dir_patterns(dir: &str) -> io::Result<(Vec<Pattern>, Option<Vec<u8>>)> {
+    // the rules of the directory's .gitignore and its blob hash
+    let path = Path::new(dir).join(GITIGNORE);
+    match fs::read(&path) {
+        Ok(data) => {
+            let patterns = ignore::parse_patterns(&String::from_utf8_lossy(&data), dir, &path.to_string_lossy());
+            Ok((patterns, Some(blob_hash(&data))))
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok((Vec::new(), None)),
+        Err(e) => Err(e),
+    }
+}
+
+fn invalidate_dir(dir: &mut CacheDir, path: &str) {
+    if let Some((name, rest)) = path.split_once('/') {
+        if let Some(sub) = dir.dirs.iter_mut().find(|d| d.name == name) {
+            invalidate_dir(sub, rest);
+        }
+    }
+    dir.valid = false;
+    dir.untracked.clear();
+}
+
+fn build_tree(dirs: &mut impl Iterator<Item = (CacheDir, usize)>) -> Option<CacheDir> {
+    // a directory is followed by its sub directories, each followed by theirs
+    let (mut dir, count) = dirs.next()?;
+    for _ in 0..count {
+        let sub = build_tree(dirs)?;
+        dir.dirs.push(sub);
+    }
+    Some(dir)
+}
+
+fn flatten<'a>(dir: &'a CacheDir, dirs: &mut Vec<&'a CacheDir>) {
+    dirs.push(dir);
+    dir.dirs.iter().for_each(|sub| flatten(sub, dirs));
+}
+
+fn ewah_as_bytes(bits: &[bool]) -> Vec<u8> {
+    // <bit count><word count><words><position of the last marker word>,
+    // here one marker word saying all words after it are literal, lowest bit first
+    let size = bits.iter().rposition(|&b| b).map_or(0, |i| i + 1);
+    let words = bits[..size].chunks(64)
+                .map(|chunk| chunk.iter().enumerate().fold(0u64, |word, (i, &bit)| word | (bit as u64) << i))
+                .collect::<Vec<u64>>();
+    let marker = (words.len() as u64) << 33;
+    [
+        Vec::from((size as u32).to_be_bytes()),
+        Vec::from((words.len() as u32 + 1).to_be_bytes()),
+        iter::once(marker).chain(words).flat_map(|w| w.to_be_bytes()).collect(),
+        vec![0; 4],
+    ].concat()
+}
+
+fn ewah_from(data: &[u8]) -> Option<(Vec<usize>, usize)> {
+    // the positions of the set bits and the bytes read
+    let size = hex_to_num(data.get(0..4)?) as usize;
+    let count = hex_to_num(data.get(4..8)?) as usize;
+    let words = data.get(8..(8 + count * 8))?.chunks(8)
+                .map(|w| w.iter().fold(0u64, |word, &b| word << 8 | b as u64))
+                .collect::<Vec<u64>>();
+    data.get((8 + count * 8)..(12 + count * 8))?;
+    let mut bits = Vec::new();
+    let (mut i, mut position) = (0, 0);
+    while i < words.len() {
+        // a marker word is a run of equal words, then the number of literal words after it
+        let marker = words[i];
+        let run = ((marker >> 1) & 0xffff_ffff) as usize * 64;
+        if marker & 1 == 1 {
+            bits.extend(position..(position + run));
+        }
+        position += run;
+        let literals = (marker >> 33) as usize;
+        for word in words.get((i + 1)..(i + 1 + literals))? {
+            bits.extend((0..64).filter(|b| word >> b & 1 == 1).map(|b| position + b));
+            position += 64;
+        }
+        i += 1 + literals;
+    }
+    bits.retain(|&b| b < size);
+    Some((bits, 12 + count * 8))
+}
+
+fn blob_hash(data: &[u8]) -> Vec<u8> {
+    // git hashes an exclude file as it parses it, with a newline added unless it is empty
+    let content = String::from_utf8_lossy(data);
+    match content.is_empty() {
+        true => Blob::new("").calc_hash(),
+        false => Blob::new(&format!("{}\n", content)).calc_hash(),
+    }
+}
+
+fn ident() -> io::Result<String> {
+    // a cache made for another work tree or system is of no use here
+    let system = match env::consts::OS {
+        "linux" => "Linux",
+        "macos" => "Darwin",
+        os => os,
+    };
+    Ok(format!("Location {}, system {}\0", fs::canonicalize(".")?.display(), system))
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() { String::from(name) } else { format!("{}/{}", dir, name) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheDir, UntrackedCache};
+
+    #[test]
+    fn test_ewah() {
+        let bits = [true, false, true, false, false, false, false, false, true];
+        let bytes = super::ewah_as_bytes(&bits);
+        assert_eq!(&bytes[0..8], &[0, 0, 0, 9, 0, 0, 0, 2]);
+        assert_eq!(super::ewah_from(&bytes), Some((vec![0, 2, 8], bytes.len())));
+        // a run of 64 set bits, then a literal word with bit 1
+        let bytes = [0, 0, 0, 0x42, 0, 0, 0, 2, 0, 0, 0, 0x02, 0, 0, 0, 0x03, 0, 0, 0, 0, 0, 0, 0, 0x02, 0, 0, 0, 0];
+        let (bits, size) = super::ewah_from(&bytes).unwrap();
+        assert_eq!(bits, (0..64).chain([65]).collect::<Vec<usize>>());
+        assert_eq!(size, 28);
+    }
+    #[test]
+    fn test_untracked_cache_bytes() {
+        let mut cache = UntrackedCache::new().unwrap();
+        let mut sub = CacheDir { name: String::from("dir"), untracked: vec![String::from("a")], check_only: true, valid: true, ..CacheDir::default() };
+        sub.stat.m_time = 1;
+        cache.root = Some(CacheDir {
+            untracked: vec![String::from("dir/"), String::from("top")],
+            dirs: vec![sub],
+            valid: true,
+            exclude_hash: Some(vec![7; 20]),
+            ..CacheDir::default()
+        });
+        let bytes = cache.as_bytes();
+        let parsed = UntrackedCache::from(&bytes).unwrap();
+        assert_eq!(parsed.as_bytes(), bytes);
+        let root = parsed.root.unwrap();
+        assert_eq!(root.untracked, vec!["dir/", "top"]);
+        assert!(root.dirs[0].check_only && root.dirs[0].valid && root.dirs[0].stat.m_time == 1);
+        assert_eq!(root.exclude_hash, Some(vec![7; 20]));
+    }
+    #[test]
+    fn test_invalidate() {
+        let mut cache = UntrackedCache::new().unwrap();
+        let sub = CacheDir { name: String::from("dir"), untracked: vec![String::from("a")], valid: true, ..CacheDir::default() };
+        let other = CacheDir { name: String::from("other"), untracked: vec![String::from("b")], valid: true, ..CacheDir::default() };
+        cache.root = Some(CacheDir { dirs: vec![sub, other], valid: true, ..CacheDir::default() });
+        cache.invalidate("dir/a");
+        let root = cache.root.unwrap();
+        assert!(!root.valid && !root.dirs[0].valid && root.dirs[0].untracked.is_empty());
+        assert!(root.dirs[1].valid);
+    }
+}
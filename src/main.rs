@@ -18,11 +18,29 @@ use crate::cmd::status;
 use crate::cmd::diff;
 use crate::cmd::branch;
 use crate::cmd::checkout;
+use crate::cmd::show_ref;
+use crate::cmd::for_each_ref;
+use crate::cmd::merge_base;
+use crate::cmd::rebase;
+use crate::cmd::cherry_pick;
+use crate::cmd::revert;
+use crate::cmd::reset;
+use crate::cmd::switch;
+use crate::cmd::restore;
+use crate::cmd::stash;
+use crate::cmd::rm;
+use crate::cmd::mv;
+use crate::cmd::check_ignore;
 
 pub mod cmd;
 mod object;
 mod index;
 mod refs;
+mod config;
+mod merge;
+mod worktree;
+mod graph;
+mod sequencer;
 
 fn main() {
     // rusgit app definition
@@ -35,8 +53,10 @@ fn main() {
         .subcommand(SubCommand::with_name("cat-file")
             .about("cat git object file")
             .arg(Arg::with_name("hash")
-                .help("hash key")
+                .help("hash key, or the expected object type when the hash key follows")
                 .required(true))
+            .arg(Arg::with_name("object")
+                .help("hash key of an object printed raw as the given type"))
             .arg(Arg::with_name("type")
                 .help("show object type")
                 .short("t")
@@ -65,15 +85,22 @@ fn main() {
             .arg(Arg::with_name("add")
                 .help("do not ignore new files")
                 .long("add")
-                .takes_value(true)
-                // .empty_values(true)
-                .default_value("")
-                .required(true))
+                .takes_value(true))
             .arg(Arg::with_name("cacheinfo")
                 .help("add the specified entry to the index")
                 .long("cacheinfo")
                 .takes_value(true)
                 .multiple(true))
+            .arg(Arg::with_name("refresh")
+                .help("refresh stat information of the entries whose files did not change")
+                .long("refresh"))
+            .arg(Arg::with_name("really-refresh")
+                .help("like --refresh, but check every file regardless of its stat information")
+                .long("really-refresh"))
+            .arg(Arg::with_name("index-version")
+                .help("write the index in the given format version, 2 to 4")
+                .long("index-version")
+                .takes_value(true))
         )
         .subcommand(SubCommand::with_name("ls-files")
             .about("list up files")
@@ -81,13 +108,32 @@ fn main() {
             .help("show staged contents' object name in the output")
             .short("s")
             .long("stage"))
+            .arg(Arg::with_name("unmerged")
+            .help("show only unmerged files, with their stages")
+            .short("u")
+            .long("unmerged"))
         )
         .subcommand(SubCommand::with_name("add")
             .about("stage files")
             .arg(Arg::with_name("file")
             .help("stage files")
-            .multiple(true)
-            .required(true))
+            .multiple(true))
+            .arg(Arg::with_name("all")
+                .help("stage additions, modifications and deletions")
+                .short("A")
+                .long("all"))
+            .arg(Arg::with_name("update")
+                .help("stage modifications and deletions of tracked files only")
+                .short("u")
+                .long("update"))
+            .arg(Arg::with_name("dry-run")
+                .help("show what would be staged")
+                .short("n")
+                .long("dry-run"))
+            .arg(Arg::with_name("force")
+                .help("allow adding ignored files")
+                .short("f")
+                .long("force"))
         )
         .subcommand(SubCommand::with_name("write-tree")
             .about("write index as tree object")
@@ -120,23 +166,64 @@ fn main() {
             .arg(Arg::with_name("message")
             .help("commit message")
             .short("m")
-            .takes_value(true)
-            .required(true)) // message must be specified
+            .takes_value(true))
         )
         .subcommand(SubCommand::with_name("log")
             .about("log")
         )
         .subcommand(SubCommand::with_name("status")
             .about("show status")
+            .arg(Arg::with_name("short")
+                .help("give the output in the short-format")
+                .short("s")
+                .long("short"))
+            .arg(Arg::with_name("porcelain")
+                .help("give the output in a stable format for scripts, v1 or v2")
+                .long("porcelain")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .possible_values(&["v1", "v2"]))
+            .arg(Arg::with_name("branch")
+                .help("show the branch and tracking info in short-format")
+                .short("b")
+                .long("branch"))
+            .arg(Arg::with_name("z")
+                .help("terminate entries with NUL")
+                .short("z"))
+            .arg(Arg::with_name("untracked-files")
+                .help("show untracked files, no, normal or all")
+                .short("u")
+                .long("untracked-files")
+                .takes_value(true)
+                .min_values(0)
+                .possible_values(&["no", "normal", "all"]))
+            .arg(Arg::with_name("ignored")
+                .help("show ignored files as well")
+                .long("ignored"))
         )
         .subcommand(SubCommand::with_name("diff")
             .about("show diff")
+            .arg(Arg::with_name("unified")
+            .help("generate diffs with <n> lines of context")
+            .short("U")
+            .long("unified")
+            .takes_value(true)
+            .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|_| String::from("switch `U' expects a numerical value"))))
         )
         .subcommand(SubCommand::with_name("branch")
             .about("show branch")
             .arg(Arg::with_name("branch-name")
             .help("branch name")
             .takes_value(true))
+            .arg(Arg::with_name("delete")
+            .help("delete a fully merged branch")
+            .short("d")
+            .requires("branch-name"))
+            .arg(Arg::with_name("force-delete")
+            .help("delete a branch, even if it is not merged")
+            .short("D")
+            .requires("branch-name"))
         )
         .subcommand(SubCommand::with_name("checkout")
             .about("switch branch")
@@ -146,6 +233,330 @@ fn main() {
             .arg(Arg::with_name("new-branch")
             .help("create and checkout a new branch")
             .short("b"))
+        )
+        .subcommand(SubCommand::with_name("show-ref")
+            .about("list references")
+            .arg(Arg::with_name("pattern")
+            .help("show references matching the pattern")
+            .multiple(true))
+            .arg(Arg::with_name("heads")
+            .help("show only branches")
+            .long("heads"))
+            .arg(Arg::with_name("tags")
+            .help("show only tags")
+            .long("tags"))
+            .arg(Arg::with_name("verify")
+            .help("require an exact reference name")
+            .long("verify"))
+        )
+        .subcommand(SubCommand::with_name("for-each-ref")
+            .about("output information on each reference")
+            .arg(Arg::with_name("pattern")
+            .help("show references matching the pattern")
+            .multiple(true))
+            .arg(Arg::with_name("format")
+            .help("format string with %(fieldname) placeholders")
+            .long("format")
+            .takes_value(true))
+            .arg(Arg::with_name("sort")
+            .help("field name to sort on, prefix - to sort in descending order")
+            .long("sort")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+            .arg(Arg::with_name("count")
+            .help("stop after showing <count> refs")
+            .long("count")
+            .takes_value(true))
+            .arg(Arg::with_name("points-at")
+            .help("only list refs which points at the given object")
+            .long("points-at")
+            .takes_value(true))
+        )
+        .subcommand(SubCommand::with_name("merge")
+            .about("join two development histories together")
+            .arg(Arg::with_name("branch")
+            .help("branch to merge into the current branch")
+            .required(true))
+            .arg(Arg::with_name("no-ff")
+            .help("create a merge commit even when the merge resolves as a fast-forward")
+            .long("no-ff"))
+            .arg(Arg::with_name("message")
+            .help("merge commit message")
+            .short("m")
+            .takes_value(true))
+        )
+        .subcommand(SubCommand::with_name("merge-base")
+            .about("find as good common ancestors as possible for a merge")
+            .arg(Arg::with_name("commit")
+            .help("commits to find the common ancestors of")
+            .multiple(true)
+            .required(true))
+            .arg(Arg::with_name("all")
+            .help("output all merge bases")
+            .short("a")
+            .long("all"))
+            .arg(Arg::with_name("octopus")
+            .help("compute the best common ancestors of all supplied commits")
+            .long("octopus"))
+            .arg(Arg::with_name("is-ancestor")
+            .help("check if the first commit is an ancestor of the second")
+            .long("is-ancestor"))
+            .arg(Arg::with_name("independent")
+            .help("list the commits that cannot be reached from any other")
+            .long("independent"))
+        )
+        .subcommand(SubCommand::with_name("rebase")
+            .about("reapply commits on top of another base tip")
+            .arg(Arg::with_name("upstream")
+            .help("upstream branch to compare against")
+            .required_unless_one(&["continue", "skip", "abort"]))
+            .arg(Arg::with_name("interactive")
+            .help("make a list of the commits which are about to be rebased and let the user edit it")
+            .short("i")
+            .long("interactive"))
+            .arg(Arg::with_name("autosquash")
+            .help("move fixup! and squash! commits after the commits they modify")
+            .long("autosquash"))
+            .arg(Arg::with_name("continue")
+            .help("restart the rebasing process after having resolved a merge conflict")
+            .long("continue")
+            .conflicts_with_all(&["skip", "abort"]))
+            .arg(Arg::with_name("skip")
+            .help("restart the rebasing process by skipping the current patch")
+            .long("skip")
+            .conflicts_with("abort"))
+            .arg(Arg::with_name("abort")
+            .help("abort the rebase operation and reset HEAD to the original branch")
+            .long("abort"))
+        )
+        .subcommand(SubCommand::with_name("cherry-pick")
+            .about("apply the changes introduced by some existing commits")
+            .arg(Arg::with_name("commit")
+            .help("commits to cherry-pick, A..B picks the range")
+            .multiple(true)
+            .required_unless_one(&["continue", "skip", "abort"]))
+            .arg(Arg::with_name("no-commit")
+            .help("apply the changes to the index and the working tree without committing")
+            .short("n")
+            .long("no-commit"))
+            .arg(Arg::with_name("x")
+            .help("append a line that records the original commit")
+            .short("x"))
+            .arg(Arg::with_name("mainline")
+            .help("the parent number to diff a merge commit against")
+            .short("m")
+            .long("mainline")
+            .takes_value(true))
+            .arg(Arg::with_name("continue")
+            .help("continue the operation after resolving a conflict")
+            .long("continue")
+            .conflicts_with_all(&["skip", "abort"]))
+            .arg(Arg::with_name("skip")
+            .help("skip the current commit and continue with the rest of the sequence")
+            .long("skip")
+            .conflicts_with("abort"))
+            .arg(Arg::with_name("abort")
+            .help("cancel the operation and return to the pre-sequence state")
+            .long("abort"))
+        )
+        .subcommand(SubCommand::with_name("revert")
+            .about("revert some existing commits")
+            .arg(Arg::with_name("commit")
+            .help("commits to revert, A..B reverts the range")
+            .multiple(true)
+            .required_unless_one(&["continue", "skip", "abort"]))
+            .arg(Arg::with_name("no-commit")
+            .help("apply the changes to the index and the working tree without committing")
+            .short("n")
+            .long("no-commit"))
+            .arg(Arg::with_name("mainline")
+            .help("the parent number to diff a merge commit against")
+            .short("m")
+            .long("mainline")
+            .takes_value(true))
+            .arg(Arg::with_name("continue")
+            .help("continue the operation after resolving a conflict")
+            .long("continue")
+            .conflicts_with_all(&["skip", "abort"]))
+            .arg(Arg::with_name("skip")
+            .help("skip the current commit and continue with the rest of the sequence")
+            .long("skip")
+            .conflicts_with("abort"))
+            .arg(Arg::with_name("abort")
+            .help("cancel the operation and return to the pre-sequence state")
+            .long("abort"))
+        )
+        .subcommand(SubCommand::with_name("reset")
+            .about("reset current HEAD to the specified state")
+            .arg(Arg::with_name("commit")
+            .help("the commit to reset to, HEAD by default"))
+            .arg(Arg::with_name("paths")
+            .help("reset only these index entries")
+            .multiple(true)
+            .last(true))
+            .arg(Arg::with_name("soft")
+            .help("move the branch only")
+            .long("soft")
+            .conflicts_with_all(&["mixed", "hard", "keep"]))
+            .arg(Arg::with_name("mixed")
+            .help("move the branch and reset the index, the default")
+            .long("mixed")
+            .conflicts_with_all(&["hard", "keep"]))
+            .arg(Arg::with_name("hard")
+            .help("move the branch and reset the index and the working tree")
+            .long("hard")
+            .conflicts_with("keep"))
+            .arg(Arg::with_name("keep")
+            .help("like --hard, but keep local changes the reset does not touch")
+            .long("keep"))
+        )
+        .subcommand(SubCommand::with_name("switch")
+            .about("switch branches")
+            .arg(Arg::with_name("branch")
+            .help("branch to switch to, or the commit to detach at")
+            .required(true))
+            .arg(Arg::with_name("start-point")
+            .help("the commit a new branch starts at"))
+            .arg(Arg::with_name("create")
+            .help("create a new branch")
+            .short("c")
+            .long("create")
+            .conflicts_with_all(&["force-create", "detach"]))
+            .arg(Arg::with_name("force-create")
+            .help("create a new branch, or reset it if it exists")
+            .short("C")
+            .long("force-create")
+            .conflicts_with("detach"))
+            .arg(Arg::with_name("detach")
+            .help("switch to a commit for inspection")
+            .short("d")
+            .long("detach"))
+        )
+        .subcommand(SubCommand::with_name("restore")
+            .about("restore working tree files")
+            .arg(Arg::with_name("pathspec")
+            .help("files to restore")
+            .multiple(true)
+            .required(true))
+            .arg(Arg::with_name("source")
+            .help("restore from the given tree")
+            .short("s")
+            .long("source")
+            .takes_value(true))
+            .arg(Arg::with_name("staged")
+            .help("restore the index")
+            .short("S")
+            .long("staged"))
+            .arg(Arg::with_name("worktree")
+            .help("restore the working tree, the default")
+            .short("W")
+            .long("worktree"))
+        )
+        .subcommand(SubCommand::with_name("stash")
+            .about("stash the changes in a dirty working directory away")
+            .arg(Arg::with_name("include-untracked")
+            .help("stash untracked files too")
+            .short("u")
+            .long("include-untracked"))
+            .arg(Arg::with_name("message")
+            .help("describe the stash")
+            .short("m")
+            .long("message")
+            .takes_value(true))
+            .subcommand(SubCommand::with_name("push")
+                .about("save local modifications to a new stash entry")
+                .arg(Arg::with_name("pathspec")
+                .help("stash only these paths")
+                .multiple(true)
+                .last(true))
+                .arg(Arg::with_name("include-untracked")
+                .help("stash untracked files too")
+                .short("u")
+                .long("include-untracked"))
+                .arg(Arg::with_name("message")
+                .help("describe the stash")
+                .short("m")
+                .long("message")
+                .takes_value(true)))
+            .subcommand(SubCommand::with_name("pop")
+                .about("apply a stash and remove it from the list")
+                .arg(Arg::with_name("stash"))
+                .arg(Arg::with_name("index")
+                .help("reinstate the staged changes too")
+                .long("index")))
+            .subcommand(SubCommand::with_name("apply")
+                .about("apply a stash and keep it in the list")
+                .arg(Arg::with_name("stash"))
+                .arg(Arg::with_name("index")
+                .help("reinstate the staged changes too")
+                .long("index")))
+            .subcommand(SubCommand::with_name("list")
+                .about("list the stash entries"))
+            .subcommand(SubCommand::with_name("show")
+                .about("show the changes recorded in a stash")
+                .arg(Arg::with_name("stash"))
+                .arg(Arg::with_name("patch")
+                .help("show the changes as a patch")
+                .short("p")
+                .long("patch")))
+            .subcommand(SubCommand::with_name("drop")
+                .about("remove a stash entry")
+                .arg(Arg::with_name("stash")))
+            .subcommand(SubCommand::with_name("clear")
+                .about("remove all stash entries"))
+            .subcommand(SubCommand::with_name("branch")
+                .about("create a branch at the stashed commit and apply the stash there")
+                .arg(Arg::with_name("branch")
+                .required(true))
+                .arg(Arg::with_name("stash")))
+        )
+        .subcommand(SubCommand::with_name("rm")
+            .about("remove files from the working tree and from the index")
+            .arg(Arg::with_name("pathspec")
+            .help("files to remove")
+            .multiple(true)
+            .required(true))
+            .arg(Arg::with_name("cached")
+            .help("only remove from the index")
+            .long("cached"))
+            .arg(Arg::with_name("recursive")
+            .help("allow recursive removal when a leading directory name is given")
+            .short("r"))
+            .arg(Arg::with_name("force")
+            .help("override the up-to-date check")
+            .short("f")
+            .long("force"))
+        )
+        .subcommand(SubCommand::with_name("mv")
+            .about("move or rename a file or a directory")
+            .arg(Arg::with_name("source")
+            .help("files or directories to move")
+            .multiple(true)
+            .required(true))
+            .arg(Arg::with_name("destination")
+            .help("the new name, or the directory to move into")
+            .required(true))
+        )
+        .subcommand(SubCommand::with_name("check-ignore")
+            .about("debug gitignore / exclude files")
+            .arg(Arg::with_name("path")
+            .help("paths to check")
+            .multiple(true))
+            .arg(Arg::with_name("verbose")
+                .help("output the matching pattern for each path")
+                .short("v")
+                .long("verbose"))
+            .arg(Arg::with_name("non-matching")
+                .help("show paths that match no pattern too")
+                .short("n")
+                .long("non-matching"))
+            .arg(Arg::with_name("no-index")
+                .help("do not look in the index when undertaking the checks")
+                .long("no-index"))
+            .arg(Arg::with_name("stdin")
+                .help("read paths from the standard input, one per line")
+                .long("stdin"))
         );
 
     // parse subcommands and arguments
@@ -160,6 +571,10 @@ fn main() {
     match matches.subcommand_matches("cat-file") {
         Some(matches) => {
             let sha1 = matches.value_of("hash").unwrap();
+            if let Some(object) = matches.value_of("object") {
+                cat_file::cat_file_typed(sha1, object).unwrap();
+                return;
+            }
             if let Some(_) = matches.args.get("type") {
                 cat_file::cat_file(sha1, cat_file::CatFileType::Type).unwrap();
             }
@@ -182,6 +597,18 @@ fn main() {
     };
     match matches.subcommand_matches("update-index") {
         Some(matches) => {
+            let refresh = if let Some(_) = matches.args.get("refresh") { true } else { false };
+            let really_refresh = if let Some(_) = matches.args.get("really-refresh") { true } else { false };
+            if let Some(version) = matches.value_of("index-version") {
+                update_index::index_version(version).unwrap();
+                return;
+            }
+            if refresh || really_refresh {
+                if !update_index::refresh(really_refresh).unwrap() {
+                    std::process::exit(1);
+                }
+                return;
+            }
             match matches.values_of("cacheinfo") {
                 Some(val) => {
                     let values: Vec<&str> = val.collect();
@@ -208,14 +635,22 @@ fn main() {
     match matches.subcommand_matches("ls-files") {
         Some(matches) => {
             let staged = if let Some(_) = matches.args.get("stage") { true } else { false };
-            ls_files::ls_files(staged).unwrap();
+            let unmerged = if let Some(_) = matches.args.get("unmerged") { true } else { false };
+            ls_files::ls_files(staged, unmerged).unwrap();
         },
         None => {}
     };
     match matches.subcommand_matches("add") {
         Some(matches) => {
-            let files: Vec<&str> = matches.values_of("file").unwrap().collect();
-            add::add(files).unwrap();
+            let files: Vec<&str> = match matches.values_of("file") {
+                Some(files) => files.collect(),
+                None => Vec::new(),
+            };
+            let all = if let Some(_) = matches.args.get("all") { true } else { false };
+            let update = if let Some(_) = matches.args.get("update") { true } else { false };
+            let dry_run = if let Some(_) = matches.args.get("dry-run") { true } else { false };
+            let force = if let Some(_) = matches.args.get("force") { true } else { false };
+            add::add(files, all, update, dry_run, force).unwrap();
         },
         None => {}
     };
@@ -241,8 +676,7 @@ fn main() {
     };
     match matches.subcommand_matches("commit") {
         Some(matches) => {
-            let message = matches.value_of("message").unwrap();
-            commit::commit(message).unwrap();
+            commit::commit(matches.value_of("message")).unwrap();
         },
         None => {},
     };
@@ -251,17 +685,42 @@ fn main() {
         None => {},
     };
     match matches.subcommand_matches("status") {
-        Some(_) => status::status().unwrap(),
+        Some(matches) => {
+            let format = match matches.args.get("porcelain") {
+                Some(_) if matches.value_of("porcelain") == Some("v2") => status::StatusFormat::PorcelainV2,
+                Some(_) => status::StatusFormat::Porcelain,
+                None if matches.args.get("short").is_some() => status::StatusFormat::Short,
+                None => status::StatusFormat::Long,
+            };
+            // -u alone shows all files
+            let untracked = match matches.args.get("untracked-files") {
+                Some(_) => match matches.value_of("untracked-files") {
+                    Some("no") => status::UntrackedMode::No,
+                    Some("normal") => status::UntrackedMode::Normal,
+                    _ => status::UntrackedMode::All,
+                },
+                None => status::UntrackedMode::Normal,
+            };
+            let branch = if let Some(_) = matches.args.get("branch") { true } else { false };
+            let nul = if let Some(_) = matches.args.get("z") { true } else { false };
+            let ignored = if let Some(_) = matches.args.get("ignored") { true } else { false };
+            status::status(format, branch, nul, untracked, ignored).unwrap();
+        },
         None => {},
     };
     match matches.subcommand_matches("diff") {
-        Some(_) => diff::diff().unwrap(),
+        Some(matches) => {
+            let context = matches.value_of("unified").and_then(|n| n.parse::<usize>().ok()).unwrap_or(diff::DEFAULT_CONTEXT);
+            diff::diff(context).unwrap();
+        },
         None => {},
     };
     match matches.subcommand_matches("branch") {
         Some(matches) => {
             let branch_name = matches.value_of("branch-name");
-            branch::branch(branch_name).unwrap()
+            let delete = if let Some(_) = matches.args.get("delete") { true } else { false };
+            let force = matches.args.contains_key("force-delete");
+            branch::branch(branch_name, delete, force).unwrap()
         },
         None => {},
     };
@@ -273,4 +732,223 @@ fn main() {
         },
         None => {},
     };
+    match matches.subcommand_matches("show-ref") {
+        Some(matches) => {
+            let patterns: Vec<&str> = matches.values_of("pattern").map(|v| v.collect()).unwrap_or_default();
+            let heads = if let Some(_) = matches.args.get("heads") { true } else { false };
+            let tags = if let Some(_) = matches.args.get("tags") { true } else { false };
+            let verify = if let Some(_) = matches.args.get("verify") { true } else { false };
+            show_ref::show_ref(patterns, heads, tags, verify).unwrap();
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("for-each-ref") {
+        Some(matches) => {
+            let patterns: Vec<&str> = matches.values_of("pattern").map(|v| v.collect()).unwrap_or_default();
+            let sort: Vec<&str> = matches.values_of("sort").map(|v| v.collect()).unwrap_or_default();
+            let count = matches.value_of("count").map(|c| c.parse::<usize>().expect("--count must be a number"));
+            for_each_ref::for_each_ref(patterns, matches.value_of("format"), sort, count, matches.value_of("points-at")).unwrap();
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("merge") {
+        Some(matches) => {
+            let branch = matches.value_of("branch").unwrap();
+            let no_ff = if let Some(_) = matches.args.get("no-ff") { true } else { false };
+            if !cmd::merge::merge(branch, no_ff, matches.value_of("message")).unwrap() {
+                std::process::exit(1);
+            }
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("merge-base") {
+        Some(matches) => {
+            let commits: Vec<&str> = matches.values_of("commit").unwrap().collect();
+            let all = if let Some(_) = matches.args.get("all") { true } else { false };
+            let octopus = if let Some(_) = matches.args.get("octopus") { true } else { false };
+            let is_ancestor = if let Some(_) = matches.args.get("is-ancestor") { true } else { false };
+            let independent = if let Some(_) = matches.args.get("independent") { true } else { false };
+            if !merge_base::merge_base(commits, all, octopus, is_ancestor, independent).unwrap() {
+                std::process::exit(1);
+            }
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("rebase") {
+        Some(matches) => {
+            let option = if let Some(_) = matches.args.get("continue") {
+                rebase::RebaseOption::Continue
+            } else if matches.args.contains_key("skip") {
+                rebase::RebaseOption::Skip
+            } else if let Some(_) = matches.args.get("abort") {
+                rebase::RebaseOption::Abort
+            } else {
+                rebase::RebaseOption::Start {
+                    upstream: String::from(matches.value_of("upstream").unwrap()),
+                    interactive: if let Some(_) = matches.args.get("interactive") { true } else { false },
+                    autosquash: if let Some(_) = matches.args.get("autosquash") { true } else { false },
+                }
+            };
+            if !rebase::rebase(option).unwrap() {
+                std::process::exit(1);
+            }
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("cherry-pick") {
+        Some(matches) => {
+            let option = if let Some(_) = matches.args.get("continue") {
+                cherry_pick::CherryPickOption::Continue
+            } else if matches.args.contains_key("skip") {
+                cherry_pick::CherryPickOption::Skip
+            } else if let Some(_) = matches.args.get("abort") {
+                cherry_pick::CherryPickOption::Abort
+            } else {
+                cherry_pick::CherryPickOption::Start {
+                    commits: matches.values_of("commit").unwrap().map(String::from).collect(),
+                    no_commit: if let Some(_) = matches.args.get("no-commit") { true } else { false },
+                    record_origin: if let Some(_) = matches.args.get("x") { true } else { false },
+                    mainline: matches.value_of("mainline").map(|m| m.parse::<usize>().expect("mainline must be a number")),
+                }
+            };
+            if !cherry_pick::cherry_pick(option).unwrap() {
+                std::process::exit(1);
+            }
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("revert") {
+        Some(matches) => {
+            let option = if let Some(_) = matches.args.get("continue") {
+                revert::RevertOption::Continue
+            } else if matches.args.contains_key("skip") {
+                revert::RevertOption::Skip
+            } else if let Some(_) = matches.args.get("abort") {
+                revert::RevertOption::Abort
+            } else {
+                revert::RevertOption::Start {
+                    commits: matches.values_of("commit").unwrap().map(String::from).collect(),
+                    no_commit: if let Some(_) = matches.args.get("no-commit") { true } else { false },
+                    mainline: matches.value_of("mainline").map(|m| m.parse::<usize>().expect("mainline must be a number")),
+                }
+            };
+            if !revert::revert(option).unwrap() {
+                std::process::exit(1);
+            }
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("reset") {
+        Some(matches) => {
+            let mode = if let Some(_) = matches.args.get("soft") {
+                reset::ResetMode::Soft
+            } else if let Some(_) = matches.args.get("hard") {
+                reset::ResetMode::Hard
+            } else if let Some(_) = matches.args.get("keep") {
+                reset::ResetMode::Keep
+            } else {
+                reset::ResetMode::Mixed
+            };
+            let paths: Vec<&str> = match matches.values_of("paths") {
+                Some(paths) => paths.collect(),
+                None => Vec::new(),
+            };
+            reset::reset(matches.value_of("commit"), mode, paths).unwrap();
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("switch") {
+        Some(matches) => {
+            let mode = if let Some(_) = matches.args.get("create") {
+                switch::SwitchMode::Create
+            } else if let Some(_) = matches.args.get("force-create") {
+                switch::SwitchMode::ForceCreate
+            } else if let Some(_) = matches.args.get("detach") {
+                switch::SwitchMode::Detach
+            } else {
+                switch::SwitchMode::Branch
+            };
+            switch::switch(matches.value_of("branch").unwrap(), matches.value_of("start-point"), mode).unwrap();
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("restore") {
+        Some(matches) => {
+            let paths: Vec<&str> = matches.values_of("pathspec").unwrap().collect();
+            let staged = if let Some(_) = matches.args.get("staged") { true } else { false };
+            let worktree = if let Some(_) = matches.args.get("worktree") { true } else { false };
+            restore::restore(paths, matches.value_of("source"), staged, worktree).unwrap();
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("stash") {
+        Some(matches) => {
+            let stash_name = |m: &clap::ArgMatches| m.value_of("stash").map(String::from);
+            let option = match matches.subcommand() {
+                ("pop", Some(m)) => stash::StashOption::Pop {
+                    stash: stash_name(m),
+                    index: if let Some(_) = m.args.get("index") { true } else { false },
+                },
+                ("apply", Some(m)) => stash::StashOption::Apply {
+                    stash: stash_name(m),
+                    index: if let Some(_) = m.args.get("index") { true } else { false },
+                },
+                ("list", Some(_)) => stash::StashOption::List,
+                ("show", Some(m)) => stash::StashOption::Show {
+                    stash: stash_name(m),
+                    patch: if let Some(_) = m.args.get("patch") { true } else { false },
+                },
+                ("drop", Some(m)) => stash::StashOption::Drop { stash: stash_name(m) },
+                ("clear", Some(_)) => stash::StashOption::Clear,
+                ("branch", Some(m)) => stash::StashOption::Branch {
+                    name: String::from(m.value_of("branch").unwrap()),
+                    stash: stash_name(m),
+                },
+                (_, m) => {
+                    // stash alone is stash push
+                    let m = m.unwrap_or(matches);
+                    stash::StashOption::Push {
+                        paths: m.values_of("pathspec").map(|v| v.map(String::from).collect()).unwrap_or_default(),
+                        message: m.value_of("message").map(String::from),
+                        untracked: if let Some(_) = m.args.get("include-untracked") { true } else { false },
+                    }
+                },
+            };
+            stash::stash(option).unwrap();
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("rm") {
+        Some(matches) => {
+            let paths: Vec<&str> = matches.values_of("pathspec").unwrap().collect();
+            let cached = if let Some(_) = matches.args.get("cached") { true } else { false };
+            let recursive = if let Some(_) = matches.args.get("recursive") { true } else { false };
+            let force = if let Some(_) = matches.args.get("force") { true } else { false };
+            rm::rm(paths, cached, recursive, force).unwrap();
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("mv") {
+        Some(matches) => {
+            let sources: Vec<&str> = matches.values_of("source").unwrap().collect();
+            mv::mv(sources, matches.value_of("destination").unwrap()).unwrap();
+        },
+        None => {},
+    };
+    match matches.subcommand_matches("check-ignore") {
+        Some(matches) => {
+            let paths: Vec<&str> = match matches.values_of("path") {
+                Some(paths) => paths.collect(),
+                None => Vec::new(),
+            };
+            let verbose = if let Some(_) = matches.args.get("verbose") { true } else { false };
+            let non_matching = if let Some(_) = matches.args.get("non-matching") { true } else { false };
+            let no_index = if let Some(_) = matches.args.get("no-index") { true } else { false };
+            let stdin = if let Some(_) = matches.args.get("stdin") { true } else { false };
+            if !check_ignore::check_ignore(paths, verbose, non_matching, no_index, stdin).unwrap() {
+                std::process::exit(1);
+            }
+        },
+        None => {},
+    };
 }
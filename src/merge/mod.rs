@@ -0,0 +1,416 @@
+pub mod text;
+
+use std::io;
+use std::fs;
+use std::io::Write;
+use std::collections::{BTreeSet, HashMap};
+use crate::index;
+use crate::index::Entry;
+use crate::object::Object;
+use crate::object::blob::Blob;
+use crate::object::tree::Tree;
+use crate::worktree;
+use crate::cmd::cat_file::hash_key_to_path;
+use crate::cmd::{GIT_INDEX, GIT_MERGE_HEAD, GIT_MERGE_MSG};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version {
+    pub mode: u32,
+    pub hash: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeEntry {
+    pub name: String,
+    pub base: Option<Version>,
+    pub ours: Option<Version>,
+    pub theirs: Option<Version>,
+    // the merged version, None when deleted or conflicted
+    pub result: Option<Version>,
+    // what is left in the working tree for a conflicted path, the surviving version when None
+    pub content: Option<String>,
+    pub conflict: Option<String>,
+    pub auto_merged: bool,
+}
+
+impl Version {
+    pub fn new(mode: u32, hash: &[u8]) -> Version {
+        Version {
+            mode,
+            hash: hash.to_vec(),
+        }
+    }
+}
+
+impl MergeEntry {
+    fn new(name: &str, base: Option<Version>, ours: Option<Version>, theirs: Option<Version>) -> MergeEntry {
+        MergeEntry {
+            name: String::from(name),
+            base,
+            ours,
+            theirs,
+            result: None,
+            content: None,
+            conflict: None,
+            auto_merged: false,
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.conflict.is_none()
+    }
+
+    pub fn is_changed(&self) -> bool {
+        // whether the index and the working tree have to be touched
+        !self.is_clean() || self.result != self.ours
+    }
+}
+
+pub fn read_tree_files(tree: Option<&str>) -> io::Result<HashMap<String, Version>> {
+    let tree = match tree {
+        Some(tree) => Tree::from_hash_file(&hash_key_to_path(tree))?,
+        None => return Ok(HashMap::new()),
+    };
+    Ok(tree.flatten("")?.into_iter()
+        .map(|f| (f.name, Version::new(f.mode as u32, &f.hash)))
+        .collect())
+}
+
+// how merge_files reads a blob and stores a merged one, the object database outside tests
+type ReadBlob<'a> = &'a dyn Fn(&[u8]) -> io::Result<Blob>;
+type WriteBlob<'a> = &'a dyn Fn(Blob) -> io::Result<Vec<u8>>;
+
+pub fn merge_trees(base: Option<&str>, ours: &str, theirs: &str, ours_label: &str, theirs_label: &str) -> io::Result<Vec<MergeEntry>> {
+    let base = read_tree_files(base)?;
+    let ours = read_tree_files(Some(ours))?;
+    let theirs = read_tree_files(Some(theirs))?;
+    merge_files(&base, &ours, &theirs, ours_label, theirs_label, &read_blob, &write_blob)
+}
+
+fn read_blob(hash: &[u8]) -> io::Result<Blob> {
+    Blob::from_hash_file(&hash_key_to_path(&hex::encode(hash)))
+}
+
+fn write_blob(blob: Blob) -> io::Result<Vec<u8>> {
+    let hash = Object::Blob(blob).write()?;
+    hex::decode(hash).or(Err(io::Error::from(io::ErrorKind::InvalidData)))
+}
+
+fn merge_files(
+    base: &HashMap<String, Version>,
+    ours: &HashMap<String, Version>,
+    theirs: &HashMap<String, Version>,
+    ours_label: &str,
+    theirs_label: &str,
+    read: ReadBlob,
+    write: WriteBlob,
+) -> io::Result<Vec<MergeEntry>> {
+    let names = base.keys().chain(ours.keys()).chain(theirs.keys()).collect::<BTreeSet<&String>>();
+    let mut entries: Vec<MergeEntry> = Vec::new();
+    for name in names {
+        let mut entry = MergeEntry::new(name, base.get(name).cloned(), ours.get(name).cloned(), theirs.get(name).cloned());
+        if entry.ours == entry.theirs || entry.base == entry.theirs {
+            entry.result = entry.ours.clone();
+        } else if entry.base == entry.ours {
+            entry.result = entry.theirs.clone();
+        } else {
+            merge_entry(&mut entry, ours_label, theirs_label, read, write)?;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn merge_entry(entry: &mut MergeEntry, ours_label: &str, theirs_label: &str, read: ReadBlob, write: WriteBlob) -> io::Result<()> {
+    // both sides changed the path in different ways
+    match (&entry.base, &entry.ours, &entry.theirs) {
+        (base, Some(ours), Some(theirs)) => {
+            let mode = match &base {
+                Some(base) if base.mode == ours.mode => theirs.mode,
+                _ => ours.mode,
+            };
+            let kind = if base.is_some() { "content" } else { "add/add" };
+            let base_blob = match base {
+                Some(base) => read(&base.hash)?,
+                None => Blob::new(""),
+            };
+            let ours_blob = read(&ours.hash)?;
+            let theirs_blob = read(&theirs.hash)?;
+            entry.auto_merged = true;
+            let (base_text, ours_text, theirs_text) = match (base_blob.text(), ours_blob.text(), theirs_blob.text()) {
+                (Some(base), Some(ours), Some(theirs)) => (base, ours, theirs),
+                _ => {
+                    // no line merge for binary files, our version stays in the working tree
+                    entry.conflict = Some(format!(
+                        "warning: Cannot merge binary files: {} ({} vs. {})\nCONFLICT ({}): Merge conflict in {}",
+                        entry.name, ours_label, theirs_label, kind, entry.name
+                    ));
+                    return Ok(());
+                },
+            };
+            let merged = text::merge(base_text, ours_text, theirs_text, ours_label, theirs_label);
+            if merged.is_clean() {
+                let hash = write(Blob::new(&merged.content))?;
+                entry.result = Some(Version::new(mode, &hash));
+            } else {
+                entry.conflict = Some(format!("CONFLICT ({}): Merge conflict in {}", kind, entry.name));
+                entry.content = Some(merged.content);
+            }
+        },
+        (_, Some(_), None) => {
+            entry.conflict = Some(format!(
+                "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  Version {} of {} left in tree.",
+                entry.name, theirs_label, ours_label, ours_label, entry.name
+            ));
+        },
+        (_, None, Some(_)) => {
+            entry.conflict = Some(format!(
+                "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  Version {} of {} left in tree.",
+                entry.name, ours_label, theirs_label, theirs_label, entry.name
+            ));
+        },
+        (_, None, None) => entry.result = None,
+    }
+    Ok(())
+}
+
+pub fn check_worktree(entries: &[MergeEntry]) -> io::Result<()> {
+    // refuse to overwrite local changes in the paths the merge touches
+    let index = index::read_index(GIT_INDEX)?;
+    let dirty = entries.iter()
+        .filter(|e| e.is_changed())
+        .filter(|e| {
+            let ours = e.ours.as_ref().map(|v| v.hash.clone());
+            let staged = index.entries.iter().find(|i| i.name == e.name).map(|i| i.hash.clone());
+            staged != ours || worktree::file_hash(&e.name) != ours
+        })
+        .map(|e| e.name.clone())
+        .collect::<Vec<String>>();
+    if dirty.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Your local changes to the following files would be overwritten by merge:\n\t{}", dirty.join("\n\t")),
+    ))
+}
+
+pub fn checkout_entries(entries: &[MergeEntry]) -> io::Result<()> {
+    // write the merge result to the working tree and the index,
+    // conflicted paths get their base, ours and theirs versions as stage 1, 2 and 3
+    let index = index::read_index(GIT_INDEX)?;
+    let changed = entries.iter().filter(|e| e.is_changed()).collect::<Vec<&MergeEntry>>();
+    let mut index_entries: Vec<Entry> = Vec::new();
+    for entry in changed.iter() {
+        if entry.is_clean() {
+            match &entry.result {
+                Some(v) => {
+                    worktree::write_blob(&entry.name, &v.hash, v.mode)?;
+                    index_entries.push(Entry::from_name(v.hash.clone(), &entry.name)?);
+                },
+                None => worktree::remove_file(&entry.name)?,
+            }
+            continue;
+        }
+        let stages = [(1, &entry.base), (2, &entry.ours), (3, &entry.theirs)];
+        for (stage, version) in stages.iter() {
+            if let Some(v) = version {
+                index_entries.push(Entry::from_stage(v.hash.clone(), &entry.name, v.mode, *stage));
+            }
+        }
+        match (&entry.content, entry.ours.as_ref().or(entry.theirs.as_ref())) {
+            (Some(content), v) => worktree::write_file(&entry.name, content.as_bytes(), v.map_or(100644, |v| v.mode))?,
+            (None, Some(v)) => worktree::write_blob(&entry.name, &v.hash, v.mode)?,
+            (None, None) => {},
+        }
+    }
+    let names = changed.iter().map(|e| e.name.clone()).collect::<BTreeSet<String>>();
+    index::write_index(GIT_INDEX, &index::replace_paths(index, &names, index_entries))
+}
+
+pub fn apply_change(base: Option<&str>, ours: &str, theirs: &str, theirs_label: &str) -> io::Result<Vec<MergeEntry>> {
+    // replay the change from base to theirs on top of ours, as cherry-pick and rebase do
+    let entries = merge_trees(base, ours, theirs, "HEAD", theirs_label)?;
+    check_worktree(&entries)?;
+    checkout_entries(&entries)?;
+    Ok(entries)
+}
+
+pub fn describe(entries: &[MergeEntry]) -> Vec<String> {
+    let mut messages: Vec<String> = Vec::new();
+    for entry in entries.iter() {
+        // git warns about a binary file before it says it merges it
+        let (warnings, conflicts): (Vec<&str>, Vec<&str>) = entry.conflict.iter()
+            .flat_map(|c| c.lines())
+            .partition(|l| l.starts_with("warning: "));
+        messages.extend(warnings.into_iter().map(String::from));
+        if entry.auto_merged {
+            messages.push(format!("Auto-merging {}", entry.name));
+        }
+        messages.extend(conflicts.into_iter().map(String::from));
+    }
+    messages
+}
+
+pub fn write_merge_state(hash: &str, message: &str) -> io::Result<()> {
+    let mut file = fs::File::create(GIT_MERGE_HEAD)?;
+    file.write_all(format!("{}\n", hash).as_bytes())?;
+    let mut file = fs::File::create(GIT_MERGE_MSG)?;
+    file.write_all(message.as_bytes())
+}
+
+pub fn read_merge_head() -> io::Result<Option<String>> {
+    match fs::read_to_string(GIT_MERGE_HEAD) {
+        Ok(content) => Ok(Some(String::from(content.trim()))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn read_merge_msg() -> io::Result<Option<String>> {
+    match fs::read_to_string(GIT_MERGE_MSG) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn clear_merge_state() -> io::Result<()> {
+    for path in [GIT_MERGE_HEAD, GIT_MERGE_MSG].iter() {
+        match fs::remove_file(path) {
+            Ok(_) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use crate::object::blob::Blob;
+    use super::{merge_files, describe, MergeEntry, Version};
+
+    fn version(mode: u32, content: &[u8]) -> Version {
+        Version::new(mode, &Blob::from(content).unwrap().calc_hash())
+    }
+
+    fn files(list: &[(&str, u32, &[u8])]) -> HashMap<String, Version> {
+        list.iter().map(|(name, mode, content)| (String::from(*name), version(*mode, content))).collect()
+    }
+
+    fn merge(base: &[(&str, u32, &[u8])], ours: &[(&str, u32, &[u8])], theirs: &[(&str, u32, &[u8])]) -> (Vec<MergeEntry>, Vec<Blob>) {
+        // blobs live in a map instead of .git/objects, merged ones are collected
+        let blobs = base.iter().chain(ours.iter()).chain(theirs.iter())
+            .map(|(_, _, content)| (Blob::from(content).unwrap().calc_hash(), Blob::from(content).unwrap()))
+            .collect::<HashMap<Vec<u8>, Blob>>();
+        let written: RefCell<Vec<Blob>> = RefCell::new(Vec::new());
+        let read = |hash: &[u8]| blobs.get(hash).cloned().ok_or(io::Error::from(io::ErrorKind::NotFound));
+        let write = |blob: Blob| {
+            let hash = blob.calc_hash();
+            written.borrow_mut().push(blob);
+            Ok(hash)
+        };
+        let entries = merge_files(&files(base), &files(ours), &files(theirs), "HEAD", "feature", &read, &write).unwrap();
+        (entries, written.into_inner())
+    }
+
+    #[test]
+    fn test_merge_one_side() {
+        let base: &[(&str, u32, &[u8])] = &[("a", 100644, b"a\n"), ("b", 100644, b"b\n")];
+        let ours: &[(&str, u32, &[u8])] = &[("a", 100644, b"a\n"), ("b", 100644, b"b\n")];
+        let theirs: &[(&str, u32, &[u8])] = &[("a", 100644, b"A\n"), ("c", 100644, b"c\n")];
+        let (entries, written) = merge(base, ours, theirs);
+        let names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        assert!(entries.iter().all(|e| e.is_clean() && !e.auto_merged));
+        assert_eq!(entries[0].result, Some(version(100644, b"A\n")));
+        assert_eq!(entries[1].result, None);
+        assert_eq!(entries[2].result, Some(version(100644, b"c\n")));
+        assert!(entries.iter().all(|e| e.is_changed()));
+        assert!(written.is_empty());
+
+        // the other way round our versions stay and nothing changes
+        let (entries, _) = merge(base, theirs, ours);
+        assert!(entries.iter().all(|e| e.is_clean() && !e.is_changed()));
+    }
+
+    #[test]
+    fn test_merge_same_change() {
+        let base: &[(&str, u32, &[u8])] = &[("a", 100644, b"a\n"), ("b", 100644, b"b\n")];
+        let both: &[(&str, u32, &[u8])] = &[("a", 100755, b"A\n"), ("c", 100644, b"c\n")];
+        let (entries, written) = merge(base, both, both);
+        assert!(entries.iter().all(|e| e.is_clean() && !e.is_changed() && !e.auto_merged));
+        assert_eq!(entries[0].result, Some(version(100755, b"A\n")));
+        assert_eq!(entries[1].result, None);
+        assert!(written.is_empty());
+    }
+
+    #[test]
+    fn test_merge_modify_delete() {
+        let base: &[(&str, u32, &[u8])] = &[("a", 100644, b"a\n")];
+        let modified: &[(&str, u32, &[u8])] = &[("a", 100644, b"A\n")];
+        let (entries, _) = merge(base, modified, &[]);
+        assert_eq!(entries[0].conflict.as_deref(), Some("CONFLICT (modify/delete): a deleted in feature and modified in HEAD.  Version HEAD of a left in tree."));
+        assert_eq!(entries[0].result, None);
+        assert_eq!(entries[0].content, None);
+
+        let (entries, _) = merge(base, &[], modified);
+        assert_eq!(entries[0].conflict.as_deref(), Some("CONFLICT (modify/delete): a deleted in HEAD and modified in feature.  Version feature of a left in tree."));
+        assert_eq!(entries[0].theirs, Some(version(100644, b"A\n")));
+        assert_eq!(entries[0].content, None);
+    }
+
+    #[test]
+    fn test_merge_add_add() {
+        let ours: &[(&str, u32, &[u8])] = &[("a", 100644, b"ours\n")];
+        let theirs: &[(&str, u32, &[u8])] = &[("a", 100644, b"theirs\n")];
+        let (entries, written) = merge(&[], ours, theirs);
+        assert!(entries[0].auto_merged);
+        assert_eq!(entries[0].conflict.as_deref(), Some("CONFLICT (add/add): Merge conflict in a"));
+        assert_eq!(entries[0].content.as_deref(), Some("<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\n"));
+        assert_eq!(entries[0].base, None);
+        assert!(written.is_empty());
+    }
+
+    #[test]
+    fn test_merge_mode() {
+        // a mode change on one side and a content change on the other both go in
+        let base: &[(&str, u32, &[u8])] = &[("a", 100644, b"a\nb\nc\n")];
+        let ours: &[(&str, u32, &[u8])] = &[("a", 100755, b"a\nb\nc\n")];
+        let theirs: &[(&str, u32, &[u8])] = &[("a", 100644, b"a\nb\nC\n")];
+        let (entries, written) = merge(base, ours, theirs);
+        assert!(entries[0].is_clean() && entries[0].auto_merged);
+        assert_eq!(entries[0].result, Some(version(100755, b"a\nb\nC\n")));
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].content, b"a\nb\nC\n");
+
+        let (entries, _) = merge(base, theirs, ours);
+        assert_eq!(entries[0].result, Some(version(100755, b"a\nb\nC\n")));
+    }
+
+    #[test]
+    fn test_merge_binary() {
+        let base: &[(&str, u32, &[u8])] = &[("bin", 100644, b"\0base")];
+        let ours: &[(&str, u32, &[u8])] = &[("bin", 100644, b"\0ours")];
+        let theirs: &[(&str, u32, &[u8])] = &[("bin", 100644, b"\0theirs")];
+        let (entries, written) = merge(base, ours, theirs);
+        assert_eq!(entries[0].conflict.as_deref(), Some("warning: Cannot merge binary files: bin (HEAD vs. feature)\nCONFLICT (content): Merge conflict in bin"));
+        // nothing is written over our version, all three go to stages 1, 2 and 3
+        assert_eq!(entries[0].content, None);
+        assert_eq!(entries[0].ours, Some(version(100644, b"\0ours")));
+        assert!(entries[0].base.is_some() && entries[0].theirs.is_some());
+        assert!(written.is_empty());
+
+        let theirs: &[(&str, u32, &[u8])] = &[("bin", 100644, &[0xff, b'\n'])];
+        let (entries, _) = merge(&[], ours, theirs);
+        assert_eq!(describe(&entries), vec![
+            "warning: Cannot merge binary files: bin (HEAD vs. feature)",
+            "Auto-merging bin",
+            "CONFLICT (add/add): Merge conflict in bin",
+        ]);
+    }
+}
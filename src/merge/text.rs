@@ -0,0 +1,164 @@
+use wu_diff;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Hunk<'a> {
+    // base[start..end] is replaced by lines
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeFile {
+    pub content: String,
+    pub conflicts: usize,
+}
+
+impl MergeFile {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts == 0
+    }
+}
+
+pub fn merge(base: &str, ours: &str, theirs: &str, ours_label: &str, theirs_label: &str) -> MergeFile {
+    // diff3 style: changes on one side are taken, overlapping changes on both sides conflict
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let ours = hunks(&base, &ours);
+    let theirs = hunks(&base, &theirs);
+
+    let mut content = String::new();
+    let mut conflicts = 0;
+    let mut pos = 0;
+    let (mut i, mut j) = (0, 0);
+    while i < ours.len() || j < theirs.len() {
+        let lo = match (ours.get(i), theirs.get(j)) {
+            (Some(a), Some(b)) => a.start.min(b.start),
+            (Some(a), None) => a.start,
+            (None, Some(b)) => b.start,
+            (None, None) => break,
+        };
+        // collect every hunk touching the region, from either side
+        let mut hi = lo;
+        let (group_start_i, group_start_j) = (i, j);
+        loop {
+            if let Some(a) = ours.get(i).filter(|a| a.start <= hi) {
+                hi = hi.max(a.end);
+                i += 1;
+            } else if let Some(b) = theirs.get(j).filter(|b| b.start <= hi) {
+                hi = hi.max(b.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+        base[pos..lo].iter().for_each(|l| content.push_str(l));
+        let ours_group = &ours[group_start_i..i];
+        let theirs_group = &theirs[group_start_j..j];
+        let a = apply(&base, ours_group, lo, hi);
+        let b = apply(&base, theirs_group, lo, hi);
+        if theirs_group.is_empty() || a == b {
+            a.iter().for_each(|l| content.push_str(l));
+        } else if ours_group.is_empty() {
+            b.iter().for_each(|l| content.push_str(l));
+        } else {
+            conflicts += 1;
+            content.push_str(&format!("<<<<<<< {}\n", ours_label));
+            push_lines(&mut content, &a);
+            content.push_str("=======\n");
+            push_lines(&mut content, &b);
+            content.push_str(&format!(">>>>>>> {}\n", theirs_label));
+        }
+        pos = hi;
+    }
+    base[pos..].iter().for_each(|l| content.push_str(l));
+    MergeFile { content, conflicts }
+}
+
+fn hunks<'a>(base: &[&str], other: &[&'a str]) -> Vec<Hunk<'a>> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut current: Option<Hunk> = None;
+    let mut pos = 0;
+    for result in wu_diff::diff(base, other) {
+        match result {
+            wu_diff::DiffResult::Common(elm) => {
+                if let Some(hunk) = current.take() {
+                    hunks.push(hunk);
+                }
+                pos = elm.old_index.map(|i| i + 1).unwrap_or(pos);
+            },
+            wu_diff::DiffResult::Removed(elm) => {
+                let hunk = current.get_or_insert(Hunk { start: pos, end: pos, lines: Vec::new() });
+                pos = elm.old_index.map(|i| i + 1).unwrap_or(pos);
+                hunk.end = pos;
+            },
+            wu_diff::DiffResult::Added(elm) => {
+                let hunk = current.get_or_insert(Hunk { start: pos, end: pos, lines: Vec::new() });
+                if let Some(line) = elm.new_index.and_then(|i| other.get(i)) {
+                    hunk.lines.push(line);
+                }
+            },
+        }
+    }
+    if let Some(hunk) = current {
+        hunks.push(hunk);
+    }
+    hunks
+}
+
+fn apply<'a>(base: &[&'a str], hunks: &[Hunk<'a>], lo: usize, hi: usize) -> Vec<&'a str> {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut pos = lo;
+    for hunk in hunks {
+        lines.extend_from_slice(&base[pos..hunk.start]);
+        lines.extend_from_slice(&hunk.lines);
+        pos = hunk.end;
+    }
+    lines.extend_from_slice(&base[pos..hi]);
+    lines
+}
+
+fn push_lines(content: &mut String, lines: &[&str]) {
+    // a side ending without newline must not glue onto the next marker
+    lines.iter().for_each(|l| content.push_str(l));
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::merge;
+
+    const BASE: &str = "a\nb\nc\nd\ne\n";
+
+    #[test]
+    fn test_merge_clean() {
+        let ours = "A\nb\nc\nd\ne\n";
+        let theirs = "a\nb\nc\nd\nE\n";
+        let res = merge(BASE, ours, theirs, "HEAD", "feature");
+        assert!(res.is_clean());
+        assert_eq!(res.content, "A\nb\nc\nd\nE\n");
+    }
+    #[test]
+    fn test_merge_same_change() {
+        let ours = "a\nB\nc\nd\ne\n";
+        let res = merge(BASE, ours, ours, "HEAD", "feature");
+        assert!(res.is_clean());
+        assert_eq!(res.content, ours);
+    }
+    #[test]
+    fn test_merge_conflict() {
+        let ours = "a\nb\nours\nd\ne\n";
+        let theirs = "a\nb\ntheirs\nd\ne\n";
+        let res = merge(BASE, ours, theirs, "HEAD", "feature");
+        assert_eq!(res.conflicts, 1);
+        assert_eq!(res.content, "a\nb\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\nd\ne\n");
+    }
+    #[test]
+    fn test_merge_without_newline() {
+        let res = merge("a\n", "a\nb", "a\nc", "HEAD", "feature");
+        assert_eq!(res.content, "a\n<<<<<<< HEAD\nb\n=======\nc\n>>>>>>> feature\n");
+    }
+}
@@ -8,28 +8,23 @@ use crate::object::{Object, ObjectType};
 #[derive(Debug, Clone)]
 pub struct Blob {
     pub size: usize,
-    pub content: String,
+    // the bytes as stored, a blob need not be text
+    pub content: Vec<u8>,
 }
 
 impl Blob {
     pub fn new(content: &str) -> Self {
         Blob {
             size: content.len(),
-            content: String::from(content),
+            content: Vec::from(content.as_bytes()),
         }
     }
 
     pub fn from(content: &[u8]) -> Option<Self> {
-        let data = String::from_utf8(content.to_vec());
-        match data {
-            Ok(d) => {
-                Some(Blob{
-                    size: d.len(),
-                    content: d,
-                })
-            },
-            Err(_) => None
-        }
+        Some(Blob {
+            size: content.len(),
+            content: content.to_vec(),
+        })
     }
 
     pub fn from_name(name: &str) -> io::Result<Blob> {
@@ -46,8 +41,15 @@ impl Blob {
 
     pub fn as_bytes(&self) -> Vec<u8> {
         let hdr = format!("{} {}\0", ObjectType::Blob.to_string(), self.size);
-        let content = format!("{}{}", hdr, self.content);
-        Vec::from(content.as_bytes())
+        [hdr.as_bytes(), &self.content].concat()
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        // None for a binary blob, one with a NUL byte or that is not UTF-8, like git decides
+        if self.content.contains(&0) {
+            return None;
+        }
+        std::str::from_utf8(&self.content).ok()
     }
 
     pub fn typ(&self) -> ObjectType {
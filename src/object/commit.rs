@@ -12,7 +12,7 @@ use crate::object::{Object, ObjectType};
 #[derive(Debug, Clone)]
 pub struct Commit {
     pub tree: String,
-    pub parent: Option<String>,
+    pub parents: Vec<String>,
     pub author: User,
     pub commiter: User,
     pub message: String,
@@ -26,7 +26,7 @@ pub struct User {
 }
 
 impl User {
-    fn new(name: &str, email: &str, time: DateTime<FixedOffset>) -> Self {
+    pub fn new(name: &str, email: &str, time: DateTime<FixedOffset>) -> Self {
         User {
             name: String::from(name),
             email: String::from(email),
@@ -34,22 +34,22 @@ impl User {
         }
     }
 
+    pub fn now(name: &str, email: &str) -> Self {
+        let utc = Utc::now();
+        let time = utc.with_timezone(&FixedOffset::east(9 * 360));
+        User::new(name, email, time)
+    }
+
     pub fn from(data: &str) -> Option<Self> {
-        let mut iter = data.split_whitespace();
-        // author or commiter
-        iter.next().unwrap();
-        // name
-        let name = String::from(iter.next().unwrap());
-        let email = iter.next().map(|d| String::from(d.trim_matches(|d| d == '<' || d == '>')))?;
+        // author A U Thor <author@example.com> 1616834749 +0900
+        let data = data.split_once(' ')?.1;
+        let email_head = data.find('<')?;
+        let email_tail = data.find('>')?;
+        let name = String::from(data[..email_head].trim());
+        let email = String::from(&data[(email_head + 1)..email_tail]);
+        let mut iter = data[(email_tail + 1)..].split_whitespace();
         let ts = Utc.timestamp(iter.next().and_then(|x| x.parse::<i64>().ok())?, 0);
-        let offset = iter.next().and_then(|d| d.parse::<i32>().ok())
-            .map(|x| {
-                if x < 0 {
-                    FixedOffset::west(x / 100 * 60 * 60)
-                } else {
-                    FixedOffset::east(x / 100 * 60 * 60)
-                }
-            })?;
+        let offset = iter.next().and_then(parse_offset)?;
         Some(User {
             name,
             email,
@@ -58,69 +58,81 @@ impl User {
     }
 }
 
+fn parse_offset(data: &str) -> Option<FixedOffset> {
+    // +0530 -> 5 hours 30 minutes east
+    if data.len() != 5 {
+        return None;
+    }
+    let sign = match &data[0..1] {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let hours = data[1..3].parse::<i32>().ok()?;
+    let minutes = data[3..5].parse::<i32>().ok()?;
+    Some(FixedOffset::east(sign * (hours * 60 * 60 + minutes * 60)))
+}
+
 impl fmt::Display for User {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let offset = self.timestamp.offset().local_minus_utc();
+        let sign = if offset < 0 { '-' } else { '+' };
+        let offset = offset.abs() / 60;
         write!(
             f,
-            "{} <{}> {} {:+05}",
+            "{} <{}> {} {}{:02}{:02}",
             self.name,
             self.email,
             self.timestamp.timestamp(),
-            self.timestamp.offset().local_minus_utc() / 36
+            sign,
+            offset / 60,
+            offset % 60
         )
     }
 }
 
 impl Commit {
-    pub fn new(tree: &str, parent: Option<&str>, author: User, commiter: User, message: &str) -> Self {
-        match parent {
-            Some(parent) => {
-                Commit {
-                    tree: String::from(tree),
-                    parent: Some(String::from(parent)),
-                    author,
-                    commiter,
-                    message: String::from(message),
-                }
-            },
-            None => {
-                Commit {
-                    tree: String::from(tree),
-                    parent: None,
-                    author,
-                    commiter,
-                    message: String::from(message),
-                }
-
-            }
+    pub fn new(tree: &str, parents: Vec<&str>, author: User, commiter: User, message: &str) -> Self {
+        Commit {
+            tree: String::from(tree),
+            parents: parents.into_iter().map(String::from).collect(),
+            author,
+            commiter,
+            message: String::from(message),
         }
     }
 
+    // the first parent is the branch the commit was made on
+    pub fn parent(&self) -> Option<&str> {
+        self.parents.first().map(|p| p.as_str())
+    }
+
     pub fn from(data: &[u8]) -> Option<Self> {
-        let mut lines = data.split(|&d| d == b'\n').filter(|&d| d != b"");
-        let c = lines.clone().count();
-        let tree = str::from_utf8(lines.next()?)
-            .ok()?
-            .split_whitespace()
-            .last()?;
-        let parent = if c == 5 {
-            // parent exists
-            Some(String::from(str::from_utf8(lines.next()?)
-            .ok()?
-            .split_whitespace()
-            .last()?))
-        } else {
-            None
+        // headers, a blank line, then the message
+        let data = str::from_utf8(data).ok()?;
+        let (hdr, message) = match data.find("\n\n") {
+            Some(i) => (&data[..i], &data[(i + 2)..]),
+            None => (data, ""),
         };
-        let author = User::from(str::from_utf8(lines.next()?).ok()?)?;
-        let commiter = User::from(str::from_utf8(lines.next()?).ok()?)?;
-        let message = String::from_utf8(lines.next()?.to_vec()).ok()?;
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut commiter = None;
+        for line in hdr.lines() {
+            match line.split(' ').next()? {
+                "tree" => tree = line.split_whitespace().last().map(String::from),
+                "parent" => parents.push(String::from(line.split_whitespace().last()?)),
+                "author" => author = User::from(line),
+                "committer" => commiter = User::from(line),
+                _ => {},
+            }
+        }
         Some(Commit {
-            tree: String::from(tree),
-            parent,
-            author,
-            commiter,
-            message,
+            tree: tree?,
+            parents,
+            author: author?,
+            commiter: commiter?,
+            message: String::from(message.strip_suffix('\n').unwrap_or(message)),
         })
     }
 
@@ -131,7 +143,10 @@ impl Commit {
         let mut decoder = Decoder::new(&buf[..])?;
         let mut data = Vec::new();
         decoder.read_to_end(&mut data)?;
-        let commit = Commit::from(&data).ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+        let mut iter = data.splitn(2, |&b| b == b'\0');
+        iter.next().ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+        let d = iter.next().ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+        let commit = Commit::from(d).ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
         Ok(commit)
     }
 
@@ -154,9 +169,9 @@ impl Commit {
 impl fmt::Display for Commit {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let tree = format!("tree {}", self.tree);
-        let parent = if let Some(parent) = &self.parent {
-            format!("parent {}\n", parent)
-        } else { String::from("") };
+        let parent = self.parents.iter()
+            .map(|p| format!("parent {}\n", p))
+            .collect::<String>();
         write!(f,
             "{}\n{}author {}\ncommitter {}\n\n{}\n",
             tree,
@@ -168,11 +183,9 @@ impl fmt::Display for Commit {
     }
 }
 
-pub fn commit_tree(name: &str, email: &str, tree_hash: &str, message: &str, parent: Option<&str>) -> io::Result<Commit> {
-    let utc = Utc::now();
-    let time = utc.with_timezone(&FixedOffset::east(9 * 360));
-    let user = User::new(name, email, time);
-    let commit = Commit::new(tree_hash, parent, user.clone(), user.clone(), message);
+pub fn commit_tree(name: &str, email: &str, tree_hash: &str, message: &str, parents: Vec<&str>) -> io::Result<Commit> {
+    let user = User::now(name, email);
+    let commit = Commit::new(tree_hash, parents, user.clone(), user.clone(), message);
     Ok(commit)
 }
 
@@ -186,16 +199,42 @@ mod tests {
         assert_eq!(user.name, String::from("terassyi"));
         assert_eq!(user.email, String::from("iscale821@gmail.com"));
     }
+    #[test]
+    fn test_user_from_with_space() {
+        let usr_str = "committer A U Thor <author@example.com> 1616834749 -0530";
+        let user = User::from(usr_str).unwrap();
+        assert_eq!(user.name, String::from("A U Thor"));
+        assert_eq!(user.email, String::from("author@example.com"));
+        assert_eq!(user.timestamp.offset().local_minus_utc(), -(5 * 60 * 60 + 30 * 60));
+        assert_eq!(format!("{}", user), "A U Thor <author@example.com> 1616834749 -0530");
+    }
     use super::Commit;
     #[test]
     fn test_commit_from() {
         let commit_str = "tree bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c\nparent a213f26901a29e8fecf60da136c31d61dd41544b\nauthor terassyi <iscale821@gmail.com> 1616834749 +0900\ncommitter terassyi <iscale821@gmail.com> 1616834749 +0900\n\nadd init cmd\n";
         let commit = Commit::from(commit_str.as_bytes()).unwrap();
         assert_eq!(commit.tree, String::from("bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c"));
-        assert_eq!(commit.parent, Some(String::from("a213f26901a29e8fecf60da136c31d61dd41544b")));
+        assert_eq!(commit.parents, vec![String::from("a213f26901a29e8fecf60da136c31d61dd41544b")]);
         assert_eq!(commit.message, String::from("add init cmd"));
     }
     #[test]
+    fn test_commit_from_multiline_message() {
+        let commit_str = "tree bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c\nauthor A U Thor <author@example.com> 1616834749 +0900\ncommitter A U Thor <author@example.com> 1616834749 +0900\n\nadd init cmd\n\nlong description\n";
+        let commit = Commit::from(commit_str.as_bytes()).unwrap();
+        assert_eq!(commit.parents.len(), 0);
+        assert_eq!(commit.author.name, String::from("A U Thor"));
+        assert_eq!(commit.message, String::from("add init cmd\n\nlong description"));
+        assert_eq!(format!("{}", commit), commit_str);
+    }
+    #[test]
+    fn test_commit_from_merge() {
+        let commit_str = "tree bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c\nparent a213f26901a29e8fecf60da136c31d61dd41544b\nparent 6b8710a711f3b689885aa5c26c6c06bde348e82b\nauthor terassyi <iscale821@gmail.com> 1616834749 +0900\ncommitter terassyi <iscale821@gmail.com> 1616834749 +0900\n\nMerge branch 'feature'\n";
+        let commit = Commit::from(commit_str.as_bytes()).unwrap();
+        assert_eq!(commit.parents.len(), 2);
+        assert_eq!(commit.parent(), Some("a213f26901a29e8fecf60da136c31d61dd41544b"));
+        assert_eq!(format!("{}", commit), commit_str);
+    }
+    #[test]
     fn test_commit_fmt() {
         let commit_str = "tree bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c\nparent a213f26901a29e8fecf60da136c31d61dd41544b\nauthor terassyi <iscale821@gmail.com> 1616834749 +0900\ncommitter terassyi <iscale821@gmail.com> 1616834749 +0900\n\nadd init cmd\n";
         let commit = Commit::from(commit_str.as_bytes()).unwrap();
@@ -208,7 +247,7 @@ mod tests {
         let email = "test@example.com";
         let tree_hash = "test_tree_hash";
         let message = "test message";
-        let commit = super::commit_tree(name, email, tree_hash, message, None).unwrap();
+        let commit = super::commit_tree(name, email, tree_hash, message, vec![]).unwrap();
         assert_eq!(commit.commiter.name, name);
         assert_eq!(commit.author.email, email);
         assert_eq!(commit.tree, tree_hash);
@@ -222,12 +261,12 @@ mod tests {
         let tree_hash = "test_tree_hash";
         let parent = "parent";
         let message = "test message";
-        let commit = super::commit_tree(name, email, tree_hash, message, Some(parent)).unwrap();
+        let commit = super::commit_tree(name, email, tree_hash, message, vec![parent]).unwrap();
         assert_eq!(commit.commiter.name, name);
         assert_eq!(commit.author.email, email);
         assert_eq!(commit.tree, tree_hash);
         assert_eq!(commit.message, message);
-        assert_eq!(commit.parent, Some(String::from(parent)));
+        assert_eq!(commit.parent(), Some(parent));
 
     }
 }
@@ -2,27 +2,37 @@
 pub mod blob;
 pub mod commit;
 pub mod tree;
+pub mod tag;
+pub mod stream;
 
 use std::str;
 use std::io;
 use std::fs;
 use std::io::Write;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use libflate::zlib::Encoder;
 
 use crate::object::blob::Blob;
 use crate::object::commit::Commit;
 use crate::object::tree::{Tree, File};
+use crate::object::tag::Tag;
 use crate::cmd::GIT_OBJECTS_DIR;
 
 const BLOB: &str = "blob";
 const COMMIT: &str = "commit";
 const TREE: &str = "tree";
+const TAG: &str = "tag";
+
+// tells apart the temporary files of objects written at the same time
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Debug, Clone)]
 pub enum Object {
     Blob(Blob),
     Commit(Commit),
     Tree(Tree),
+    Tag(Tag),
 }
 
 impl Object {
@@ -39,6 +49,7 @@ impl Object {
                 ObjectType::Blob => Blob::from(d).map(Object::Blob),
                 ObjectType::Commit => Commit::from(d).map(Object::Commit),
                 ObjectType::Tree => Tree::from(d).map(Object::Tree),
+                ObjectType::Tag => Tag::from(d).map(Object::Tag),
             }
         ).unwrap()
     }
@@ -48,6 +59,7 @@ impl Object {
             Object::Blob(blob) => blob.typ(),
             Object::Commit(commit) => commit.typ(),
             Object::Tree(tree) => tree.typ(),
+            Object::Tag(tag) => tag.typ(),
         }
     }
 
@@ -62,19 +74,23 @@ impl Object {
     }
 
     pub fn write(&self) -> io::Result<String> {
+        // safe to call from several threads at once: an object already stored is left alone,
+        // and a new one only appears under its name once it is complete
         let hash = hex::encode(self.calc_hash());
         let (sub_dir, name) = hash.split_at(2);
         let dir = format!("{}/{}", GIT_OBJECTS_DIR, sub_dir);
-        if !fs::metadata(&dir).is_ok() {
-            fs::create_dir(&dir)?;
-        }
         let file_path = format!("{}/{}", dir, name);
-        let mut file = fs::File::create(file_path)?;
+        if fs::metadata(&file_path).is_ok() {
+            return Ok(hash);
+        }
+        fs::create_dir_all(&dir)?;
         let mut encoder = Encoder::new(Vec::new())?;
         encoder.write_all(&self.as_bytes())?;
         let data = encoder.finish().into_result()?;
-        file.write(&data)?;
-        Ok(String::from(hash))
+        let temp_path = temp_path(&dir);
+        fs::write(&temp_path, &data)?;
+        fs::rename(&temp_path, &file_path)?;
+        Ok(hash)
     }
 
     pub fn calc_hash(&self) -> Vec<u8> {
@@ -82,6 +98,7 @@ impl Object {
             Object::Blob(blob) => blob.calc_hash(),
             Object::Commit(commit) => commit.calc_hash(),
             Object::Tree(tree) => tree.calc_hash(),
+            Object::Tag(tag) => tag.calc_hash(),
         } 
     }
 
@@ -90,24 +107,32 @@ impl Object {
             Object::Blob(blob) => blob.as_bytes(),
             Object::Commit(commit) => commit.as_bytes(),
             Object::Tree(tree) => tree.as_bytes(),
+            Object::Tag(tag) => tag.as_bytes(),
         }
     }
 }
 
+fn temp_path(dir: &str) -> String {
+    // a name no other writer, in this process or another, picks at the same time
+    format!("{}/tmp_obj_{}_{}", dir, process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ObjectType {
     Blob,
     Commit,
     Tree,
+    Tag,
 }
 
 impl ObjectType {
-    fn from(data: &str) -> Option<Self> {
+    pub fn from(data: &str) -> Option<Self> {
         let mut hdr = data.split_whitespace();
         match hdr.next()? {
             BLOB => Some(ObjectType::Blob),
             COMMIT => Some(ObjectType::Commit),
             TREE => Some(ObjectType::Tree),
+            TAG => Some(ObjectType::Tag),
             _ => None,
         }
     }
@@ -117,6 +142,7 @@ impl ObjectType {
             ObjectType::Blob => String::from(BLOB),
             ObjectType::Commit => String::from(COMMIT),
             ObjectType::Tree => String::from(TREE),
+            ObjectType::Tag => String::from(TAG),
         }
     }
 }
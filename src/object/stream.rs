@@ -0,0 +1,178 @@
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use libflate::zlib::{Encoder, Decoder};
+use sha1::{Sha1, Digest};
+use crate::object::ObjectType;
+use crate::cmd::GIT_OBJECTS_DIR;
+use crate::cmd::cat_file::hash_key_to_path;
+
+// how much of a file is held in memory at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct ObjectReader {
+    pub typ: ObjectType,
+    pub size: usize,
+    decoder: Decoder<BufReader<File>>,
+}
+
+impl ObjectReader {
+    pub fn open(hash: &str) -> io::Result<ObjectReader> {
+        ObjectReader::open_path(&hash_key_to_path(hash), hash)
+    }
+
+    fn open_path(path: &str, hash: &str) -> io::Result<ObjectReader> {
+        // inflate only the "<type> <size>\0" header, the content is read as it is asked for
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+        let mut header = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            decoder.read_exact(&mut byte)?;
+            if byte[0] == b'\0' {
+                break;
+            }
+            if header.len() > 32 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("object {} has a broken header", hash)));
+            }
+            header.push(byte[0]);
+        }
+        let header = String::from_utf8_lossy(&header);
+        let typ = ObjectType::from(&header);
+        let size = header.split_whitespace().nth(1).and_then(|s| s.parse::<usize>().ok());
+        match (typ, size) {
+            (Some(typ), Some(size)) => Ok(ObjectReader { typ, size, decoder }),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("object {} has a broken header", hash))),
+        }
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+pub fn hash_file(path: &str) -> io::Result<Vec<u8>> {
+    // the blob hash of a file, read a chunk at a time
+    let mut hasher = Sha1::new();
+    copy_file(path, |chunk| {
+        hasher.update(chunk);
+        Ok(())
+    })?;
+    Ok(Vec::from(hasher.finalize().as_slice()))
+}
+
+pub fn write_file(path: &str) -> io::Result<String> {
+    write_file_in(GIT_OBJECTS_DIR, path)
+}
+
+fn write_file_in(objects_dir: &str, path: &str) -> io::Result<String> {
+    // store a file as a blob without holding it in memory: hash and deflate in one pass
+    // into a temporary file, which takes the object's name once the hash is known
+    fs::create_dir_all(objects_dir)?;
+    let temp_path = super::temp_path(objects_dir);
+    let mut hasher = Sha1::new();
+    let written = File::create(&temp_path).and_then(|temp| {
+        let mut encoder = Encoder::new(BufWriter::new(temp))?;
+        copy_file(path, |chunk| {
+            hasher.update(chunk);
+            encoder.write_all(chunk)
+        })?;
+        encoder.finish().into_result()?.flush()
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    let hash = hex::encode(hasher.finalize());
+    let file_path = format!("{}/{}/{}", objects_dir, &hash[..2], &hash[2..]);
+    if fs::metadata(&file_path).is_ok() {
+        fs::remove_file(&temp_path)?;
+        return Ok(hash);
+    }
+    fs::create_dir_all(format!("{}/{}", objects_dir, &hash[..2]))?;
+    fs::rename(&temp_path, &file_path)?;
+    Ok(hash)
+}
+
+fn copy_file<F>(path: &str, mut f: F) -> io::Result<()>
+    where F: FnMut(&[u8]) -> io::Result<()>
+{
+    // the blob header, then the content in chunks; a file that changes size on the way is an error
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    f(format!("{} {}\0", ObjectType::Blob.to_string(), size).as_bytes())?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut total = 0u64;
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        total += n as u64;
+        if total > size {
+            break;
+        }
+        f(&buf[..n])?;
+    }
+    if total != size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} changed while it was read", path)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use crate::object::ObjectType;
+    use crate::object::blob::Blob;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("rusgit-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        String::from(dir.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_hash_file() {
+        let blob = Blob::from_name("Cargo.toml").unwrap();
+        assert_eq!(super::hash_file("Cargo.toml").unwrap(), blob.calc_hash());
+    }
+    #[test]
+    fn test_write_file() {
+        // binary content over several chunks comes back byte for byte
+        let dir = temp_dir("stream-write-file");
+        let path = format!("{}/data.bin", dir);
+        let content = (0..super::CHUNK_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        std::fs::write(&path, &content).unwrap();
+        let objects = format!("{}/objects", dir);
+        let hash = super::write_file_in(&objects, &path).unwrap();
+        assert_eq!(hash, hex::encode(Blob::from(&content).unwrap().calc_hash()));
+
+        let object_path = format!("{}/{}/{}", objects, &hash[..2], &hash[2..]);
+        let mut reader = super::ObjectReader::open_path(&object_path, &hash).unwrap();
+        assert_eq!(reader.typ, ObjectType::Blob);
+        assert_eq!(reader.size, content.len());
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, content);
+
+        // storing it again finds the object and leaves nothing behind
+        assert_eq!(super::write_file_in(&objects, &path).unwrap(), hash);
+        assert_eq!(std::fs::read_dir(&objects).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_write_file_error() {
+        // a file that cannot be read leaves no temporary file in the object store
+        let dir = temp_dir("stream-write-error");
+        let objects = format!("{}/objects", dir);
+        assert!(super::write_file_in(&objects, &format!("{}/missing", dir)).is_err());
+        assert_eq!(std::fs::read_dir(&objects).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
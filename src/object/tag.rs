@@ -0,0 +1,100 @@
+use sha1::{Sha1, Digest};
+use std::str;
+use std::fmt;
+
+use crate::object::ObjectType;
+use crate::object::commit::User;
+
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub object: String,
+    pub typ: ObjectType,
+    pub tag: String,
+    pub tagger: Option<User>,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn from(data: &[u8]) -> Option<Self> {
+        // object <hash>\ntype <type>\ntag <name>\ntagger <user>\n\n<message>
+        let data = str::from_utf8(data).ok()?;
+        let (hdr, message) = match data.find("\n\n") {
+            Some(i) => (&data[..i], &data[(i + 2)..]),
+            None => (data, ""),
+        };
+        let mut object = None;
+        let mut typ = None;
+        let mut tag = None;
+        let mut tagger = None;
+        for line in hdr.lines() {
+            let mut iter = line.splitn(2, ' ');
+            match (iter.next()?, iter.next()) {
+                ("object", Some(v)) => object = Some(String::from(v)),
+                ("type", Some(v)) => typ = ObjectType::from(v),
+                ("tag", Some(v)) => tag = Some(String::from(v)),
+                ("tagger", Some(_)) => tagger = User::from(line),
+                _ => {},
+            }
+        }
+        Some(Tag {
+            object: object?,
+            typ: typ?,
+            tag: tag?,
+            tagger,
+            message: String::from(message),
+        })
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let content = format!("{}", self);
+        let hdr = format!("tag {}\0", content.len());
+        [hdr.as_bytes(), content.as_bytes()].concat()
+    }
+
+    pub fn calc_hash(&self) -> Vec<u8> {
+        Vec::from(Sha1::digest(&self.as_bytes()).as_slice())
+    }
+
+    pub fn typ(&self) -> ObjectType {
+        ObjectType::Tag
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tagger = if let Some(tagger) = &self.tagger {
+            format!("tagger {}\n", tagger)
+        } else { String::from("") };
+        write!(f,
+            "object {}\ntype {}\ntag {}\n{}\n{}",
+            self.object,
+            self.typ.to_string(),
+            self.tag,
+            tagger,
+            self.message
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tag;
+    use crate::object::ObjectType;
+
+    const TAG: &str = "object a213f26901a29e8fecf60da136c31d61dd41544b\ntype commit\ntag v0.1.0\ntagger A U Thor <author@example.com> 1616834749 +0900\n\nfirst release\n";
+
+    #[test]
+    fn test_tag_from() {
+        let tag = Tag::from(TAG.as_bytes()).unwrap();
+        assert_eq!(tag.object, "a213f26901a29e8fecf60da136c31d61dd41544b");
+        assert_eq!(tag.typ, ObjectType::Commit);
+        assert_eq!(tag.tag, "v0.1.0");
+        assert_eq!(tag.tagger.unwrap().name, "A U Thor");
+        assert_eq!(tag.message, "first release\n");
+    }
+    #[test]
+    fn test_tag_fmt() {
+        let tag = Tag::from(TAG.as_bytes()).unwrap();
+        assert_eq!(format!("{}", tag), TAG);
+    }
+}
@@ -35,7 +35,7 @@ pub struct Tree {
 }
 
 impl File {
-    fn new(mode: usize, hash: &[u8], name: &str, typ: ObjectType) -> Self {
+    pub fn new(mode: usize, hash: &[u8], name: &str, typ: ObjectType) -> Self {
         File {
             mode,
             name: String::from(name),
@@ -47,14 +47,14 @@ impl File {
     pub fn from(hdr: &[u8], hash: &[u8]) -> Option<Self> {
         let iterstr = str::from_utf8(hdr).ok()?;
         let mut iter = iterstr
-                    .split_whitespace();
+                    .splitn(2, ' ');
         let mode = iter.next()
                     .and_then(|d| d.parse::<usize>().ok())?;
         let name = iter.next()?;
         Some(File {
             mode,
             name: String::from(name),
-            typ: is_dir(name),
+            typ: mode_to_type(mode),
             hash: hash.to_vec(),
         })
     }
@@ -71,18 +71,19 @@ impl File {
     }
 
     // This method should not use.
-    fn to_entry(&self) -> io::Result<Entry> {
-        // TODO: I'm not sure that this way is correct.
-        Entry::from_name(self.hash.clone(), &self.name)
+    fn to_entry(&self) -> Entry {
+        // the working tree may hold anything under this name, so there is nothing to stat
+        Entry::from_stage(self.hash.clone(), &self.name, self.mode as u32, 0)
     }
     
 }
 
-fn is_dir(path: &str) -> ObjectType {
-    let path = Path::new(path);
-    match path.is_dir() {
-        true => ObjectType::Tree,
-        false => ObjectType::Blob,
+fn mode_to_type(mode: usize) -> ObjectType {
+    // 40000 is a directory, 160000 a submodule commit
+    match mode {
+        40000 => ObjectType::Tree,
+        160000 => ObjectType::Commit,
+        _ => ObjectType::Blob,
     }
 }
 
@@ -108,29 +109,15 @@ impl Tree {
 
     pub fn from(data: &[u8]) -> Option<Self> {
         // <mode> <name>\0<hash><mode> <name>\0<hash>....<mode> <name>\0<hash>
+        // names never contain \0, hashes may, so walk the entries one by one.
         let mut files: Vec<File> = Vec::new();
-        let splitter_offsets: Vec<usize> = data.iter().enumerate()
-                                .filter(|(_, &d)| d == b'\0' )
-                                .map(|(off, _)| off )
-                                .collect();
-        let mut offsets: Vec<usize> = Vec::new();
-        let mut prev = 0;
-        for i in splitter_offsets {
-            if i - prev >= 20 {
-                // \0 in hash
-                offsets.push(i);
-            } else if i < 20 {
-                offsets.push(i);
-            }
-            prev = i;
-        }
         let mut head = 0;
-        for offset in offsets.iter() {
-            let hdr = &data[head..*offset];
-            let hash = &data[(*offset + 1)..(*offset + 21)];
-            let file = File::from(hdr, hash)?;
+        while head < data.len() {
+            let offset = head + data[head..].iter().position(|&d| d == b'\0')?;
+            let hash = data.get((offset + 1)..(offset + 21))?;
+            let file = File::from(&data[head..offset], hash)?;
             files.push(file);
-            head = *offset + 21;
+            head = offset + 21;
         }
         Some(Tree::new(files))
     }
@@ -169,7 +156,7 @@ impl Tree {
             base_path.push(&file.name);
             let p = base_path.as_path().to_str()
                         .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
-            if is_dir(p) == ObjectType::Tree {
+            if file.typ == ObjectType::Tree {
                 let path = hex::encode(file.hash.clone());
                 let tree = Tree::from_hash_file(&hash_key_to_path(&path))?;
                 tree.switch(p)?;
@@ -181,43 +168,36 @@ impl Tree {
     }
     
     fn to_entries(&self, base: &str) -> io::Result<Vec<Entry>> {
-        let mut entries: Vec<Entry> = Vec::new();
-        for file in self.files.iter() {
-            let mut base_path = PathBuf::from(base);
-            base_path.push(&file.name);
-            let p = base_path.as_path().to_str()
-                        .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
-            if is_dir(p) == ObjectType::Tree {
-                let path = hex::encode(file.hash.clone());
-                let tree = Tree::from_hash_file(&hash_key_to_path(&path))?;
-                let mut e = tree.to_entries(p)?;
-                entries.append(&mut e);
-            } else {
-                let entry = file.to_entry()?;    
-                entries.push(entry);
-            }
-        }
-        Ok(entries)
+        // index entries are named from the top of the working tree
+        let base = if base == "." { "" } else { base };
+        Ok(self.flatten(base)?.iter().map(|file| file.to_entry()).collect())
     }
 
-    fn to_tree_entries(&self) -> io::Result<Vec<TreeEntry>> {
-        let mut entries: Vec<TreeEntry> = Vec::new();
-        for file in self.files.iter() {
-            if is_dir(&file.name) == ObjectType::Tree {
-                let path = hex::encode(file.hash.clone());
-                println!("path {}", path);
+    pub fn to_tree_entries(&self) -> io::Result<Vec<TreeEntry>> {
+        // an index read from this tree starts with every directory in its cache-tree valid
+        let mut tree_entries: Vec<TreeEntry> = Vec::new();
+        CacheTree::from_tree(self, ".")?.push_entries(&mut tree_entries);
+        Ok(tree_entries)
+    }
 
+    pub fn flatten(&self, base: &str) -> io::Result<Vec<File>> {
+        // every blob below this tree, named by its path from the top
+        let mut files: Vec<File> = Vec::new();
+        for file in self.files.iter() {
+            let name = if base.is_empty() { file.name.clone() } else { format!("{}/{}", base, file.name) };
+            if file.typ == ObjectType::Tree {
+                let tree = Tree::from_hash_file(&hash_key_to_path(&hex::encode(&file.hash)))?;
+                files.append(&mut tree.flatten(&name)?);
+            } else {
+                files.push(File::new(file.mode, &file.hash, &name, file.typ.clone()));
             }
         }
-        Ok(entries)
+        Ok(files)
     }
 
     pub fn to_index(&self) -> io::Result<Index> {
-        let entries = self.files.iter()
-                        .map(|file| file.to_entry())
-                        .flat_map(|f| f)
-                        .collect::<Vec<Entry>>();
-        let tree_entries = Vec::new();
+        let entries = self.to_entries("")?;
+        let tree_entries = self.to_tree_entries()?;
         Ok(Index::new(entries, tree_entries))
     }
 }
@@ -237,17 +217,189 @@ impl fmt::Display for Tree {
 }
 
 pub fn write_tree() -> io::Result<Tree> {
-    let index = index::read_index(GIT_INDEX)?;
-    let entries: Vec<File> = index.entries.iter()
-                    .map(|e| File::new(e.mode as usize, &e.hash, &e.name, ObjectType::Blob))
-                    .collect();
-    Ok(Tree::new(entries))
+    // the cache-tree is written back only when nobody else holds the index lock
+    let lock = index::lock_index(GIT_INDEX).ok();
+    let mut index = index::read_index(GIT_INDEX)?;
+    if index.entries.iter().any(|e| e.stage != 0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "you need to resolve your current index first"));
+    }
+    // a directory the cache-tree still vouches for is taken as it is, the rest is written again
+    let cache = CacheTree::from_entries(&index.tree_entries);
+    let mut trees: Vec<Tree> = Vec::new();
+    let (hash, cache) = update_cache_tree(&index.entries, "", ".", cache.as_ref(), &mut trees);
+    for tree in trees {
+        Object::Tree(tree).write()?;
+    }
+    let mut tree_entries: Vec<TreeEntry> = Vec::new();
+    cache.push_entries(&mut tree_entries);
+    let changed = tree_entries.len() != index.tree_entries.len()
+                    || tree_entries.iter().zip(index.tree_entries.iter()).any(|(a, b)| a.as_bytes() != b.as_bytes());
+    if let Some(lock) = lock {
+        if changed {
+            index.tree_entries = tree_entries;
+            index::write_index(GIT_INDEX, &index)?;
+        }
+        lock.commit()?;
+    }
+    Tree::from_hash_file(&hash_key_to_path(&hex::encode(hash)))
+}
+
+#[derive(Clone)]
+struct CacheTree {
+    name: String,
+    // the index entries below the directory, -1 when the hash no longer describes them
+    entries: i32,
+    hash: Vec<u8>,
+    subtrees: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    fn from_entries(tree_entries: &[TreeEntry]) -> Option<CacheTree> {
+        CacheTree::read(&mut tree_entries.iter())
+    }
+
+    fn read<'a, I>(iter: &mut I) -> Option<CacheTree>
+        where I: Iterator<Item = &'a TreeEntry>
+    {
+        // each directory is followed by its subtrees, in preorder
+        let tree_entry = iter.next()?;
+        let subtrees = (0..tree_entry.subtrees)
+                    .map(|_| CacheTree::read(iter))
+                    .collect::<Option<Vec<CacheTree>>>()?;
+        Some(CacheTree {
+            name: tree_entry.path.clone(),
+            entries: tree_entry.entries,
+            hash: tree_entry.hash.clone(),
+            subtrees,
+        })
+    }
+
+    fn from_tree(tree: &Tree, name: &str) -> io::Result<CacheTree> {
+        let mut entries = 0;
+        let mut subtrees: Vec<CacheTree> = Vec::new();
+        for file in tree.files.iter() {
+            if file.typ == ObjectType::Tree {
+                let subtree = Tree::from_hash_file(&hash_key_to_path(&hex::encode(&file.hash)))?;
+                let cache = CacheTree::from_tree(&subtree, &file.name)?;
+                entries += cache.entries;
+                subtrees.push(cache);
+            } else {
+                entries += 1;
+            }
+        }
+        Ok(CacheTree::new(name, entries, tree.calc_hash(), subtrees))
+    }
+
+    fn new(name: &str, entries: i32, hash: Vec<u8>, mut subtrees: Vec<CacheTree>) -> CacheTree {
+        // git keeps subtrees ordered by the length of their names first
+        subtrees.sort_by(|a, b| (a.name.len(), &a.name).cmp(&(b.name.len(), &b.name)));
+        CacheTree { name: String::from(name), entries, hash, subtrees }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.entries >= 0 && !self.hash.is_empty()
+    }
+
+    fn push_entries(&self, tree_entries: &mut Vec<TreeEntry>) {
+        tree_entries.push(TreeEntry::new(&self.name, self.hash.clone(), self.entries, self.subtrees.len()));
+        for subtree in self.subtrees.iter() {
+            subtree.push_entries(tree_entries);
+        }
+    }
+}
+
+fn update_cache_tree(entries: &[Entry], base: &str, name: &str, cache: Option<&CacheTree>, trees: &mut Vec<Tree>) -> (Vec<u8>, CacheTree) {
+    // entries are the index entries below base, which ends with '/' unless it is the top,
+    // the tree objects that had to be built again are added to trees for the caller to write
+    if let Some(cache) = cache.filter(|c| c.is_valid() && c.entries as usize == entries.len()) {
+        return (cache.hash.clone(), cache.clone());
+    }
+    let mut files: Vec<File> = Vec::new();
+    let mut subtrees: Vec<CacheTree> = Vec::new();
+    // a path added with -N is left out until its content is staged, and its trees stay invalid
+    let mut valid = true;
+    let mut i = 0;
+    while i < entries.len() {
+        let path = &entries[i].name[base.len()..];
+        match path.find('/') {
+            Some(slash) => {
+                // the entries of a directory sit next to each other in the index
+                let dir = &path[..slash];
+                let prefix = format!("{}{}/", base, dir);
+                let end = i + entries[i..].iter().take_while(|e| e.name.starts_with(&prefix)).count();
+                let child = cache.and_then(|c| c.subtrees.iter().find(|s| s.name == dir));
+                let (hash, subtree) = update_cache_tree(&entries[i..end], &prefix, dir, child, trees);
+                valid &= subtree.is_valid();
+                if entries[i..end].iter().any(|e| !e.intent_to_add) {
+                    files.push(File::new(40000, &hash, dir, ObjectType::Tree));
+                }
+                subtrees.push(subtree);
+                i = end;
+            },
+            None => {
+                let entry = &entries[i];
+                if entry.intent_to_add {
+                    valid = false;
+                } else {
+                    files.push(File::new(entry.mode as usize, &entry.hash, path, ObjectType::Blob));
+                }
+                i += 1;
+            },
+        }
+    }
+    // git sorts tree entries as if directory names ended with '/'
+    files.sort_by_key(sort_key);
+    let tree = Tree::new(files);
+    let hash = tree.calc_hash();
+    trees.push(tree);
+    let cache = if valid {
+        CacheTree::new(name, entries.len() as i32, hash.clone(), subtrees)
+    } else {
+        CacheTree::new(name, -1, Vec::new(), subtrees)
+    };
+    (hash, cache)
+}
+
+pub fn build_tree(files: Vec<File>) -> io::Result<Tree> {
+    // files are named by their full path, sub directories are written as tree objects
+    let mut tree_files: Vec<File> = Vec::new();
+    let mut sub_dirs: Vec<(String, Vec<File>)> = Vec::new();
+    for file in files {
+        match file.name.find('/') {
+            Some(i) => {
+                let dir = &file.name[..i];
+                let child = File::new(file.mode, &file.hash, &file.name[(i + 1)..], file.typ.clone());
+                match sub_dirs.iter_mut().find(|(d, _)| d == dir) {
+                    Some((_, children)) => children.push(child),
+                    None => sub_dirs.push((String::from(dir), vec![child])),
+                }
+            },
+            None => tree_files.push(file),
+        }
+    }
+    for (dir, children) in sub_dirs {
+        let obj = Object::Tree(build_tree(children)?);
+        let hash = hex::decode(obj.write()?).or(Err(io::Error::from(io::ErrorKind::InvalidData)))?;
+        tree_files.push(File::new(40000, &hash, &dir, ObjectType::Tree));
+    }
+    // git sorts tree entries as if directory names ended with '/'
+    tree_files.sort_by_key(sort_key);
+    Ok(Tree::new(tree_files))
+}
+
+fn sort_key(file: &File) -> Vec<u8> {
+    match file.typ {
+        ObjectType::Tree => [file.name.as_bytes(), b"/"].concat(),
+        _ => Vec::from(file.name.as_bytes()),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::File;
     use super::Tree;
+    use super::{CacheTree, update_cache_tree};
+    use crate::index::{Entry, Index};
     use crate::object::ObjectType; 
 
     const FILE: [u8; 41] = [
@@ -343,4 +495,29 @@ mod tests {
         assert_eq!(entries.len() > 7, true);
 
     }
+    #[test]
+    fn test_update_cache_tree() {
+        let entry = |name: &str, hash: u8| Entry::from_stage(vec![hash; 20], name, 100644, 0);
+        let mut index = Index::new(vec![entry("a/x", 1), entry("b/y", 2), entry("top", 3)], Vec::new());
+        let mut trees = Vec::new();
+        let (_, cache) = update_cache_tree(&index.entries, "", ".", None, &mut trees);
+        assert_eq!(trees.len(), 3);
+        cache.push_entries(&mut index.tree_entries);
+
+        // a valid cache-tree is taken as it is
+        let mut trees = Vec::new();
+        let (hash, _) = update_cache_tree(&index.entries, "", ".", CacheTree::from_entries(&index.tree_entries).as_ref(), &mut trees);
+        assert!(trees.is_empty());
+        assert_eq!(hash, cache.hash);
+
+        // only the directory that changed and the root are built again
+        index.entries[1] = entry("b/y", 4);
+        index.invalidate_tree("b/y");
+        let mut trees = Vec::new();
+        let (hash, cache) = update_cache_tree(&index.entries, "", ".", CacheTree::from_entries(&index.tree_entries).as_ref(), &mut trees);
+        assert_eq!(trees.iter().map(|t| t.files.iter().map(|f| f.name.as_str()).collect::<Vec<&str>>()).collect::<Vec<_>>(), vec![vec!["y"], vec!["a", "b", "top"]]);
+        assert!(cache.is_valid() && cache.subtrees.iter().all(|s| s.is_valid()));
+        let (fresh, _) = update_cache_tree(&index.entries, "", ".", None, &mut Vec::new());
+        assert_eq!(hash, fresh);
+    }
 }
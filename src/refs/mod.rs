@@ -7,14 +7,55 @@ use std::io::Write;
 use std::io::Read;
 use std::fs::File;
 use crate::cmd::cat_file::hash_key_to_path;
-use crate::object::commit::Commit;
-use crate::object::tree::Tree;
+use crate::object::commit::{Commit, User};
+use crate::config::Config;
+use crate::graph;
 use crate::cmd::GIT_BASE_DIR;
 use crate::cmd::GIT_HEAD_FILE;
+use crate::cmd::GIT_REFS_DIR;
 use crate::cmd::GIT_REFS_HEADS_DIR;
+use crate::cmd::GIT_PACKED_REFS;
+use crate::cmd::GIT_OBJECTS_DIR;
+use crate::cmd::GIT_LOGS_DIR;
 use crate::cmd::REFS_HEADS_DIR;
 
 const REFS: &str = "ref:";
+const REFS_HEADS_PREFIX: &str = "refs/heads/";
+const REFS_TAGS_PREFIX: &str = "refs/tags/";
+const REFS_REMOTES_PREFIX: &str = "refs/remotes/";
+pub const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub committer: User,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ref {
+    pub name: String,
+    pub hash: String,
+    // the object an annotated tag points to, recorded in packed-refs as ^<hash>
+    pub peeled: Option<String>,
+}
+
+impl Ref {
+    pub fn new(name: &str, hash: &str, peeled: Option<&str>) -> Ref {
+        Ref {
+            name: String::from(name),
+            hash: String::from(hash),
+            peeled: peeled.map(String::from),
+        }
+    }
+
+    pub fn short_name(&self) -> &str {
+        [REFS_HEADS_PREFIX, REFS_TAGS_PREFIX, REFS_REMOTES_PREFIX, "refs/"].iter()
+            .find_map(|p| self.name.strip_prefix(p))
+            .unwrap_or(&self.name)
+    }
+}
 
 pub fn create_head() -> io::Result<()> {
     let mut file = File::create(GIT_HEAD_FILE)?; 
@@ -23,18 +64,27 @@ pub fn create_head() -> io::Result<()> {
 }
 
 pub fn read_head() -> io::Result<String> {
+    // the file the current commit is recorded in, HEAD itself when detached
     let mut file = File::open(GIT_HEAD_FILE)?;
     let mut buf = Vec::new();
     file.read_to_end(&mut buf)?;
     let content = String::from_utf8(buf).or(Err(io::Error::from(io::ErrorKind::NotFound)))?;
     let mut iter = content.split_whitespace();
-    iter.next().ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+    let first = iter.next().ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+    if first != REFS {
+        return Ok(String::from(GIT_HEAD_FILE));
+    }
     let refs = iter.next().ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
 
     Ok(format!("{}/{}", GIT_BASE_DIR, refs))
 }
 
-fn update_head(name: &str) -> io::Result<String> {
+pub fn detach_head(hash: &str) -> io::Result<()> {
+    let mut file = File::create(GIT_HEAD_FILE)?;
+    file.write_all(format!("{}\n", hash).as_bytes())
+}
+
+pub fn update_head(name: &str) -> io::Result<String> {
     let mut file = File::create(GIT_HEAD_FILE)?; 
     let path = format!("{}/{}", REFS_HEADS_DIR, name);
     let content = format!("{} {}\n", REFS, path);
@@ -43,22 +93,51 @@ fn update_head(name: &str) -> io::Result<String> {
 }
 
 pub fn create_branch(name: &str) -> io::Result<()> {
-    let ref_path = format!("{}/{}", GIT_REFS_HEADS_DIR, name);
     let head_path = read_head()?;
     let head_hash = read_ref(&head_path)?;
-    let mut file = File::create(ref_path)?;
-    file.write_all(head_hash.as_bytes())
-}
-
-pub fn switch_branch(name: &str) -> io::Result<()> {
-    let path = update_head(name)?; // update .git/HEAD
-    // update contents
-    let head_hash = read_ref(&path)?;
-    let commit = Commit::from_hash_file(&hash_key_to_path(&head_hash))?; 
-    let tree = Tree::from_hash_file(&hash_key_to_path(&commit.tree))?; 
-    tree.switch(".")?;
-    // update .git/index
-    Ok(())
+    create_branch_at(name, &head_hash, false)
+}
+
+pub fn create_branch_at(name: &str, hash: &str, force: bool) -> io::Result<()> {
+    // force moves a branch that already exists, like branch -f and switch -C
+    if !is_valid_branch_name(name) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a valid branch name", name)));
+    }
+    let ref_path = format!("{}/{}", GIT_REFS_HEADS_DIR, name);
+    if Path::new(&ref_path).exists() {
+        if force {
+            return write_ref(&ref_path, hash);
+        }
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("a branch named '{}' already exists", name)));
+    }
+    // feature/foo can not coexist with feature, because one of them must be a directory.
+    if let Some(conflict) = show_branches()?.iter().find(|b| is_nested_conflict(b, name)) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("'{}' exists; cannot create '{}'", conflict, name)));
+    }
+    write_ref(&ref_path, hash)
+}
+
+pub fn delete_branch(name: &str, force: bool) -> io::Result<String> {
+    // the commit the branch pointed at; without force it must be merged into HEAD
+    if Some(name) == read_head_branch().ok().as_deref() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("cannot delete branch '{}' checked out", name)));
+    }
+    // the branch may be loose, packed or both
+    let ref_name = format!("{}{}", REFS_HEADS_PREFIX, name);
+    let hash = resolve_ref(&ref_name).or(Err(io::Error::new(io::ErrorKind::NotFound, format!("branch '{}' not found.", name))))?;
+    if !force && !is_merged(&hash)? {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The branch '{}' is not fully merged.\nIf you are sure you want to delete it, run 'rusgit branch -D {}'.", name, name)));
+    }
+    delete_ref(&ref_name)?;
+    Ok(hash)
+}
+
+fn is_merged(hash: &str) -> io::Result<bool> {
+    // nothing is merged into an unborn HEAD
+    match rev_parse("HEAD") {
+        Ok(head) => graph::is_ancestor(hash, &head),
+        Err(_) => Ok(false),
+    }
 }
 
 pub fn read_head_branch() -> io::Result<String> {
@@ -66,21 +145,222 @@ pub fn read_head_branch() -> io::Result<String> {
     let mut file = File::open(GIT_HEAD_FILE)?;
     let mut buf = Vec::new();
     file.read_to_end(&mut buf)?;
-    let path = Path::new(str::from_utf8(&buf[0..buf.len()-1]).unwrap());
-    let branch = path.file_name().ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+    let content = str::from_utf8(&buf).or(Err(io::Error::from(io::ErrorKind::InvalidData)))?;
+    let branch = branch_name_from_head(content).ok_or(io::Error::from(io::ErrorKind::NotFound))?;
 
-    Ok(String::from(branch.to_str().unwrap()))
+    Ok(String::from(branch))
 }
 
 pub fn show_branches() -> io::Result<Vec<String>> {
-    let branchs = fs::read_dir(GIT_REFS_HEADS_DIR)?
-                        .flat_map(|f| f)
-                        .map(|f| {
-                            let name = f.file_name();
-                            String::from(name.to_str().unwrap())
-                        })
+    let branches = read_refs()?.iter()
+                        .filter_map(|r| r.name.strip_prefix(REFS_HEADS_PREFIX))
+                        .map(String::from)
                         .collect::<Vec<String>>();
-    Ok(branchs)
+    Ok(branches)
+}
+
+fn branch_name_from_head(content: &str) -> Option<&str> {
+    // ref: refs/heads/feature/foo -> feature/foo
+    let refs = content.trim_end().strip_prefix(REFS)?.trim_start();
+    refs.strip_prefix(REFS_HEADS_PREFIX)
+}
+
+fn is_nested_conflict(existing: &str, name: &str) -> bool {
+    existing.starts_with(&format!("{}/", name)) || name.starts_with(&format!("{}/", existing))
+}
+
+fn is_valid_branch_name(name: &str) -> bool {
+    // subset of git check-ref-format
+    !name.is_empty()
+        && name != "HEAD"
+        && !name.starts_with('-')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.chars().any(|c| c.is_control() || " ~^:?*[\\".contains(c))
+        && name.split('/').all(|c| !c.is_empty() && !c.starts_with('.') && !c.ends_with(".lock"))
+}
+
+pub fn read_refs() -> io::Result<Vec<Ref>> {
+    // loose refs take precedence over packed ones
+    let mut refs = read_loose_refs(Path::new(GIT_REFS_DIR), "refs", Vec::new())?;
+    for packed in read_packed_refs()? {
+        if !refs.iter().any(|r| r.name == packed.name) {
+            refs.push(packed);
+        }
+    }
+    refs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(refs)
+}
+
+fn read_loose_refs(dir: &Path, prefix: &str, mut refs: Vec<Ref>) -> io::Result<Vec<Ref>> {
+    let list = match fs::read_dir(dir) {
+        Ok(list) => list,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(refs),
+        Err(e) => return Err(e),
+    };
+    for f in list.flatten() {
+        let name = format!("{}/{}", prefix, f.file_name().to_str().ok_or(io::Error::from(io::ErrorKind::InvalidData))?);
+        if f.path().is_dir() {
+            refs = read_loose_refs(&f.path(), &name, refs)?;
+        } else if let Ok(hash) = resolve_ref(&name) {
+            // dangling symbolic refs are skipped like git does
+            refs.push(Ref::new(&name, &hash, None));
+        }
+    }
+    Ok(refs)
+}
+
+pub fn read_packed_refs() -> io::Result<Vec<Ref>> {
+    let mut file = match File::open(GIT_PACKED_REFS) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut buf = String::new();
+    file.read_to_string(&mut buf)?;
+    Ok(packed_refs_from(&buf))
+}
+
+fn packed_refs_from(data: &str) -> Vec<Ref> {
+    // # pack-refs with: peeled fully-peeled sorted
+    // <hash> refs/tags/v0.1.0
+    // ^<peeled hash>
+    let mut refs: Vec<Ref> = Vec::new();
+    for line in data.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            if let Some(last) = refs.last_mut() {
+                last.peeled = Some(String::from(peeled));
+            }
+            continue;
+        }
+        let mut iter = line.splitn(2, ' ');
+        if let (Some(hash), Some(name)) = (iter.next(), iter.next()) {
+            refs.push(Ref::new(name, hash, None));
+        }
+    }
+    refs
+}
+
+pub fn resolve_ref(name: &str) -> io::Result<String> {
+    // follow symbolic refs like refs/remotes/origin/HEAD until reaching a hash
+    let mut name = String::from(name);
+    for _ in 0..5 {
+        let path = format!("{}/{}", GIT_BASE_DIR, name);
+        let content = match read_ref(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return read_packed_refs()?.into_iter()
+                    .find(|r| r.name == name)
+                    .map(|r| r.hash)
+                    .ok_or(e);
+            },
+            Err(e) => return Err(e),
+        };
+        match content.strip_prefix(REFS) {
+            Some(target) => name = String::from(target.trim()),
+            None => return Ok(content),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, format!("symbolic ref loop at {}", name)))
+}
+
+pub fn rev_parse(name: &str) -> io::Result<String> {
+    // <rev>~<n> and <rev>^ walk first parents
+    if let Some(i) = name.rfind(['~', '^']) {
+        let (base, suffix) = name.split_at(i);
+        let n = match suffix[1..].parse::<usize>() {
+            Ok(n) if suffix.starts_with('~') => n,
+            Ok(1) => 1,
+            Ok(0) => 0,
+            Err(_) if suffix.len() == 1 => 1,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown revision '{}'", name))),
+        };
+        let mut hash = rev_parse(base)?;
+        for _ in 0..n {
+            let commit = Commit::from_hash_file(&hash_key_to_path(&hash))?;
+            hash = commit.parent().map(String::from)
+                .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("unknown revision '{}'", name)))?;
+        }
+        return Ok(hash);
+    }
+    // <refname>@{<n>} is the n-th newest entry of the reflog
+    if let Some((base, n)) = name.strip_suffix('}').and_then(|n| n.split_once("@{")) {
+        let n = n.parse::<usize>().or(Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown revision '{}'", name))))?;
+        let base = if base.is_empty() { "HEAD" } else { base };
+        let candidates = [String::from(base), format!("refs/{}", base), format!("{}{}", REFS_HEADS_PREFIX, base)];
+        for c in candidates.iter() {
+            let log = read_reflog(c)?;
+            if !log.is_empty() {
+                return log.iter().rev().nth(n).map(|e| e.new.clone())
+                    .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("log for '{}' only has {} entries", base, log.len())));
+            }
+        }
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown revision '{}'", name)));
+    }
+    // HEAD, <full or abbreviated hash>, <refname>
+    if name == "HEAD" {
+        return resolve_ref("HEAD");
+    }
+    let candidates = [
+        String::from(name),
+        format!("refs/{}", name),
+        format!("{}{}", REFS_TAGS_PREFIX, name),
+        format!("{}{}", REFS_HEADS_PREFIX, name),
+        format!("{}{}", REFS_REMOTES_PREFIX, name),
+        format!("{}{}/HEAD", REFS_REMOTES_PREFIX, name),
+    ];
+    if name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(name.to_lowercase());
+    }
+    for c in candidates.iter().filter(|c| c.starts_with("refs/")) {
+        if let Ok(hash) = resolve_ref(c) {
+            return Ok(hash);
+        }
+    }
+    if name.len() >= 4 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+        return find_object_by_prefix(&name.to_lowercase());
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown revision '{}'", name)))
+}
+
+fn find_object_by_prefix(prefix: &str) -> io::Result<String> {
+    let (dir, rest) = prefix.split_at(2);
+    let list = match fs::read_dir(format!("{}/{}", GIT_OBJECTS_DIR, dir)) {
+        Ok(list) => list,
+        Err(_) => return Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown revision '{}'", prefix))),
+    };
+    let found = list.flatten()
+        .flat_map(|f| f.file_name().into_string())
+        .filter(|f| f.starts_with(rest))
+        .collect::<Vec<String>>();
+    match found.len() {
+        1 => Ok(format!("{}{}", dir, found[0])),
+        0 => Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown revision '{}'", prefix))),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("short object ID {} is ambiguous", prefix))),
+    }
+}
+
+pub fn upstream_of(name: &str, config: &Config) -> Option<String> {
+    // the ref a branch tracks, from branch.<name>.remote and branch.<name>.merge
+    let branch = name.strip_prefix("refs/heads/")?;
+    let remote = config.get(&format!("branch.{}.remote", branch))?;
+    let merge = config.get(&format!("branch.{}.merge", branch))?;
+    if remote == "." {
+        return Some(String::from(merge));
+    }
+    // map through the remote's fetch refspec, +refs/heads/*:refs/remotes/origin/*
+    let refspec = config.get(&format!("remote.{}.fetch", remote))?;
+    let mut iter = refspec.trim_start_matches('+').splitn(2, ':');
+    let (src, dst) = (iter.next()?, iter.next()?);
+    match (src.strip_suffix('*'), dst.strip_suffix('*')) {
+        (Some(src), Some(dst)) => merge.strip_prefix(src).map(|m| format!("{}{}", dst, m)),
+        _ if src == merge => Some(String::from(dst)),
+        _ => None,
+    }
 }
 
 pub fn read_ref(path: &str) -> io::Result<String> {
@@ -93,6 +373,9 @@ pub fn read_ref(path: &str) -> io::Result<String> {
 }
 
 fn write_ref(path: &str, hash: &str) -> io::Result<()> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
     let mut file = File::create(path)?;
     file.write_all(hash.as_bytes())
 }
@@ -101,8 +384,211 @@ pub fn update_ref(path: &str, hash: &str) -> io::Result<()> {
     write_ref(path, hash)
 }
 
+pub fn read_reflog(name: &str) -> io::Result<Vec<ReflogEntry>> {
+    // <old> <new> <committer>\t<message>, oldest first
+    let data = match fs::read_to_string(format!("{}/{}", GIT_LOGS_DIR, name)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    data.lines().filter(|l| !l.is_empty()).map(reflog_entry_from).collect()
+}
+
+fn reflog_entry_from(line: &str) -> io::Result<ReflogEntry> {
+    let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+    let mut iter = head.splitn(3, ' ');
+    let old = iter.next().ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+    let new = iter.next().ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+    let committer = iter.next()
+        .and_then(|c| User::from(&format!("committer {}", c)))
+        .ok_or(io::Error::new(io::ErrorKind::InvalidData, format!("invalid reflog entry: {}", line)))?;
+    Ok(ReflogEntry {
+        old: String::from(old),
+        new: String::from(new),
+        committer,
+        message: String::from(message),
+    })
+}
+
+pub fn write_reflog(name: &str, entries: &[ReflogEntry]) -> io::Result<()> {
+    let path = format!("{}/{}", GIT_LOGS_DIR, name);
+    if let Some(dir) = Path::new(&path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let data = entries.iter()
+                .map(|e| format!("{} {} {}\t{}\n", e.old, e.new, e.committer, e.message))
+                .collect::<String>();
+    fs::write(path, data)
+}
+
+pub fn append_reflog(name: &str, old: Option<&str>, new: &str, committer: User, message: &str) -> io::Result<()> {
+    let mut entries = read_reflog(name)?;
+    entries.push(ReflogEntry {
+        old: String::from(old.unwrap_or(NULL_HASH)),
+        new: String::from(new),
+        committer,
+        message: String::from(message),
+    });
+    write_reflog(name, &entries)
+}
+
+pub fn delete_ref(name: &str) -> io::Result<()> {
+    // the ref and its reflog go away together, along with its line in packed-refs
+    delete_packed_ref(name)?;
+    for top in [GIT_BASE_DIR, GIT_LOGS_DIR].iter() {
+        match fs::remove_file(format!("{}/{}", top, name)) {
+            Ok(_) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+        // remove directories left empty by feature/foo style names, refs/heads itself stays
+        let mut dir = name;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            if parent.matches('/').count() < 2 || fs::remove_dir(format!("{}/{}", top, parent)).is_err() {
+                break;
+            }
+            dir = parent;
+        }
+    }
+    Ok(())
+}
+
+fn delete_packed_ref(name: &str) -> io::Result<()> {
+    let data = match fs::read_to_string(GIT_PACKED_REFS) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let data = match packed_refs_without(&data, name) {
+        Some(data) => data,
+        None => return Ok(()),
+    };
+    // written aside and renamed in place, like git does
+    let lock = format!("{}.lock", GIT_PACKED_REFS);
+    let mut file = match fs::OpenOptions::new().write(true).create_new(true).open(&lock) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("Unable to create '{}': File exists.", lock)));
+        },
+        Err(e) => return Err(e),
+    };
+    if let Err(e) = file.write_all(data.as_bytes()) {
+        let _ = fs::remove_file(&lock);
+        return Err(e);
+    }
+    fs::rename(&lock, GIT_PACKED_REFS)
+}
+
+fn packed_refs_without(data: &str, name: &str) -> Option<String> {
+    // packed-refs without the line of a ref and the peeled line after it, None when the ref is not there
+    let mut result = String::new();
+    let mut found = false;
+    let mut skipping = false;
+    for line in data.lines() {
+        if line.starts_with('^') && skipping {
+            continue;
+        }
+        skipping = line.split_once(' ').map(|(_, n)| n) == Some(name) && !line.starts_with('#');
+        found |= skipping;
+        if !skipping {
+            result.push_str(line);
+            result.push('\n');
+        }
+    }
+    if found { Some(result) } else { None }
+}
+
+#[derive(Debug, Default)]
+pub struct RefTransaction {
+    // ref path, new hash and the hash it must still hold, if any
+    updates: Vec<(String, String, Option<String>)>,
+}
+
+impl RefTransaction {
+    pub fn new() -> RefTransaction {
+        RefTransaction { updates: Vec::new() }
+    }
+
+    pub fn update(&mut self, path: &str, hash: &str, old: Option<&str>) {
+        self.updates.push((String::from(path), String::from(hash), old.map(String::from)));
+    }
+
+    pub fn commit(self) -> io::Result<()> {
+        // every ref is locked and checked before any of them is moved
+        let mut locks: Vec<String> = Vec::new();
+        let res = self.prepare(&mut locks);
+        if res.is_err() {
+            for lock in locks.iter() {
+                let _ = fs::remove_file(lock);
+            }
+            return res;
+        }
+        for ((path, _, _), lock) in self.updates.iter().zip(locks.iter()) {
+            fs::rename(lock, path)?;
+        }
+        Ok(())
+    }
+
+    fn prepare(&self, locks: &mut Vec<String>) -> io::Result<()> {
+        for (path, hash, old) in self.updates.iter() {
+            if let Some(dir) = Path::new(path).parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let lock = format!("{}.lock", path);
+            let mut file = match fs::OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("Unable to create '{}': File exists.", lock)));
+                },
+                Err(e) => return Err(e),
+            };
+            locks.push(lock);
+            if let Some(old) = old {
+                let current = read_ref(path).ok();
+                if current.as_deref() != Some(old.as_str()) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("cannot lock ref '{}': is at {} but expected {}", path, current.unwrap_or_default(), old)));
+                }
+            }
+            file.write_all(format!("{}\n", hash).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn test_reflog_entry_from() {
+        let line = "0000000000000000000000000000000000000000 1111111111111111111111111111111111111111 A U Thor <author@example.com> 1616834749 +0900\tWIP on master: 1234567 first";
+        let entry = super::reflog_entry_from(line).unwrap();
+        assert_eq!(entry.new, "1111111111111111111111111111111111111111");
+        assert_eq!(entry.committer.name, "A U Thor");
+        assert_eq!(entry.message, "WIP on master: 1234567 first");
+        assert_eq!(format!("{} {} {}\t{}", entry.old, entry.new, entry.committer, entry.message), line);
+    }
+    #[test]
+    fn test_ref_transaction() {
+        let dir = std::env::temp_dir().join(format!("rusgit-ref-transaction-{}", std::process::id()));
+        let path = dir.join("refs/heads/master");
+        let path = path.to_str().unwrap();
+        let one = "1111111111111111111111111111111111111111";
+        let two = "2222222222222222222222222222222222222222";
+        let mut transaction = super::RefTransaction::new();
+        transaction.update(path, one, None);
+        transaction.commit().unwrap();
+        assert_eq!(super::read_ref(path).unwrap(), one);
+        // a stale expectation leaves the ref and no lock behind
+        let mut transaction = super::RefTransaction::new();
+        transaction.update(path, two, Some(two));
+        assert!(transaction.commit().is_err());
+        assert_eq!(super::read_ref(path).unwrap(), one);
+        assert!(!std::path::Path::new(&format!("{}.lock", path)).exists());
+        let mut transaction = super::RefTransaction::new();
+        transaction.update(path, two, Some(one));
+        transaction.commit().unwrap();
+        assert_eq!(super::read_ref(path).unwrap(), two);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
     #[test]
     fn test_read_head() {
         let refs = match super::read_head() {
@@ -120,6 +606,69 @@ mod tests {
         assert_eq!(refs, true);
     }
     #[test]
+    fn test_branch_name_from_head() {
+        assert_eq!(super::branch_name_from_head("ref: refs/heads/master\n"), Some("master"));
+        assert_eq!(super::branch_name_from_head("ref: refs/heads/feature/login"), Some("feature/login"));
+        assert_eq!(super::branch_name_from_head("a213f26901a29e8fecf60da136c31d61dd41544b\n"), None);
+    }
+    #[test]
+    fn test_is_valid_branch_name() {
+        assert!(super::is_valid_branch_name("feature/login"));
+        assert!(!super::is_valid_branch_name("feature//login"));
+        assert!(!super::is_valid_branch_name("/feature"));
+        assert!(!super::is_valid_branch_name("feature/"));
+        assert!(!super::is_valid_branch_name("feature.lock"));
+        assert!(!super::is_valid_branch_name("a..b"));
+    }
+    #[test]
+    fn test_is_nested_conflict() {
+        assert!(super::is_nested_conflict("feature", "feature/login"));
+        assert!(super::is_nested_conflict("feature/login", "feature"));
+        assert!(!super::is_nested_conflict("feature/login", "feature/logout"));
+        assert!(!super::is_nested_conflict("feat", "feature/login"));
+    }
+    #[test]
+    fn test_packed_refs_from() {
+        let data = "# pack-refs with: peeled fully-peeled sorted
+a213f26901a29e8fecf60da136c31d61dd41544b refs/heads/feature/login
+bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c refs/tags/v0.1.0
+^a213f26901a29e8fecf60da136c31d61dd41544b
+";
+        let refs = super::packed_refs_from(data);
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[0].name, "refs/heads/feature/login");
+        assert_eq!(refs[0].peeled, None);
+        assert_eq!(refs[1].hash, "bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c");
+        assert_eq!(refs[1].peeled, Some(String::from("a213f26901a29e8fecf60da136c31d61dd41544b")));
+    }
+    #[test]
+    fn test_packed_refs_without() {
+        let data = "# pack-refs with: peeled fully-peeled sorted
+a213f26901a29e8fecf60da136c31d61dd41544b refs/heads/feature/login
+bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c refs/tags/v0.1.0
+^a213f26901a29e8fecf60da136c31d61dd41544b
+a213f26901a29e8fecf60da136c31d61dd41544b refs/tags/v0.2.0
+";
+        assert_eq!(super::packed_refs_without(data, "refs/heads/feature/login").unwrap(), "# pack-refs with: peeled fully-peeled sorted
+bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c refs/tags/v0.1.0
+^a213f26901a29e8fecf60da136c31d61dd41544b
+a213f26901a29e8fecf60da136c31d61dd41544b refs/tags/v0.2.0
+");
+        // the peeled line goes with its tag
+        assert_eq!(super::packed_refs_without(data, "refs/tags/v0.1.0").unwrap(), "# pack-refs with: peeled fully-peeled sorted
+a213f26901a29e8fecf60da136c31d61dd41544b refs/heads/feature/login
+a213f26901a29e8fecf60da136c31d61dd41544b refs/tags/v0.2.0
+");
+        assert_eq!(super::packed_refs_without(data, "refs/heads/feature"), None);
+    }
+    #[test]
+    fn test_ref_short_name() {
+        let hash = "a213f26901a29e8fecf60da136c31d61dd41544b";
+        assert_eq!(super::Ref::new("refs/heads/feature/login", hash, None).short_name(), "feature/login");
+        assert_eq!(super::Ref::new("refs/remotes/origin/master", hash, None).short_name(), "origin/master");
+        assert_eq!(super::Ref::new("refs/notes/commits", hash, None).short_name(), "notes/commits");
+    }
+    #[test]
     fn test_show_branches() {
         let branches = super::show_branches().unwrap();
         for branch in branches.iter() { println!("{}", branch); }
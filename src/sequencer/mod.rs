@@ -0,0 +1,436 @@
+use std::io;
+use std::fs;
+use std::path::Path;
+use crate::refs;
+use crate::merge;
+use crate::graph;
+use crate::graph::{ReadCommit, RevWalk};
+use crate::index;
+use crate::worktree;
+use crate::config::Config;
+use crate::object::Object;
+use crate::object::tree;
+use crate::object::commit::Commit;
+use crate::cmd::commit;
+use crate::cmd::{GIT_INDEX, GIT_MERGE_MSG, GIT_SEQUENCER_DIR, GIT_CHERRY_PICK_HEAD, GIT_REVERT_HEAD};
+
+// files in .git/sequencer
+const HEAD: &str = "head";
+const TODO: &str = "todo";
+const OPTS: &str = "opts";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Pick,
+    Revert,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    // -n, apply to the index without committing
+    pub no_commit: bool,
+    // -x, append "(cherry picked from commit ...)"
+    pub record_origin: bool,
+    // -m, the parent to diff a merge against, starting from 1
+    pub mainline: Option<usize>,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    fn command(&self) -> &'static str {
+        match self {
+            Action::Pick => "cherry-pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    fn head_file(&self) -> &'static str {
+        match self {
+            Action::Pick => GIT_CHERRY_PICK_HEAD,
+            Action::Revert => GIT_REVERT_HEAD,
+        }
+    }
+}
+
+pub fn start(action: Action, revs: Vec<&str>, options: Options) -> io::Result<bool> {
+    // false when the series stopped at a conflict or an empty commit
+    if in_progress().is_some() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "a cherry-pick or revert is already in progress"));
+    }
+    let commits = resolve_commits(action, revs)?;
+    if commits.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty commit set passed"));
+    }
+    // refuse bad merges up front instead of stopping half way
+    for hash in commits.iter() {
+        select_parent(hash, &graph::read_commit(hash)?, options.mainline)?;
+    }
+    fs::create_dir_all(GIT_SEQUENCER_DIR)?;
+    write_state(HEAD, &format!("{}\n", refs::rev_parse("HEAD")?))?;
+    write_state(OPTS, &format_options(&options))?;
+    write_todo(&commits.iter().map(|c| (action, c.clone())).collect::<Vec<(Action, String)>>())?;
+    run()
+}
+
+pub fn resume() -> io::Result<bool> {
+    let action = in_progress().ok_or(io::Error::new(io::ErrorKind::NotFound, "no cherry-pick or revert in progress"))?;
+    if !index::with_lock(GIT_INDEX, || commit_resolved(action))? {
+        return Ok(false);
+    }
+    if Path::new(GIT_SEQUENCER_DIR).exists() {
+        return run();
+    }
+    Ok(true)
+}
+
+pub fn skip() -> io::Result<bool> {
+    in_progress().ok_or(io::Error::new(io::ErrorKind::NotFound, "no cherry-pick or revert in progress"))?;
+    // what the stopped commit left in the index and the working tree goes, the rest of the series goes on
+    index::with_lock(GIT_INDEX, || {
+        worktree::checkout_tree(&graph::read_commit(&refs::rev_parse("HEAD")?)?.tree)?;
+        clear_head_files()
+    })?;
+    if Path::new(GIT_SEQUENCER_DIR).exists() {
+        return run();
+    }
+    Ok(true)
+}
+
+fn commit_resolved(action: Action) -> io::Result<bool> {
+    // false when the resolution leaves nothing to commit
+    let index = index::read_index(GIT_INDEX)?;
+    if !index.unmerged().is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Committing is not possible because you have unmerged files.\nfix them up in the work tree, and then use 'rusgit add <file>' as appropriate, then run 'rusgit {} --continue'", action.command())));
+    }
+    // the conflicted commit, unless the resolution was committed by hand
+    if let Ok(hash) = fs::read_to_string(action.head_file()) {
+        let commit = graph::read_commit(hash.trim())?;
+        let head = refs::rev_parse("HEAD")?;
+        if is_empty_change(&head)? {
+            print_empty(action);
+            return Ok(false);
+        }
+        let message = fs::read_to_string(GIT_MERGE_MSG)?;
+        let author = if action == Action::Pick { Some(commit.author.clone()) } else { None };
+        let hash = commit::commit_index(message.trim_end(), vec![&head], author)?;
+        print_commit(&hash, &message)?;
+        clear_head_files()?;
+    }
+    Ok(true)
+}
+
+fn is_empty_change(head: &str) -> io::Result<bool> {
+    // the index holds HEAD's tree, the change is already there
+    let staged = hex::encode(Object::Tree(tree::write_tree()?).calc_hash());
+    Ok(staged == graph::read_commit(head)?.tree)
+}
+
+fn print_empty(action: Action) {
+    println!("The previous {} is now empty, possibly due to conflict resolution.", action.command());
+    println!("If you wish to commit it anyway, use:");
+    println!();
+    println!("    rusgit commit");
+    println!();
+    println!("Otherwise, please use 'rusgit {} --skip'", action.command());
+}
+
+pub fn abort() -> io::Result<()> {
+    in_progress().ok_or(io::Error::new(io::ErrorKind::NotFound, "no cherry-pick or revert in progress"))?;
+    index::with_lock(GIT_INDEX, || {
+        let head = read_state(HEAD)?;
+        let head = head.trim();
+        worktree::checkout_tree(&graph::read_commit(head)?.tree)?;
+        refs::update_ref(&refs::read_head()?, head)?;
+        clear_head_files()?;
+        fs::remove_dir_all(GIT_SEQUENCER_DIR)
+    })
+}
+
+fn in_progress() -> Option<Action> {
+    if Path::new(GIT_CHERRY_PICK_HEAD).exists() {
+        return Some(Action::Pick);
+    }
+    if Path::new(GIT_REVERT_HEAD).exists() {
+        return Some(Action::Revert);
+    }
+    // between two commits of a series the todo list tells what is going on
+    let todo = read_state(TODO).ok()?;
+    match todo.split_whitespace().next() {
+        Some("revert") => Some(Action::Revert),
+        _ if Path::new(GIT_SEQUENCER_DIR).exists() => Some(Action::Pick),
+        _ => None,
+    }
+}
+
+fn resolve_commits(action: Action, revs: Vec<&str>) -> io::Result<Vec<String>> {
+    // A..B is every commit reachable from B but not from A
+    let mut commits: Vec<String> = Vec::new();
+    for rev in revs {
+        let (from, to) = match rev.split_once("..") {
+            Some((from, to)) => (from, to),
+            None => {
+                commits.push(refs::rev_parse(rev)?);
+                continue;
+            },
+        };
+        let from = refs::rev_parse(if from.is_empty() { "HEAD" } else { from })?;
+        let to = refs::rev_parse(if to.is_empty() { "HEAD" } else { to })?;
+        commits.append(&mut range_commits(action, &from, &to, &graph::read_commit)?);
+    }
+    Ok(commits)
+}
+
+fn range_commits(action: Action, from: &str, to: &str, read: ReadCommit) -> io::Result<Vec<String>> {
+    let exclude = graph::reachable_with(from, read)?;
+    let mut walk = RevWalk::with_reader(read);
+    walk.push(to)?;
+    let mut range: Vec<String> = Vec::new();
+    for node in walk {
+        let node = node?;
+        if !exclude.contains(&node.hash) {
+            range.push(node.hash);
+        }
+    }
+    // picks replay oldest first, reverts undo newest first
+    if action == Action::Pick {
+        range.reverse();
+    }
+    Ok(range)
+}
+
+fn run() -> io::Result<bool> {
+    let options = read_options()?;
+    loop {
+        let mut todo = read_todo()?;
+        if todo.is_empty() {
+            return fs::remove_dir_all(GIT_SEQUENCER_DIR).map(|_| true);
+        }
+        let (action, hash) = todo.remove(0);
+        write_todo(&todo)?;
+        // each commit is locked and written on its own, HEAD has already moved past the earlier ones
+        if !index::with_lock(GIT_INDEX, || apply(action, &hash, &options))? {
+            return Ok(false);
+        }
+    }
+}
+
+fn apply(action: Action, hash: &str, options: &Options) -> io::Result<bool> {
+    // true when the commit went in cleanly
+    let commit = graph::read_commit(hash)?;
+    let parent = select_parent(hash, &commit, options.mainline)?;
+    let parent_tree = match &parent {
+        Some(parent) => Some(graph::read_commit(parent)?.tree),
+        None => None,
+    };
+    let subject = commit.message.lines().next().unwrap_or("");
+    let (base, theirs, label) = match action {
+        Action::Pick => (parent_tree, Some(commit.tree.clone()), format!("{} ({})", &hash[..7], subject)),
+        Action::Revert => (Some(commit.tree.clone()), parent_tree, format!("parent of {} ({})", &hash[..7], subject)),
+    };
+    let theirs = match theirs {
+        Some(theirs) => theirs,
+        // reverting a root commit leaves nothing behind
+        None => Object::Tree(tree::Tree::new(Vec::new())).write()?,
+    };
+    let head = refs::rev_parse("HEAD")?;
+    // with -n the changes pile up in the index
+    let ours = if options.no_commit {
+        Object::Tree(tree::write_tree()?).write()?
+    } else {
+        graph::read_commit(&head)?.tree
+    };
+    let entries = merge::apply_change(base.as_deref(), &ours, &theirs, &label)?;
+    for message in merge::describe(&entries) {
+        println!("{}", message);
+    }
+
+    let message = match action {
+        Action::Pick if options.record_origin => format!("{}\n\n(cherry picked from commit {})", commit.message, hash),
+        Action::Pick => commit.message.clone(),
+        Action::Revert => match (&parent, options.mainline) {
+            (Some(parent), Some(_)) => format!("Revert \"{}\"\n\nThis reverts commit {}, reversing\nchanges made to {}.", subject, hash, parent),
+            _ => format!("Revert \"{}\"\n\nThis reverts commit {}.", subject, hash),
+        },
+    };
+    if entries.iter().any(|e| !e.is_clean()) {
+        fs::write(action.head_file(), format!("{}\n", hash))?;
+        fs::write(GIT_MERGE_MSG, format!("{}\n", message))?;
+        let verb = if action == Action::Pick { "apply" } else { "revert" };
+        println!("error: could not {} {}... {}", verb, &hash[..7], subject);
+        println!("hint: After resolving the conflicts, mark them with");
+        println!("hint: \"rusgit add <paths>\", then run");
+        println!("hint: \"rusgit {} --continue\".", action.command());
+        println!("hint: To abort and get back to the state before \"rusgit {}\",", action.command());
+        println!("hint: run \"rusgit {} --abort\".", action.command());
+        return Ok(false);
+    }
+    if options.no_commit {
+        return Ok(true);
+    }
+    // a change HEAD already has stops rather than making an empty commit
+    if is_empty_change(&head)? {
+        fs::write(action.head_file(), format!("{}\n", hash))?;
+        fs::write(GIT_MERGE_MSG, format!("{}\n", message))?;
+        print_empty(action);
+        return Ok(false);
+    }
+    let author = if action == Action::Pick { Some(commit.author.clone()) } else { None };
+    let hash = commit::commit_index(&message, vec![&head], author)?;
+    print_commit(&hash, &message)?;
+    Ok(true)
+}
+
+fn select_parent(hash: &str, commit: &Commit, mainline: Option<usize>) -> io::Result<Option<String>> {
+    match (commit.parents.len(), mainline) {
+        (n, None) if n > 1 => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("commit {} is a merge but no -m option was given.", hash))),
+        (n, Some(_)) if n <= 1 => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("mainline was specified but commit {} is not a merge.", hash))),
+        (n, Some(m)) if m == 0 || m > n => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("commit {} does not have parent {}", hash, m))),
+        (_, Some(m)) => Ok(Some(commit.parents[m - 1].clone())),
+        (_, None) => Ok(commit.parent().map(String::from)),
+    }
+}
+
+fn print_commit(hash: &str, message: &str) -> io::Result<()> {
+    let branch = refs::read_head_branch().unwrap_or_else(|_| String::from("detached HEAD"));
+    println!("[{} {}] {}", branch, &hash[..7], message.lines().next().unwrap_or(""));
+    Ok(())
+}
+
+fn clear_head_files() -> io::Result<()> {
+    for path in [GIT_CHERRY_PICK_HEAD, GIT_REVERT_HEAD, GIT_MERGE_MSG].iter() {
+        match fs::remove_file(path) {
+            Ok(_) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn format_options(options: &Options) -> String {
+    // written in config syntax like git's sequencer/opts
+    let mut opts = String::from("[options]\n");
+    if options.no_commit {
+        opts.push_str("\tno-commit = true\n");
+    }
+    if options.record_origin {
+        opts.push_str("\trecord-origin = true\n");
+    }
+    if let Some(m) = options.mainline {
+        opts.push_str(&format!("\tmainline = {}\n", m));
+    }
+    opts
+}
+
+fn read_options() -> io::Result<Options> {
+    let config = Config::from(&read_state(OPTS).unwrap_or_default());
+    Ok(Options {
+        no_commit: config.get("options.no-commit") == Some("true"),
+        record_origin: config.get("options.record-origin") == Some("true"),
+        mainline: config.get("options.mainline").and_then(|m| m.parse::<usize>().ok()),
+    })
+}
+
+fn read_todo() -> io::Result<Vec<(Action, String)>> {
+    let mut todo: Vec<(Action, String)> = Vec::new();
+    for line in read_state(TODO)?.lines() {
+        let mut iter = line.split_whitespace();
+        let action = match iter.next() {
+            Some("pick") | Some("p") => Action::Pick,
+            Some("revert") => Action::Revert,
+            Some(c) if c.starts_with('#') => continue,
+            None => continue,
+            Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid line in sequencer todo: {}", line))),
+        };
+        let hash = iter.next().ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+        todo.push((action, refs::rev_parse(hash)?));
+    }
+    Ok(todo)
+}
+
+fn write_todo(todo: &[(Action, String)]) -> io::Result<()> {
+    let mut lines = String::new();
+    for (action, hash) in todo {
+        let commit = graph::read_commit(hash)?;
+        lines.push_str(&format!("{} {} {}\n", action.name(), &hash[..7], commit.message.lines().next().unwrap_or("")));
+    }
+    write_state(TODO, &lines)
+}
+
+fn read_state(name: &str) -> io::Result<String> {
+    fs::read_to_string(format!("{}/{}", GIT_SEQUENCER_DIR, name))
+}
+
+fn write_state(name: &str, content: &str) -> io::Result<()> {
+    fs::write(format!("{}/{}", GIT_SEQUENCER_DIR, name), content)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::collections::HashMap;
+    use super::{format_options, Action, Options};
+    use crate::config::Config;
+    use crate::object::commit::Commit;
+
+    fn commit(parents: &[&str], date: i64) -> Commit {
+        let parents = parents.iter().map(|p| format!("parent {}\n", p)).collect::<String>();
+        let data = format!("tree bd41dfafd2299ddc08ff789c8a777ff0b8ce9e4c\n{}author A U Thor <author@example.com> {} +0900\ncommitter A U Thor <author@example.com> {} +0900\n\nmessage\n", parents, date, date);
+        Commit::from(data.as_bytes()).unwrap()
+    }
+
+    fn history() -> HashMap<String, Commit> {
+        // a - b - c - - e
+        //      \       /
+        //       d - - -
+        let commits = vec![
+            ("a", commit(&[], 1616834701)),
+            ("b", commit(&["a"], 1616834702)),
+            ("c", commit(&["b"], 1616834703)),
+            ("d", commit(&["b"], 1616834704)),
+            ("e", commit(&["c", "d"], 1616834705)),
+        ];
+        commits.into_iter().map(|(hash, commit)| (String::from(hash), commit)).collect()
+    }
+
+    #[test]
+    fn test_range_commits() {
+        let commits = history();
+        let read = |hash: &str| commits.get(hash).cloned().ok_or_else(|| io::Error::from(io::ErrorKind::NotFound));
+        // picks go oldest first, reverts newest first, and what from reaches is left out
+        assert_eq!(super::range_commits(Action::Pick, "a", "c", &read).unwrap(), vec!["b", "c"]);
+        assert_eq!(super::range_commits(Action::Revert, "a", "c", &read).unwrap(), vec!["c", "b"]);
+        assert_eq!(super::range_commits(Action::Pick, "c", "e", &read).unwrap(), vec!["d", "e"]);
+        assert_eq!(super::range_commits(Action::Revert, "b", "e", &read).unwrap(), vec!["e", "d", "c"]);
+        assert!(super::range_commits(Action::Pick, "e", "c", &read).unwrap().is_empty());
+    }
+    #[test]
+    fn test_select_parent() {
+        let commits = history();
+        assert_eq!(super::select_parent("c", &commits["c"], None).unwrap().as_deref(), Some("b"));
+        assert_eq!(super::select_parent("a", &commits["a"], None).unwrap(), None);
+        assert_eq!(super::select_parent("e", &commits["e"], Some(1)).unwrap().as_deref(), Some("c"));
+        assert_eq!(super::select_parent("e", &commits["e"], Some(2)).unwrap().as_deref(), Some("d"));
+        let message = |hash: &str, mainline: Option<usize>| super::select_parent(hash, &commits[hash], mainline).unwrap_err().to_string();
+        assert_eq!(message("e", None), "commit e is a merge but no -m option was given.");
+        assert_eq!(message("e", Some(3)), "commit e does not have parent 3");
+        assert_eq!(message("e", Some(0)), "commit e does not have parent 0");
+        assert_eq!(message("c", Some(1)), "mainline was specified but commit c is not a merge.");
+    }
+
+    #[test]
+    fn test_format_options() {
+        let options = Options { no_commit: true, record_origin: false, mainline: Some(2) };
+        let opts = format_options(&options);
+        assert_eq!(opts, "[options]\n\tno-commit = true\n\tmainline = 2\n");
+        let config = Config::from(&opts);
+        assert_eq!(config.get("options.no-commit"), Some("true"));
+        assert_eq!(config.get("options.mainline"), Some("2"));
+    }
+}
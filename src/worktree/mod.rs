@@ -0,0 +1,352 @@
+use std::io;
+use std::fs;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process;
+use std::os::unix::fs::PermissionsExt;
+use crate::index;
+use crate::index::{Entry, Index};
+use crate::merge;
+use crate::merge::Version;
+use crate::object::{Object, ObjectType};
+use crate::object::tree;
+use crate::object::stream;
+use crate::object::blob::Blob;
+use crate::cmd::cat_file::hash_key_to_path;
+use crate::cmd::{GIT_BASE_DIR, GIT_INDEX};
+
+pub fn write_blob(name: &str, hash: &[u8], mode: u32) -> io::Result<()> {
+    // streamed out of the object store, so neither the size nor the bytes of the blob matter
+    let mut reader = stream::ObjectReader::open(&hex::encode(hash))?;
+    if reader.typ != ObjectType::Blob {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a blob", hex::encode(hash))));
+    }
+    let path = prepare_path(name)?;
+    if mode == 120000 {
+        let mut target = Vec::new();
+        reader.read_to_end(&mut target)?;
+        return write_symlink(path, &target);
+    }
+    let mut file = fs::File::create(path)?;
+    let written = io::copy(&mut reader, &mut file)?;
+    if written != reader.size as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("object {} is truncated", hex::encode(hash))));
+    }
+    set_mode(path, mode)
+}
+
+pub fn write_file(name: &str, content: &[u8], mode: u32) -> io::Result<()> {
+    // modes are held as in the index, 100644 or 100755
+    let path = prepare_path(name)?;
+    if mode == 120000 {
+        return write_symlink(path, content);
+    }
+    let mut file = fs::File::create(path)?;
+    file.write_all(content)?;
+    set_mode(path, mode)
+}
+
+fn prepare_path(name: &str) -> io::Result<&Path> {
+    let path = Path::new(name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if path.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is a directory", name)));
+    }
+    Ok(path)
+}
+
+fn write_symlink(path: &Path, target: &[u8]) -> io::Result<()> {
+    // the blob of a symlink holds its target
+    let _ = fs::remove_file(path);
+    std::os::unix::fs::symlink(OsStr::from_bytes(target), path)
+}
+
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let perm = if mode == 100755 { 0o755 } else { 0o644 };
+    fs::set_permissions(path, fs::Permissions::from_mode(perm))
+}
+
+pub fn remove_file(name: &str) -> io::Result<()> {
+    match fs::remove_file(name) {
+        Ok(_) => {},
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => return Err(e),
+    }
+    // directories are not tracked, so drop the ones left empty
+    let mut dir = Path::new(name).parent();
+    while let Some(d) = dir {
+        if d.as_os_str().is_empty() || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+    Ok(())
+}
+
+pub fn update_files(changes: &[(&str, Option<&Version>)]) -> io::Result<()> {
+    update_files_in(&format!("{}/rusgit-backup-{}", GIT_BASE_DIR, process::id()), changes)
+}
+
+fn update_files_in(backup_dir: &str, changes: &[(&str, Option<&Version>)]) -> io::Result<()> {
+    // write each path to its version, or remove it for None, all or nothing: every blob is
+    // opened before the working tree is touched, and the files replaced are moved aside
+    // into backup_dir until the last path is done, so a failure can put them back
+    for (_, version) in changes.iter() {
+        if let Some(version) = version {
+            let reader = stream::ObjectReader::open(&hex::encode(&version.hash))?;
+            if reader.typ != ObjectType::Blob {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a blob", hex::encode(&version.hash))));
+            }
+        }
+    }
+    // removals first, a file may give way to a directory of the same name
+    let mut order = changes.iter().collect::<Vec<&(&str, Option<&Version>)>>();
+    order.sort_by_key(|(_, version)| version.is_some());
+    let mut done: Vec<(&str, Option<String>)> = Vec::new();
+    let mut result = Ok(());
+    for (name, version) in order {
+        result = set_aside(name, backup_dir, done.len()).and_then(|backup| {
+            done.push((name, backup));
+            match version {
+                Some(version) => write_blob(name, &version.hash, version.mode),
+                None => remove_file(name),
+            }
+        });
+        if result.is_err() {
+            break;
+        }
+    }
+    if result.is_err() {
+        for (name, backup) in done.iter().rev() {
+            let _ = remove_file(name);
+            if let Some(backup) = backup {
+                let _ = Path::new(name).parent().map_or(Ok(()), fs::create_dir_all);
+                let _ = fs::rename(backup, name);
+            }
+        }
+    }
+    match fs::remove_dir_all(backup_dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {},
+    }
+    result
+}
+
+fn set_aside(name: &str, backup_dir: &str, n: usize) -> io::Result<Option<String>> {
+    // move a file out of the way, a directory is left for the write to refuse
+    match fs::symlink_metadata(name) {
+        Ok(metadata) if !metadata.is_dir() => {
+            fs::create_dir_all(backup_dir)?;
+            let backup = format!("{}/{}", backup_dir, n);
+            fs::rename(name, &backup)?;
+            Ok(Some(backup))
+        },
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn file_hash(name: &str) -> Option<Vec<u8>> {
+    // the blob hash of the file in the working tree, None if missing or unreadable
+    match file_mode(name)? {
+        120000 => file_blob(name).ok().map(|blob| blob.calc_hash()),
+        _ => stream::hash_file(name).ok(),
+    }
+}
+
+pub fn stage_file(name: &str) -> io::Result<Vec<u8>> {
+    // store the file in the working tree as a blob, streamed so its size does not matter
+    let hash = match file_mode(name) {
+        Some(120000) => Object::Blob(file_blob(name)?).write()?,
+        _ => stream::write_file(name)?,
+    };
+    hex::decode(hash).or(Err(io::Error::from(io::ErrorKind::InvalidData)))
+}
+
+pub fn file_blob(name: &str) -> io::Result<Blob> {
+    // the blob the file in the working tree would be staged as
+    if file_mode(name) == Some(120000) {
+        // a symlink is stored as its target
+        let target = fs::read_link(name)?;
+        return Ok(Blob::new(target.to_str().ok_or(io::Error::from(io::ErrorKind::InvalidData))?));
+    }
+    Blob::from_name(name)
+}
+
+pub fn file_mode(name: &str) -> Option<u32> {
+    // the mode the file would be staged with, None if it is missing
+    let metadata = fs::symlink_metadata(name).ok()?;
+    if metadata.file_type().is_symlink() {
+        Some(120000)
+    } else if metadata.is_dir() {
+        None
+    } else if metadata.permissions().mode() & 0o100 != 0 {
+        Some(100755)
+    } else {
+        Some(100644)
+    }
+}
+
+pub fn has_mode(name: &str, mode: u32) -> bool {
+    // only the executable bit of a regular file is tracked
+    match fs::symlink_metadata(name) {
+        Ok(metadata) if metadata.file_type().is_symlink() => mode == 120000,
+        Ok(metadata) => (metadata.permissions().mode() & 0o100 != 0) == (mode == 100755),
+        Err(_) => false,
+    }
+}
+
+pub fn checkout_tree(tree: &str) -> io::Result<()> {
+    // make the index and the working tree match a tree, dropping local changes
+    let files = merge::read_tree_files(Some(tree))?;
+    let index = index::read_index(GIT_INDEX)?;
+    let mut names = files.keys().collect::<Vec<&String>>();
+    names.sort();
+    let changes = index.entries.iter()
+                    .filter(|e| !files.contains_key(&e.name))
+                    .map(|e| (e.name.as_str(), None))
+                    .chain(names.iter()
+                        .filter(|name| file_hash(name).as_ref() != Some(&files[**name].hash) || !has_mode(name, files[**name].mode))
+                        .map(|name| (name.as_str(), Some(&files[*name]))))
+                    .collect::<Vec<(&str, Option<&Version>)>>();
+    update_files(&changes)?;
+    let entries = names.iter()
+                    .map(|name| Entry::from_name(files[*name].hash.clone(), name))
+                    .collect::<io::Result<Vec<Entry>>>()?;
+    // the index now holds the tree as it is, so every directory of it is a valid cached tree
+    let tree_entries = tree::Tree::from_hash_file(&hash_key_to_path(tree))?.to_tree_entries()?;
+    index::write_index(GIT_INDEX, &Index { entries, tree_entries, ..index })
+}
+
+pub fn switch_tree(from: Option<&str>, to: &str) -> io::Result<()> {
+    // move the index and the working tree from one tree to another, carrying
+    // local changes along as long as the paths they touch do not differ
+    let old = merge::read_tree_files(from)?;
+    let new = merge::read_tree_files(Some(to))?;
+    let mut index = index::read_index(GIT_INDEX)?;
+    let staged = index.entries.iter()
+                    .filter(|e| e.stage == 0)
+                    .map(|e| (e.name.clone(), Version::new(e.mode, &e.hash)))
+                    .collect::<HashMap<String, Version>>();
+    let unmerged = index.entries.iter()
+                    .filter(|e| e.stage != 0)
+                    .map(|e| e.name.as_str())
+                    .collect::<HashSet<&str>>();
+    if !unmerged.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "you need to resolve your current index first"));
+    }
+    let changed = old.keys().chain(new.keys())
+                    .filter(|name| old.get(*name) != new.get(*name))
+                    .filter(|name| staged.get(*name) != new.get(*name))
+                    .cloned()
+                    .collect::<BTreeSet<String>>();
+    let mut dirty: Vec<&str> = Vec::new();
+    let mut untracked: Vec<&str> = Vec::new();
+    for name in changed.iter() {
+        match staged.get(name) {
+            Some(version) if old.get(name) != Some(version) => dirty.push(name),
+            // a file already gone from the working tree is no local change worth keeping
+            Some(version) if fs::symlink_metadata(name).is_ok() && (file_hash(name).as_ref() != Some(&version.hash) || !has_mode(name, version.mode)) => dirty.push(name),
+            Some(_) => {},
+            None if old.contains_key(name) => dirty.push(name),
+            None if Path::new(name).exists() => untracked.push(name),
+            None => {},
+        }
+    }
+    if !dirty.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Your local changes to the following files would be overwritten by checkout:\n\t{}\nPlease commit your changes or stash them before you switch branches.\nAborting", dirty.join("\n\t"))));
+    }
+    if !untracked.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("The following untracked working tree files would be overwritten by checkout:\n\t{}\nPlease move or remove them before you switch branches.\nAborting", untracked.join("\n\t"))));
+    }
+
+    let changes = changed.iter().map(|name| (name.as_str(), new.get(name))).collect::<Vec<(&str, Option<&Version>)>>();
+    update_files(&changes)?;
+    index.entries.retain(|e| !changed.contains(&e.name));
+    for (name, version) in changes.iter() {
+        if let Some(version) = version {
+            let mut entry = Entry::from_name(version.hash.clone(), name)?;
+            entry.mode = version.mode;
+            index.entries.push(entry);
+        }
+    }
+    index.entries.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+    index.invalidate_trees(&changed);
+    index::write_index(GIT_INDEX, &index)
+}
+
+pub fn local_changes(head_tree: Option<&str>) -> io::Result<Vec<(char, String)>> {
+    // what the index and the working tree hold on top of head_tree, as A, M or D
+    let head = merge::read_tree_files(head_tree)?;
+    let index = index::read_index(GIT_INDEX)?;
+    let mut changes: Vec<(char, String)> = Vec::new();
+    for entry in index.entries.iter().filter(|e| e.stage == 0) {
+        let hash = file_hash(&entry.name);
+        match head.get(&entry.name) {
+            None => changes.push(('A', entry.name.clone())),
+            Some(_) if hash.is_none() => changes.push(('D', entry.name.clone())),
+            Some(version) if hash.as_ref() != Some(&version.hash) || entry.mode != version.mode => changes.push(('M', entry.name.clone())),
+            Some(_) => {},
+        }
+    }
+    for name in head.keys().filter(|name| !index.entries.iter().any(|e| &&e.name == name)) {
+        changes.push(('D', name.clone()));
+    }
+    changes.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(changes)
+}
+
+pub fn is_clean(head_tree: &str) -> io::Result<bool> {
+    // nothing staged against head_tree and nothing modified against the index
+    let index = index::read_index(GIT_INDEX)?;
+    if index.entries.iter().any(|e| e.stage != 0 || file_hash(&e.name).as_ref() != Some(&e.hash)) {
+        return Ok(false);
+    }
+    let staged = Object::Tree(tree::write_tree()?).calc_hash();
+    Ok(hex::encode(staged) == head_tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::merge::Version;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("rusgit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        String::from(dir.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_update_files() {
+        let dir = temp_dir("update-files");
+        let backup = format!("{}/backup", dir);
+        let a = format!("{}/sub/a.txt", dir);
+        let d = format!("{}/d", dir);
+        fs::create_dir_all(format!("{}/sub", dir)).unwrap();
+        fs::create_dir_all(format!("{}/x", d)).unwrap();
+        fs::write(&a, "a\n").unwrap();
+
+        // a blob that cannot be read stops it before anything is touched
+        let missing = Version::new(100644, &[0u8; 20]);
+        assert!(super::update_files_in(&backup, &[(&a, None), (&d, Some(&missing))]).is_err());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a\n");
+
+        // a failure halfway puts back what was already done
+        assert!(super::update_files_in(&backup, &[(&a, None), (&d, None)]).is_err());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a\n");
+        assert!(!std::path::Path::new(&backup).exists());
+
+        assert!(super::update_files_in(&backup, &[(&a, None)]).is_ok());
+        assert!(!std::path::Path::new(&format!("{}/sub", dir)).exists());
+        assert!(!std::path::Path::new(&backup).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}